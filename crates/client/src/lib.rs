@@ -6,12 +6,22 @@ pub trait Provider {
 	fn get_header_by_number(&mut self, n: u64) -> Result<Header>;
 	fn get_receipts_by_root(&self, root: Hash) -> Result<Vec<Receipt>>;
 	fn get_transactions_by_root(&self, root: Hash) -> Result<Vec<Transaction>>;
+	/// Gets the receipts of the block of a header that was loaded with get_header or get_header_by_number.
+	/// Providers that fetch receipts separately from the block fetch them here. By default the
+	/// receipts are looked up by the receipts root.
+	fn get_receipts(&mut self, header: &Header) -> Result<Vec<Receipt>> {
+		self.get_receipts_by_root(header.receipts_root.into())
+	}
 }
 
 pub mod rpc_provider;
+pub mod shared;
 mod types;
 
+pub use types::HeaderError;
+
 pub mod prelude {
 	pub use crate::rpc_provider::Client;
+	pub use crate::shared::SharedClient;
 	pub use crate::Provider;
 }
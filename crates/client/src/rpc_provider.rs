@@ -1,61 +1,53 @@
 use crate::Provider;
 use core::prelude::*;
-use core::types::{Hash, Header};
+use core::types::{Hash, Header, Withdrawal};
 
-use ethers_providers::{Http, Middleware, Provider as RPCProvider};
+use ethers_core::{
+	types::{Block, BlockId, Bloom},
+	utils::rlp,
+};
+use ethers_providers::{Http, JsonRpcClient, Middleware, Provider as RPCProvider};
 use eyre::Result;
-use std::{collections::HashMap, convert::TryFrom};
+use mpt::compute_root;
+use std::{
+	collections::{BTreeMap, HashMap},
+	convert::TryFrom,
+};
 use tokio::runtime::Runtime;
 
+/// MAX_RECEIPT_REFETCHES caps how often the receipts of a block are refetched after failing verification.
+pub const MAX_RECEIPT_REFETCHES: u32 = 3;
+
 /// Client wraps a web3 provider to provide L1 pre-image oracle support.
 #[derive(Debug)]
-pub struct Client {
+pub struct Client<P = Http> {
 	/// The internal web3 provider
-	pub provider: RPCProvider<Http>,
+	pub provider: RPCProvider<P>,
 	/// The client runtime
 	pub rt: Runtime,
 	/// Store of receipts from Receipt Root to Receipts
 	pub receipts: HashMap<Hash, Vec<Receipt>>,
 	/// Store of transactions from Transaction Root to Transactions
 	pub transactions: HashMap<Hash, Vec<Transaction>>,
+	/// The roots that each block stored, so that the entries of reorged blocks can be dropped
+	pub roots_by_number: BTreeMap<u64, Vec<Hash>>,
+	/// How often receipts that fail verification are refetched before giving up, at most [MAX_RECEIPT_REFETCHES]
+	pub receipt_refetches: u32,
 }
 
-impl Provider for Client {
-	/// Gets a block header by block hash
+impl<P: JsonRpcClient> Provider for Client<P> {
+	/// Gets a block header by block hash. The transactions of the block are verified and stored by their root.
 	fn get_header(&mut self, hash: Hash) -> Result<Header> {
 		let hash: ethers_core::types::H256 = hash.into();
-		let block = self.rt.block_on(self.provider.get_block_with_txs(hash))?;
-		let block = block.ok_or(eyre::eyre!("did not find the block"))?;
-
-		let txs: Vec<Transaction> = block.transactions.clone().into_iter().map(|t| t.into()).collect();
-		let tx_root = block.transactions_root.into();
-
-		// let receipts = self.get_receipts_by_transactions(&txs)?;
-		// let receipt_root = block.receipts_root.into();
-
-		self.transactions.insert(tx_root, txs);
-		// self.receipts.insert(receipt_root, receipts);
-
-		let header = crate::types::header_from_block(block)?;
-		Ok(header)
+		let block = self.fetch_block(hash)?;
+		Ok(self.store_block(block))
 	}
 
-	/// Gets a block header by block number
+	/// Gets a block header by block number. The transactions of the block are verified and stored by
+	/// their root.
 	fn get_header_by_number(&mut self, n: u64) -> Result<Header> {
-		let block = self.rt.block_on(self.provider.get_block_with_txs(n))?;
-		let block = block.ok_or(eyre::eyre!("did not find the block"))?;
-
-		let txs: Vec<Transaction> = block.transactions.clone().into_iter().map(|t| t.into()).collect();
-		let tx_root = block.transactions_root.into();
-
-		// let receipts = self.get_receipts_by_transactions(&txs)?;
-		// let receipt_root = block.receipts_root.into();
-
-		self.transactions.insert(tx_root, txs);
-		// self.receipts.insert(receipt_root, receipts);
-
-		let header = crate::types::header_from_block(block)?;
-		Ok(header)
+		let block = self.fetch_block(n)?;
+		Ok(self.store_block(block))
 	}
 
 	/// Get receipts by the recipt root
@@ -73,22 +65,170 @@ impl Provider for Client {
 			.ok_or(eyre::eyre!("missing transactions for given root in internal store"))
 			.cloned()
 	}
+
+	/// Gets the receipts of the block of a header. Receipts that are not stored yet are fetched, one
+	/// request per transaction, verified & stored by their root.
+	fn get_receipts(&mut self, header: &Header) -> Result<Vec<Receipt>> {
+		if let Ok(receipts) = self.get_receipts_by_root(header.receipts_root.into()) {
+			return Ok(receipts);
+		}
+		let receipts = self.fetch_block_receipts(header)?;
+		self.store_receipts(header, receipts.clone());
+		Ok(receipts)
+	}
 }
 
-impl Client {
+impl Client<Http> {
 	/// Constructs a new client
 	pub fn new(url: &str) -> Result<Self> {
-		let provider = RPCProvider::<Http>::try_from(url)?;
+		Self::with_provider(RPCProvider::<Http>::try_from(url)?)
+	}
+
+	/// Rebuilds the underlying http provider from its url. The caches are kept.
+	pub fn reconnect(&mut self) -> Result<()> {
+		self.provider = RPCProvider::<Http>::try_from(self.provider.url().as_str())?;
+		Ok(())
+	}
+}
+
+impl<P: JsonRpcClient> Client<P> {
+	/// Constructs a new client around an existing web3 provider
+	pub fn with_provider(provider: RPCProvider<P>) -> Result<Self> {
 		let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+		Ok(Self::with_runtime(provider, rt))
+	}
 
-		Ok(Client {
+	/// Constructs a new client that drives the web3 provider on the given runtime
+	pub(crate) fn with_runtime(provider: RPCProvider<P>, rt: Runtime) -> Self {
+		Client {
 			rt,
 			provider,
 			receipts: HashMap::new(),
 			transactions: HashMap::new(),
+			roots_by_number: BTreeMap::new(),
+			receipt_refetches: 0,
+		}
+	}
+
+	/// Sets how often the receipts of a block are refetched when they fail verification, which guards
+	/// against a provider that is briefly inconsistent during a reorg. It is capped at [MAX_RECEIPT_REFETCHES].
+	pub fn set_receipt_refetches(&mut self, refetches: u32) {
+		self.receipt_refetches = refetches.min(MAX_RECEIPT_REFETCHES);
+	}
+
+	/// Gets a block header by block hash without fetching its transactions.
+	/// Unlike [Provider::get_header], the transactions root is not verified and no transactions are stored.
+	pub fn get_header_light(&self, hash: Hash) -> Result<Header> {
+		let hash: ethers_core::types::H256 = hash.into();
+		let block = self.rt.block_on(self.provider.get_block(hash))?;
+		let block = block.ok_or(eyre::eyre!("did not find the block"))?;
+		Ok(crate::types::header_from_block(block)?)
+	}
+
+	/// Gets a block header by block hash along with the receipts of its transactions.
+	/// The receipts are checked against the receipts root & the logs bloom of the header. Receipts that
+	/// are missing or fail a check are refetched from the provider up to [Client::receipt_refetches] times.
+	pub fn get_block_with_receipts(&mut self, hash: Hash) -> Result<(Header, Vec<Receipt>)> {
+		let header = self.get_header(hash)?;
+		let receipts = self.get_receipts(&header)?;
+		Ok((header, receipts))
+	}
+
+	/// Drops the cached transactions & receipts of every block at or above `number` after an L1 reorg.
+	/// The next lookup of these blocks is fetched from the provider. Roots that are shared with a
+	/// block below `number`, like the empty trie root, are kept.
+	pub fn invalidate_from(&mut self, number: u64) {
+		let orphaned = self.roots_by_number.split_off(&number);
+		for root in orphaned.into_values().flatten() {
+			if self.roots_by_number.values().any(|roots| roots.contains(&root)) {
+				continue;
+			}
+			self.transactions.remove(&root);
+			self.receipts.remove(&root);
+		}
+	}
+
+	/// Fetches a block & verifies its transactions without touching the caches, so that it only needs
+	/// shared access to the client.
+	pub(crate) fn fetch_block(&self, id: impl Into<BlockId> + Send + Sync) -> Result<VerifiedBlock> {
+		let block = self.rt.block_on(self.provider.get_block_with_txs(id))?;
+		let block = block.ok_or(eyre::eyre!("did not find the block"))?;
+		let (header, tx_root, transactions) = verified_block_contents(block)?;
+		Ok(VerifiedBlock {
+			header,
+			tx_root,
+			transactions,
 		})
 	}
 
+	/// Stores the transactions of a verified block by their root.
+	pub(crate) fn store_block(&mut self, block: VerifiedBlock) -> Header {
+		let VerifiedBlock {
+			header,
+			tx_root,
+			transactions,
+		} = block;
+		self.transactions.insert(tx_root, transactions);
+		self.record_root(header.number, tx_root);
+		header
+	}
+
+	/// Fetches & verifies the receipts of the block of a header without touching the caches. The
+	/// transactions of the block must have been stored by get_header or get_header_by_number.
+	pub(crate) fn fetch_block_receipts(&self, header: &Header) -> Result<Vec<Receipt>> {
+		let transactions = self.get_transactions_by_root(header.transactions_root.into())?;
+		self.verified_receipts(header, &transactions)
+	}
+
+	/// Stores the verified receipts of the block of a header by their root.
+	pub(crate) fn store_receipts(&mut self, header: &Header, receipts: Vec<Receipt>) {
+		self.receipts.insert(header.receipts_root.into(), receipts);
+		self.record_root(header.number, header.receipts_root.into());
+	}
+
+	// verified_receipts fetches the receipts of the transactions of the block & checks them against its
+	// receipts root & logs bloom. Receipts that are missing or fail a check are refetched up to
+	// [Client::receipt_refetches] times.
+	fn verified_receipts(&self, header: &Header, txs: &[Transaction]) -> Result<Vec<Receipt>> {
+		let root: Hash = header.receipts_root.into();
+		let bloom = Bloom::from(header.logs_bloom.to_fixed_bytes());
+		let mut refetches = 0;
+		loop {
+			let verified = self.fetch_receipts(txs).and_then(|receipts| {
+				verify_receipts_root(&receipts, root)?;
+				verify_logs_bloom(&receipts, bloom)?;
+				Ok(receipts)
+			});
+			match verified {
+				Ok(receipts) => return Ok(receipts),
+				Err(_) if refetches < self.receipt_refetches.min(MAX_RECEIPT_REFETCHES) => refetches += 1,
+				Err(err) => return Err(err),
+			}
+		}
+	}
+
+	// fetch_receipts fetches the receipts of the transactions from the provider, bypassing the cache.
+	fn fetch_receipts(&self, txs: &[Transaction]) -> Result<Vec<Receipt>> {
+		let mut receipts = Vec::with_capacity(txs.len());
+		for tx in txs.iter() {
+			let tx_hash: ethers_core::types::H256 = tx.hash.into();
+			let receipt = self.rt.block_on(self.provider.get_transaction_receipt(tx_hash))?;
+			receipts.push(receipt.ok_or(eyre::eyre!("did not find the receipt"))?);
+		}
+		Ok(receipts)
+	}
+
+	// record_root remembers that the block stored an entry under the root.
+	fn record_root(&mut self, number: u64, root: Hash) {
+		self.roots_by_number.entry(number).or_default().push(root);
+	}
+
+	/// Checks that the endpoint is alive and returns the current L1 head number
+	pub fn health_check(&self) -> Result<u64> {
+		let number = self.rt.block_on(self.provider.get_block_number())?;
+		Ok(number.as_u64())
+	}
+
 	// /// Get transaction receipts for a list of transactions
 	// fn get_receipts_by_transactions(&self, transactions: &[Transaction]) -> Result<Vec<Receipt>> {
 	// 	let mut receipts = Vec::anew();
@@ -107,3 +247,351 @@ impl Client {
 	// 	Ok(receipt)
 	// }
 }
+
+/// VerifiedBlock is a fetched block whose transactions were checked against its header.
+#[derive(Debug)]
+pub(crate) struct VerifiedBlock {
+	header: Header,
+	tx_root: Hash,
+	transactions: Vec<Transaction>,
+}
+
+/// Verifies the transactions of a block & splits it into its header, transactions root & transactions
+fn verified_block_contents(block: Block<ethers_core::types::Transaction>) -> Result<(Header, Hash, Vec<Transaction>)> {
+	let tx_root = block.transactions_root.into();
+	verify_transactions_root(&block.transactions, tx_root)?;
+	let txs: Vec<Transaction> = block.transactions.clone().into_iter().map(|t| t.into()).collect();
+	let header = crate::types::header_from_block(block)?;
+	Ok((header, tx_root, txs))
+}
+
+/// Computes the logs bloom of a block by combining the blooms of its receipts
+pub fn logs_bloom(receipts: &[Receipt]) -> Bloom {
+	receipts.iter().fold(Bloom::zero(), |mut bloom, receipt| {
+		bloom.accrue_bloom(&receipt.logs_bloom);
+		bloom
+	})
+}
+
+/// Checks that the receipts of a block combine to its logs bloom
+fn verify_logs_bloom(receipts: &[Receipt], expected: Bloom) -> Result<()> {
+	let computed = logs_bloom(receipts);
+	if computed != expected {
+		eyre::bail!("logs bloom mismatch: expected {expected:?}, computed {computed:?}");
+	}
+	Ok(())
+}
+
+/// Computes the receipts root of a block. Receipts are keyed by their RLP encoded position in the block.
+/// Typed receipts are prefixed with their transaction type like typed transactions are.
+pub fn receipts_root(receipts: &[Receipt]) -> Hash {
+	compute_root(receipts.iter().enumerate().map(|(i, receipt)| {
+		let mut encoded = Vec::new();
+		if let Some(tx_type) = receipt.transaction_type.filter(|t| !t.is_zero()) {
+			encoded.push(tx_type.as_u64() as u8);
+		}
+		encoded.extend_from_slice(&rlp::encode(receipt));
+		(rlp::encode(&i).to_vec(), encoded)
+	}))
+}
+
+/// Checks that the receipts of a block hash to its receipts root
+fn verify_receipts_root(receipts: &[Receipt], root: Hash) -> Result<()> {
+	let computed = receipts_root(receipts);
+	if computed != root {
+		eyre::bail!("receipts root mismatch: expected {root:?}, computed {computed:?}");
+	}
+	Ok(())
+}
+
+/// Computes the withdrawals root of a block. Withdrawals are keyed by their RLP encoded position in
+/// the block like transactions are.
+pub fn withdrawals_root(withdrawals: &[Withdrawal]) -> Hash {
+	compute_root(
+		withdrawals
+			.iter()
+			.enumerate()
+			.map(|(i, w)| (rlp::encode(&i).to_vec(), rlp::encode(w).to_vec())),
+	)
+}
+
+/// Checks that the transactions of a block hash to its transactions root
+fn verify_transactions_root(transactions: &[ethers_core::types::Transaction], root: Hash) -> Result<()> {
+	let computed = compute_root(
+		transactions
+			.iter()
+			.enumerate()
+			.map(|(i, tx)| (rlp::encode(&i).to_vec(), tx.rlp().to_vec())),
+	);
+	if computed != root {
+		eyre::bail!("transactions root mismatch: expected {root:?}, computed {computed:?}");
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use ethers_core::types::{BloomInput, H160, H256, H64, U64};
+	use ethers_providers::MockProvider;
+	use mpt::EMPTY_TRIE_ROOT;
+
+	pub(crate) fn empty_block<TX: Default>(transactions_root: Hash) -> Block<TX> {
+		Block {
+			number: Some(U64::from(8300532)),
+			author: Some(Default::default()),
+			logs_bloom: Some(Bloom::default()),
+			mix_hash: Some(H256::zero()),
+			nonce: Some(H64::zero()),
+			transactions_root: transactions_root.into(),
+			receipts_root: EMPTY_TRIE_ROOT.into(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_empty_block_transactions_root() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		mock.push::<Block<ethers_core::types::Transaction>, _>(empty_block(EMPTY_TRIE_ROOT))
+			.unwrap();
+		let header = client.get_header_by_number(8300532).unwrap();
+		assert_eq!(Hash::from(header.transactions_root), EMPTY_TRIE_ROOT);
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_receipts_are_fetched_separately() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		let tx = ethers_core::types::Transaction::default();
+		let tx_root = compute_root([(rlp::encode(&0usize).to_vec(), tx.rlp().to_vec())]);
+		let mut block = empty_block::<ethers_core::types::Transaction>(tx_root);
+		block.transactions = vec![tx];
+		let receipt = Receipt::default();
+		block.receipts_root = receipts_root(&[receipt.clone()]).into();
+
+		// Only the block is served, so loading the header must not request any receipt.
+		mock.push::<Block<ethers_core::types::Transaction>, _>(block).unwrap();
+		let header = client.get_header_by_number(8300532).unwrap();
+		assert!(client.get_receipts_by_root(header.receipts_root.into()).is_err());
+
+		mock.push::<Receipt, _>(&receipt).unwrap();
+		assert_eq!(client.get_receipts(&header).unwrap(), vec![receipt.clone()]);
+		// Stored receipts are served without another request.
+		assert_eq!(client.get_receipts(&header).unwrap(), vec![receipt]);
+	}
+
+	#[test]
+	fn test_transactions_root_mismatch() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		mock.push::<Block<ethers_core::types::Transaction>, _>(empty_block(Hash::default()))
+			.unwrap();
+		assert!(client.get_header_by_number(8300532).is_err());
+	}
+
+	#[test]
+	fn test_invalidate_from() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		let root = Hash::new([0x11; 32]);
+		let block = |number: u64| {
+			let mut block = empty_block::<ethers_core::types::Transaction>(EMPTY_TRIE_ROOT);
+			block.number = Some(U64::from(number));
+			block
+		};
+		mock.push::<Block<ethers_core::types::Transaction>, _>(block(10)).unwrap();
+		client.get_header_by_number(10).unwrap();
+		client.receipts.insert(root, Vec::new());
+		client.record_root(11, root);
+
+		// The empty transactions root is still used by block 10.
+		client.invalidate_from(11);
+		assert!(client.get_receipts_by_root(root).is_err());
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).is_ok());
+
+		client.invalidate_from(10);
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).is_err());
+		assert!(client.roots_by_number.is_empty());
+
+		// The canonical block is fetched again.
+		mock.push::<Block<ethers_core::types::Transaction>, _>(block(10)).unwrap();
+		client.get_header_by_number(10).unwrap();
+		mock.assert_request("eth_getBlockByNumber", (U64::from(10), true)).unwrap();
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).is_ok());
+	}
+
+	#[test]
+	fn test_withdrawals_root() {
+		let withdrawal = |index: u64, address: u8, amount: u64| Withdrawal {
+			index,
+			validator_index: 65535 + index,
+			address: Address::new([address; 20]),
+			amount,
+		};
+		let withdrawals = [withdrawal(0, 0x11, 21298), withdrawal(1, 0x11, 0), withdrawal(2, 0x22, 31337000)];
+		let expected = core::hash_literal!("1e63759782ec551551958487fd343c4acaeb04e6df7b2803c5518116e54ba840");
+		assert_eq!(withdrawals_root(&withdrawals), expected);
+		assert_eq!(withdrawals_root(&[]), EMPTY_TRIE_ROOT);
+	}
+
+	#[test]
+	fn test_logs_bloom() {
+		let receipt = |inputs: &[&[u8]]| {
+			let mut logs_bloom = Bloom::zero();
+			inputs.iter().for_each(|i| logs_bloom.accrue(BloomInput::Raw(i)));
+			Receipt {
+				logs_bloom,
+				..Default::default()
+			}
+		};
+		let address = H160::repeat_byte(0x42);
+		let topic = H256::repeat_byte(0x17);
+		let receipts = [receipt(&[address.as_bytes(), topic.as_bytes()]), receipt(&[]), receipt(&[b"other"])];
+
+		let mut header_bloom = Bloom::zero();
+		for input in [address.as_bytes(), topic.as_bytes(), b"other"] {
+			header_bloom.accrue(BloomInput::Raw(input));
+		}
+		assert_eq!(logs_bloom(&receipts), header_bloom);
+		assert!(verify_logs_bloom(&receipts, header_bloom).is_ok());
+		assert!(verify_logs_bloom(&receipts[..2], header_bloom).is_err());
+		assert_eq!(logs_bloom(&[]), Bloom::zero());
+	}
+
+	#[test]
+	fn test_get_block_with_receipts_checks_bloom() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		mock.push::<Block<ethers_core::types::Transaction>, _>(empty_block(EMPTY_TRIE_ROOT))
+			.unwrap();
+		let (header, receipts) = client.get_block_with_receipts(Hash::default()).unwrap();
+		assert!(receipts.is_empty());
+		assert!(client.get_receipts_by_root(header.receipts_root.into()).unwrap().is_empty());
+
+		// Stored receipts are not fetched again.
+		client.receipts.clear();
+		let mut block = empty_block::<ethers_core::types::Transaction>(EMPTY_TRIE_ROOT);
+		block.logs_bloom = Some(Bloom::repeat_byte(0xff));
+		mock.push::<Block<ethers_core::types::Transaction>, _>(block).unwrap();
+		assert!(client.get_block_with_receipts(Hash::default()).is_err());
+	}
+
+	#[test]
+	fn test_get_block_with_receipts_refetches_bad_receipts() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		let tx = ethers_core::types::Transaction::default();
+		let tx_root = compute_root([(rlp::encode(&0usize).to_vec(), tx.rlp().to_vec())]);
+		let mut block = empty_block::<ethers_core::types::Transaction>(tx_root);
+		block.logs_bloom = Some(Bloom::repeat_byte(0x11));
+		block.transactions = vec![tx];
+		let good = Receipt {
+			logs_bloom: Bloom::repeat_byte(0x11),
+			..Default::default()
+		};
+		block.receipts_root = receipts_root(&[good.clone()]).into();
+		let bad = Receipt::default();
+
+		// The mock serves its responses last in, first out.
+		let push_responses = |receipts: &[&Receipt]| {
+			receipts.iter().rev().for_each(|r| mock.push::<Receipt, _>(*r).unwrap());
+			mock.push::<Block<ethers_core::types::Transaction>, _>(block.clone()).unwrap();
+		};
+		push_responses(&[&bad]);
+		assert!(client.get_block_with_receipts(Hash::default()).is_err());
+
+		client.set_receipt_refetches(1);
+		push_responses(&[&bad, &good]);
+		let (_, receipts) = client.get_block_with_receipts(Hash::default()).unwrap();
+		assert_eq!(receipts, vec![good.clone()]);
+
+		// A single refetch is not enough for a provider that keeps serving bad receipts.
+		client.receipts.clear();
+		push_responses(&[&bad, &bad]);
+		assert!(client.get_block_with_receipts(Hash::default()).is_err());
+
+		client.set_receipt_refetches(100);
+		assert_eq!(client.receipt_refetches, MAX_RECEIPT_REFETCHES);
+	}
+
+	#[test]
+	fn test_get_block_with_receipts_refetches_missing_and_mismatched_receipts() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+		let tx = ethers_core::types::Transaction::default();
+		let tx_root = compute_root([(rlp::encode(&0usize).to_vec(), tx.rlp().to_vec())]);
+		let mut block = empty_block::<ethers_core::types::Transaction>(tx_root);
+		block.transactions = vec![tx];
+		let good = Receipt {
+			cumulative_gas_used: 21000.into(),
+			..Default::default()
+		};
+		block.receipts_root = receipts_root(&[good.clone()]).into();
+		// The forged receipt matches the empty logs bloom of the block but not its receipts root.
+		let forged = Receipt::default();
+
+		// The mock serves its responses last in, first out.
+		mock.push::<Receipt, _>(&good).unwrap();
+		mock.push::<Receipt, _>(&forged).unwrap();
+		mock.push::<Option<Receipt>, _>(None).unwrap();
+		mock.push::<Block<ethers_core::types::Transaction>, _>(block.clone()).unwrap();
+		client.set_receipt_refetches(2);
+		let (_, receipts) = client.get_block_with_receipts(Hash::default()).unwrap();
+		assert_eq!(receipts, vec![good]);
+
+		client.receipts.clear();
+		mock.push::<Receipt, _>(&forged).unwrap();
+		mock.push::<Option<Receipt>, _>(None).unwrap();
+		mock.push::<Block<ethers_core::types::Transaction>, _>(block).unwrap();
+		client.set_receipt_refetches(1);
+		assert!(client.get_block_with_receipts(Hash::default()).is_err());
+	}
+
+	#[test]
+	fn test_receipts_root() {
+		let legacy = Receipt {
+			status: Some(1.into()),
+			cumulative_gas_used: 21000.into(),
+			..Default::default()
+		};
+		let typed = Receipt {
+			transaction_type: Some(2.into()),
+			..legacy.clone()
+		};
+		assert_eq!(receipts_root(&[]), EMPTY_TRIE_ROOT);
+		assert_ne!(receipts_root(&[legacy.clone()]), receipts_root(&[typed]));
+		assert!(verify_receipts_root(&[legacy.clone()], receipts_root(&[legacy.clone()])).is_ok());
+		assert!(verify_receipts_root(&[legacy.clone(), legacy], EMPTY_TRIE_ROOT).is_err());
+	}
+
+	#[test]
+	fn test_get_header_light() {
+		let (provider, mock) = RPCProvider::mocked();
+		let client = Client::with_provider(provider).unwrap();
+		// The light path does not verify the root, so a bogus one is accepted.
+		mock.push::<Block<H256>, _>(empty_block(Hash::default())).unwrap();
+		let hash = H256::repeat_byte(0x11);
+		let header = client.get_header_light(hash.into()).unwrap();
+		assert_eq!(header.number, 8300532);
+		mock.assert_request("eth_getBlockByHash", (hash, false)).unwrap();
+		assert!(client.transactions.is_empty());
+	}
+
+	#[test]
+	fn test_health_check() {
+		let (provider, mock) = RPCProvider::mocked();
+		let client = Client::with_provider(provider).unwrap();
+		mock.push::<U64, _>(U64::from(8300532)).unwrap();
+		assert_eq!(client.health_check().unwrap(), 8300532);
+		mock.assert_request("eth_blockNumber", ()).unwrap();
+	}
+
+	#[test]
+	fn test_health_check_error() {
+		let (provider, _mock): (_, MockProvider) = RPCProvider::mocked();
+		let client = Client::with_provider(provider).unwrap();
+		assert!(client.health_check().is_err());
+	}
+}
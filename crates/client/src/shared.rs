@@ -0,0 +1,215 @@
+use crate::rpc_provider::Client;
+use crate::Provider;
+use core::prelude::*;
+use core::types::{Hash, Header};
+
+use ethers_providers::{Http, JsonRpcClient, Provider as RPCProvider};
+use eyre::Result;
+use std::{
+	convert::TryFrom,
+	sync::{Arc, RwLock},
+};
+
+/// SharedClient is a [Client] that can be shared between threads. Clones share the provider, the
+/// runtime & the caches. Blocks are fetched & verified under a read lock so that lookups run
+/// concurrently on a multi threaded runtime, only storing the result takes the write lock.
+#[derive(Debug)]
+pub struct SharedClient<P = Http> {
+	client: Arc<RwLock<Client<P>>>,
+}
+
+impl<P> Clone for SharedClient<P> {
+	fn clone(&self) -> Self {
+		Self {
+			client: self.client.clone(),
+		}
+	}
+}
+
+impl SharedClient<Http> {
+	/// Constructs a new shared client
+	pub fn new(url: &str) -> Result<Self> {
+		Self::with_provider(RPCProvider::<Http>::try_from(url)?)
+	}
+}
+
+impl<P: JsonRpcClient> SharedClient<P> {
+	/// Constructs a new shared client around an existing web3 provider
+	pub fn with_provider(provider: RPCProvider<P>) -> Result<Self> {
+		let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+		Ok(SharedClient {
+			client: Arc::new(RwLock::new(Client::with_runtime(provider, rt))),
+		})
+	}
+
+	/// Sets how often the receipts of a block are refetched when they fail verification.
+	/// See [Client::set_receipt_refetches].
+	pub fn set_receipt_refetches(&self, refetches: u32) {
+		self.client
+			.write()
+			.expect("Cannot write poisoned client")
+			.set_receipt_refetches(refetches);
+	}
+
+	/// Gets a block header by block hash. The transactions of the block are verified and stored by their root.
+	pub fn get_header(&self, hash: Hash) -> Result<Header> {
+		let hash: ethers_core::types::H256 = hash.into();
+		let block = self.client.read().expect("Cannot read poisoned client").fetch_block(hash)?;
+		Ok(self.client.write().expect("Cannot write poisoned client").store_block(block))
+	}
+
+	/// Gets a block header by block number. The transactions of the block are verified and stored by
+	/// their root.
+	pub fn get_header_by_number(&self, n: u64) -> Result<Header> {
+		let block = self.client.read().expect("Cannot read poisoned client").fetch_block(n)?;
+		Ok(self.client.write().expect("Cannot write poisoned client").store_block(block))
+	}
+
+	/// Gets a block header by block hash along with the receipts of its transactions.
+	/// See [Client::get_block_with_receipts].
+	pub fn get_block_with_receipts(&self, hash: Hash) -> Result<(Header, Vec<Receipt>)> {
+		let header = self.get_header(hash)?;
+		let receipts = self.get_receipts(&header)?;
+		Ok((header, receipts))
+	}
+
+	/// Gets the receipts of the block of a header. Like blocks, receipts that are not stored yet are
+	/// fetched & verified under the read lock. See [Client::get_receipts].
+	pub fn get_receipts(&self, header: &Header) -> Result<Vec<Receipt>> {
+		if let Ok(receipts) = self.get_receipts_by_root(header.receipts_root.into()) {
+			return Ok(receipts);
+		}
+		let receipts = self
+			.client
+			.read()
+			.expect("Cannot read poisoned client")
+			.fetch_block_receipts(header)?;
+		self.client
+			.write()
+			.expect("Cannot write poisoned client")
+			.store_receipts(header, receipts.clone());
+		Ok(receipts)
+	}
+
+	/// Get receipts by the recipt root
+	pub fn get_receipts_by_root(&self, root: Hash) -> Result<Vec<Receipt>> {
+		self.client.read().expect("Cannot read poisoned client").get_receipts_by_root(root)
+	}
+
+	/// Get transactions by the transaction root
+	pub fn get_transactions_by_root(&self, root: Hash) -> Result<Vec<Transaction>> {
+		self.client
+			.read()
+			.expect("Cannot read poisoned client")
+			.get_transactions_by_root(root)
+	}
+
+	/// Drops the cached transactions & receipts of every block at or above `number` after an L1 reorg.
+	/// See [Client::invalidate_from].
+	pub fn invalidate_from(&self, number: u64) {
+		self.client.write().expect("Cannot write poisoned client").invalidate_from(number);
+	}
+}
+
+impl<P: JsonRpcClient> Provider for SharedClient<P> {
+	fn get_header(&mut self, hash: Hash) -> Result<Header> {
+		SharedClient::get_header(self, hash)
+	}
+
+	fn get_header_by_number(&mut self, n: u64) -> Result<Header> {
+		SharedClient::get_header_by_number(self, n)
+	}
+
+	fn get_receipts_by_root(&self, root: Hash) -> Result<Vec<Receipt>> {
+		SharedClient::get_receipts_by_root(self, root)
+	}
+
+	fn get_transactions_by_root(&self, root: Hash) -> Result<Vec<Transaction>> {
+		SharedClient::get_transactions_by_root(self, root)
+	}
+
+	fn get_receipts(&mut self, header: &Header) -> Result<Vec<Receipt>> {
+		SharedClient::get_receipts(self, header)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rpc_provider::tests::empty_block;
+	use ethers_core::types::Block;
+	use ethers_providers::{MockError, MockProvider};
+	use mpt::EMPTY_TRIE_ROOT;
+	use serde::{de::DeserializeOwned, Serialize};
+	use std::{fmt::Debug, time::Duration};
+	use tokio::sync::Barrier;
+
+	// BarrierProvider holds every request until `workers` requests are in flight at once, so that
+	// lookups which are serialized by the client time out instead.
+	#[derive(Debug)]
+	struct BarrierProvider {
+		mock: MockProvider,
+		barrier: Barrier,
+	}
+
+	#[async_trait::async_trait]
+	impl JsonRpcClient for BarrierProvider {
+		type Error = MockError;
+
+		async fn request<T: Debug + Serialize + Send + Sync, R: DeserializeOwned>(
+			&self,
+			method: &str,
+			params: T,
+		) -> Result<R, MockError> {
+			tokio::time::timeout(Duration::from_secs(5), self.barrier.wait())
+				.await
+				.expect("Should have had every worker request in flight at once");
+			self.mock.request(method, params).await
+		}
+	}
+
+	#[test]
+	fn test_concurrent_get_header() {
+		let workers = 8;
+		let mock = MockProvider::new();
+		let provider = RPCProvider::new(BarrierProvider {
+			mock: mock.clone(),
+			barrier: Barrier::new(workers),
+		});
+		let client = SharedClient::with_provider(provider).unwrap();
+		for _ in 0..workers {
+			mock.push::<Block<ethers_core::types::Transaction>, _>(empty_block(EMPTY_TRIE_ROOT))
+				.unwrap();
+		}
+
+		let blocks: Vec<(Header, Vec<Receipt>)> = std::thread::scope(|s| {
+			let handles: Vec<_> = (0..workers)
+				.map(|_| {
+					let client = client.clone();
+					s.spawn(move || client.get_block_with_receipts(Hash::new([0x11; 32])).unwrap())
+				})
+				.collect();
+			handles.into_iter().map(|h| h.join().unwrap()).collect()
+		});
+		assert!(blocks.iter().all(|(h, receipts)| h.number == 8300532 && receipts.is_empty()));
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).unwrap().is_empty());
+		assert!(client.get_receipts_by_root(blocks[0].0.receipts_root.into()).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_shared_invalidate_from() {
+		let (provider, mock) = RPCProvider::mocked();
+		let client = SharedClient::with_provider(provider).unwrap();
+		mock.push::<Block<ethers_core::types::Transaction>, _>(empty_block(EMPTY_TRIE_ROOT))
+			.unwrap();
+		let (header, _) = client.get_block_with_receipts(Hash::new([0x11; 32])).unwrap();
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).is_ok());
+		assert!(client.get_receipts_by_root(header.receipts_root.into()).is_ok());
+
+		client.invalidate_from(header.number + 1);
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).is_ok());
+		client.invalidate_from(header.number);
+		assert!(client.get_transactions_by_root(EMPTY_TRIE_ROOT).is_err());
+		assert!(client.get_receipts_by_root(header.receipts_root.into()).is_err());
+	}
+}
@@ -1,24 +1,38 @@
-use core::types::Header;
-use ethers_core::types::{Block, Transaction};
+use core::types::{reth_h160, reth_h256, Header};
+use ethers_core::types::Block;
+
+/// HeaderError is returned when a block cannot be turned into a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+	/// A field that every mined block has is not set, as in a pending block.
+	MissingField(&'static str),
+}
+
+impl std::fmt::Display for HeaderError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			HeaderError::MissingField(field) => f.write_fmt(format_args!("block {field} is not set")),
+		}
+	}
+}
+
+impl std::error::Error for HeaderError {}
 
 /// Constructs a header from a given block
-pub fn header_from_block(block: Block<Transaction>) -> eyre::Result<Header> {
-	let author = block.author.ok_or_else(|| eyre::eyre!("block author is not set"))?;
-	let number = block.number.ok_or_else(|| eyre::eyre!("block number is not set"))?;
-	let bloom = block.logs_bloom.ok_or_else(|| eyre::eyre!("block logs bloom is not set"))?;
-	let mix_hash = block
-		.mix_hash
-		.map(|h| reth_primitives::H256::from(h.as_fixed_bytes()))
-		.ok_or_else(|| eyre::eyre!("block mix hash is not set"))?;
-	let nonce = block.nonce.ok_or_else(|| eyre::eyre!("block nonce is not set"))?;
+pub fn header_from_block<TX>(block: Block<TX>) -> Result<Header, HeaderError> {
+	let author = block.author.ok_or(HeaderError::MissingField("author"))?;
+	let number = block.number.ok_or(HeaderError::MissingField("number"))?;
+	let bloom = block.logs_bloom.ok_or(HeaderError::MissingField("logs bloom"))?;
+	let mix_hash = block.mix_hash.map(reth_h256).ok_or(HeaderError::MissingField("mix hash"))?;
+	let nonce = block.nonce.ok_or(HeaderError::MissingField("nonce"))?;
 	let nonce = nonce.to_low_u64_be();
 	Ok(Header {
-		parent_hash: reth_primitives::H256::from(block.parent_hash.as_fixed_bytes()),
-		ommers_hash: reth_primitives::H256::from(block.uncles_hash.as_fixed_bytes()),
-		state_root: reth_primitives::H256::from(block.state_root.as_fixed_bytes()),
-		beneficiary: reth_primitives::H160::from(author.as_fixed_bytes()),
-		transactions_root: reth_primitives::H256::from(block.transactions_root.as_fixed_bytes()),
-		receipts_root: reth_primitives::H256::from(block.receipts_root.as_fixed_bytes()),
+		parent_hash: reth_h256(block.parent_hash),
+		ommers_hash: reth_h256(block.uncles_hash),
+		state_root: reth_h256(block.state_root),
+		beneficiary: reth_h160(author),
+		transactions_root: reth_h256(block.transactions_root),
+		receipts_root: reth_h256(block.receipts_root),
 		number: number.as_u64(),
 		logs_bloom: reth_primitives::Bloom::from(bloom.as_fixed_bytes()),
 		gas_used: block.gas_used.as_u64(),
@@ -32,3 +46,24 @@ pub fn header_from_block(block: Block<Transaction>) -> eyre::Result<Header> {
 		withdrawals_root: None,
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethers_core::types::{Bloom, H256, H64, U64};
+
+	#[test]
+	fn test_header_from_block_missing_number() {
+		let mut block: Block<H256> = Block {
+			number: Some(U64::from(8300532)),
+			author: Some(Default::default()),
+			logs_bloom: Some(Bloom::default()),
+			mix_hash: Some(H256::zero()),
+			nonce: Some(H64::zero()),
+			..Default::default()
+		};
+		assert_eq!(header_from_block(block.clone()).unwrap().number, 8300532);
+		block.number = None;
+		assert_eq!(header_from_block(block).unwrap_err(), HeaderError::MissingField("number"));
+	}
+}
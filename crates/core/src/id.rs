@@ -24,11 +24,13 @@ pub struct L1BlockRef {
 	pub number: u64,
 	pub parent_hash: Hash,
 	pub time: u64,
+	pub base_fee: u64,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct L2BlockRef {
-	pub hash: Hash,
+	/// The hash of the block or None while it was derived but not executed yet
+	pub hash: Option<Hash>,
 	pub number: u64,
 	pub parent_hash: Hash,
 	pub time: u64,
@@ -61,6 +63,7 @@ impl From<Header> for L1BlockRef {
 			number: h.number,
 			parent_hash: h.parent_hash.into(),
 			time: h.timestamp,
+			base_fee: h.base_fee_per_gas.unwrap_or_default(),
 		}
 	}
 }
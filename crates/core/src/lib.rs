@@ -2,14 +2,62 @@ pub mod chain_config;
 pub mod id;
 pub mod types;
 
+use crate::{address_literal, hash_literal};
 use ethers_core::types::Transaction;
+use id::BlockID;
+use reth_primitives::{Bloom, Bytes, Header, U256};
+use types::{Address, Hash};
 
-#[derive(Debug)]
+/// The fee recipient of every L2 block. Fees go to the sequencer fee vault predeploy.
+pub const SEQUENCER_FEE_VAULT: Address = address_literal!("4200000000000000000000000000000000000011");
+/// The keccak hash of an empty list of ommers. L2 blocks have no ommers.
+pub const EMPTY_OMMERS_HASH: Hash = hash_literal!("1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347");
+
+#[derive(Debug, Clone, Default)]
 pub struct L2BlockCandidate {
 	pub number: u64,
 	pub timestamp: u64,
 	pub transactions: Vec<Transaction>,
-	// TODO: tx root
+	pub l1_origin: BlockID,
+	pub sequence_number: u64,
+	pub parent_hash: Hash,
+	pub gas_limit: u64,
+	// The remaining fields are only known once the execution engine built the block.
+	pub transactions_root: Hash,
+	pub gas_used: u64,
+	pub logs_bloom: Bloom,
+	pub base_fee: u64,
+	pub prev_randao: Hash,
+}
+
+impl L2BlockCandidate {
+	/// header builds the L2 header of the candidate from its fields & the post-execution roots.
+	pub fn header(&self, post_state: Hash, receipts_root: Hash) -> Header {
+		Header {
+			parent_hash: self.parent_hash.into(),
+			ommers_hash: EMPTY_OMMERS_HASH.into(),
+			beneficiary: SEQUENCER_FEE_VAULT.into(),
+			state_root: post_state.into(),
+			transactions_root: self.transactions_root.into(),
+			receipts_root: receipts_root.into(),
+			withdrawals_root: None,
+			logs_bloom: self.logs_bloom,
+			difficulty: U256::ZERO,
+			number: self.number,
+			gas_limit: self.gas_limit,
+			gas_used: self.gas_used,
+			timestamp: self.timestamp,
+			mix_hash: self.prev_randao.into(),
+			nonce: 0,
+			base_fee_per_gas: Some(self.base_fee),
+			extra_data: Bytes::default(),
+		}
+	}
+
+	/// header_hash returns the hash of the L2 block: the keccak hash of the RLP encoded header.
+	pub fn header_hash(&self, post_state: Hash, receipts_root: Hash) -> Hash {
+		self.header(post_state, receipts_root).hash_slow().into()
+	}
 }
 
 pub mod prelude {
@@ -24,3 +72,30 @@ pub mod prelude {
 	pub use crate::types::Transaction;
 	pub use crate::L2BlockCandidate; // TODO: remove
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_header_hash() {
+		let candidate = L2BlockCandidate {
+			number: 5,
+			timestamp: 1673550526,
+			parent_hash: Hash::new([0x11; 32]),
+			gas_limit: 25_000_000,
+			transactions_root: Hash::new([0x33; 32]),
+			gas_used: 21_000,
+			base_fee: 7,
+			prev_randao: Hash::new([0x22; 32]),
+			..Default::default()
+		};
+		// The keccak hash of the reference encoding
+		// rlp([parent, ommers, vault, state, txs, receipts, bloom, 0, 5, 25000000, 21000, 1673550526, "", randao, nonce, 7])
+		let hash = candidate.header_hash(Hash::new([0x44; 32]), Hash::new([0x55; 32]));
+		assert_eq!(
+			hash,
+			hash_literal!("33f3c10fd9b8dde00ab6bc79bc5e11e94c60035709dc3e1c4b18ab191a5f198e")
+		);
+	}
+}
@@ -2,9 +2,12 @@
 pub struct ChannelID([u8; 16]);
 
 impl ChannelID {
-	pub fn new(id: [u8; 16]) -> Self {
+	pub const fn new(id: [u8; 16]) -> Self {
 		Self(id)
 	}
+	pub fn to_vec(self) -> Vec<u8> {
+		Vec::from(self.0)
+	}
 }
 
 impl TryFrom<&[u8]> for ChannelID {
@@ -21,6 +24,16 @@ impl Address {
 	pub const fn new(v: [u8; 20]) -> Self {
 		Self(v)
 	}
+	pub fn to_vec(self) -> Vec<u8> {
+		Vec::from(self.0)
+	}
+}
+
+impl TryFrom<&[u8]> for Address {
+	type Error = std::array::TryFromSliceError;
+	fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+		Ok(Self::new(value.try_into()?))
+	}
 }
 
 impl From<reth_primitives::H160> for Address {
@@ -35,6 +48,18 @@ impl From<ethers_core::types::H160> for Address {
 	}
 }
 
+impl From<Address> for ethers_core::types::H160 {
+	fn from(val: Address) -> Self {
+		ethers_core::types::H160::from(val.0)
+	}
+}
+
+impl From<Address> for reth_primitives::H160 {
+	fn from(val: Address) -> Self {
+		reth_primitives::H160::from(val.0)
+	}
+}
+
 #[macro_export]
 macro_rules! address_literal {
 	($s:literal) => {
@@ -54,6 +79,13 @@ impl Hash {
 	}
 }
 
+impl TryFrom<&[u8]> for Hash {
+	type Error = std::array::TryFromSliceError;
+	fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+		Ok(Self::new(value.try_into()?))
+	}
+}
+
 impl From<reth_primitives::H256> for Hash {
 	fn from(value: reth_primitives::H256) -> Self {
 		Self(value.to_fixed_bytes())
@@ -72,6 +104,12 @@ impl From<Hash> for ethers_core::types::H256 {
 	}
 }
 
+impl From<Hash> for reth_primitives::H256 {
+	fn from(val: Hash) -> Self {
+		reth_primitives::H256::from(val.0)
+	}
+}
+
 #[macro_export]
 macro_rules! hash_literal {
 	($s:literal) => {
@@ -82,25 +120,142 @@ macro_rules! hash_literal {
 pub type Header = reth_primitives::Header;
 pub type Receipt = ethers_core::types::TransactionReceipt;
 
+/// Withdrawal is a withdrawal from the beacon chain to the execution layer as defined in EIP-4895.
+/// The amount is denominated in Gwei.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Withdrawal {
+	pub index: u64,
+	pub validator_index: u64,
+	pub address: Address,
+	pub amount: u64,
+}
+
+impl ethers_core::utils::rlp::Encodable for Withdrawal {
+	fn rlp_append(&self, s: &mut ethers_core::utils::rlp::RlpStream) {
+		s.begin_list(4);
+		s.append(&self.index);
+		s.append(&self.validator_index);
+		s.append(&ethers_core::types::H160::from(self.address));
+		s.append(&self.amount);
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Transaction {
 	pub hash: Hash,
 	pub to: Option<Address>,
 	pub from: Address,
+	pub nonce: u64,
 	pub input: Vec<u8>,
+	pub signature: Option<TxSignature>,
+	/// The position of the transaction in its block if it is known.
+	pub transaction_index: Option<u64>,
 }
 
 impl From<ethers_core::types::Transaction> for Transaction {
 	fn from(value: ethers_core::types::Transaction) -> Self {
+		let signed = !value.r.is_zero() || !value.s.is_zero();
+		let signature = signed.then(|| TxSignature {
+			signature: ethers_core::types::Signature {
+				r: value.r,
+				s: value.s,
+				v: value.v.as_u64(),
+			},
+			sighash: ethers_core::types::transaction::eip2718::TypedTransaction::from(&value)
+				.sighash()
+				.into(),
+		});
 		Transaction {
 			hash: value.hash.into(),
 			to: value.to.map(Address::from),
 			from: value.from.into(),
+			nonce: value.nonce.as_u64(),
 			input: value.input.to_vec(),
+			signature,
+			transaction_index: value.transaction_index.map(|i| i.as_u64()),
 		}
 	}
 }
 
+/// TxSignature is the signature of a transaction along with the hash that was signed.
+/// It allows the sender to be recovered rather than trusting the `from` field of the provider.
+#[derive(Debug, Clone, Copy)]
+pub struct TxSignature {
+	pub signature: ethers_core::types::Signature,
+	pub sighash: Hash,
+}
+
+impl TxSignature {
+	/// recover returns the address that signed the transaction.
+	pub fn recover(&self) -> Option<Address> {
+		let sighash: ethers_core::types::H256 = self.sighash.into();
+		self.signature.recover(sighash).ok().map(Address::from)
+	}
+}
+
 pub fn keccak(data: impl AsRef<[u8]>) -> Hash {
 	reth_primitives::keccak256(data).into()
 }
+
+/// reth_h256 converts an ethers hash into a reth hash. The two crates use distinct fixed hash types
+/// so the conversion goes through [Hash].
+pub fn reth_h256(value: ethers_core::types::H256) -> reth_primitives::H256 {
+	Hash::from(value).into()
+}
+
+/// ethers_h256 is the inverse of reth_h256.
+pub fn ethers_h256(value: reth_primitives::H256) -> ethers_core::types::H256 {
+	Hash::from(value).into()
+}
+
+/// reth_h160 converts an ethers address into a reth address.
+pub fn reth_h160(value: ethers_core::types::H160) -> reth_primitives::H160 {
+	Address::from(value).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_hash_conversions_round_trip() {
+		let bytes: [u8; 32] = std::array::from_fn(|i| i as u8);
+		let hash = Hash::new(bytes);
+		let ethers = ethers_core::types::H256::from(hash);
+		let reth = reth_primitives::H256::from(hash);
+		assert_eq!(ethers.to_fixed_bytes(), bytes);
+		assert_eq!(reth.to_fixed_bytes(), bytes);
+		assert_eq!(Hash::from(ethers), hash);
+		assert_eq!(Hash::from(reth), hash);
+		assert_eq!(reth_h256(ethers), reth);
+		assert_eq!(ethers_h256(reth), ethers);
+
+		assert_eq!(Hash::try_from(&bytes[..]).unwrap(), hash);
+		assert!(Hash::try_from(&bytes[1..]).is_err());
+	}
+
+	#[test]
+	fn test_address_conversions_round_trip() {
+		let bytes: [u8; 20] = std::array::from_fn(|i| i as u8);
+		let address = Address::new(bytes);
+		let ethers = ethers_core::types::H160::from(address);
+		let reth = reth_primitives::H160::from(address);
+		assert_eq!(Address::from(ethers), address);
+		assert_eq!(Address::from(reth), address);
+		assert_eq!(reth_h160(ethers), reth);
+		assert_eq!(Address::try_from(&bytes[..]).unwrap(), address);
+		assert!(Address::try_from(&[0u8; 32][..]).is_err());
+	}
+
+	#[test]
+	fn test_withdrawal_rlp() {
+		let withdrawal = Withdrawal {
+			index: 0,
+			validator_index: 65535,
+			address: address_literal!("388c818ca8b9251b393131c08a736a67ccb19297"),
+			amount: 21298,
+		};
+		let expected = hex_literal::hex!("dc8082ffff94388c818ca8b9251b393131c08a736a67ccb19297825332");
+		assert_eq!(ethers_core::utils::rlp::encode(&withdrawal).to_vec(), expected);
+	}
+}
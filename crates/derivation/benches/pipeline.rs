@@ -0,0 +1,36 @@
+use core::chain_config::GOERLI_CONFIG;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+#[path = "../tests/common.rs"]
+mod common;
+
+fn bench_pipeline(c: &mut Criterion) {
+	let mut group = c.benchmark_group("derivation");
+	for count in [10, 100, 1_000] {
+		let transactions = common::batcher_transactions(&GOERLI_CONFIG, count);
+		let bytes: usize = transactions.iter().map(|tx| tx.input.len()).sum();
+		group.throughput(Throughput::Bytes(bytes as u64));
+		group.bench_function(format!("load_l1_data/{count}"), |b| {
+			b.iter_batched(
+				|| transactions.clone(),
+				|transactions| common::derive_all(GOERLI_CONFIG, transactions),
+				BatchSize::SmallInput,
+			)
+		});
+	}
+	for noise in [1_000, 10_000] {
+		let transactions = common::large_block(&GOERLI_CONFIG, 100, noise);
+		group.throughput(Throughput::Elements(transactions.len() as u64));
+		group.bench_function(format!("large_block/{noise}"), |b| {
+			b.iter_batched(
+				|| transactions.clone(),
+				|transactions| common::derive_all(GOERLI_CONFIG, transactions),
+				BatchSize::SmallInput,
+			)
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+
+use derivation::frame::parse_frames;
+use libfuzzer_sys::fuzz_target;
+
+// Every frame is encoded as id (16) || number (2) || data length (4) || data || is_last (1).
+const FRAME_OVERHEAD: usize = 23;
+
+fuzz_target!(|input: &[u8]| {
+	let frames = parse_frames(input);
+	let encoded: usize = frames.iter().map(|f| f.data.len() + FRAME_OVERHEAD).sum();
+	assert!(frames.is_empty() || encoded < input.len(), "frames are larger than the transaction data");
+});
@@ -0,0 +1,210 @@
+use core::prelude::*;
+use core::types::keccak;
+use ethers_core::{
+	abi::{encode, Token},
+	types::{Bytes, Log, Transaction, H160, H256, U256, U64},
+	utils::{id, rlp::RlpStream},
+};
+use hex_literal::hex;
+
+pub(crate) const DEPOSIT_TX_TYPE: u8 = 0x7e;
+const L1_INFO_DEPOSITOR: H160 = H160(hex!("deaddeaddeaddeaddeaddeaddeaddeaddead0001"));
+const L1_BLOCK_ADDRESS: H160 = H160(hex!("4200000000000000000000000000000000000015"));
+const L1_INFO_FUNC_SIGNATURE: &str = "setL1BlockValues(uint64,uint64,uint256,bytes32,uint64,bytes32,uint256,uint256)";
+const L1_INFO_DEPOSIT_SOURCE_DOMAIN: u8 = 1;
+const USER_DEPOSIT_SOURCE_DOMAIN: u8 = 0;
+// keccak("TransactionDeposited(address,address,uint256,bytes)")
+const TRANSACTION_DEPOSITED_TOPIC: H256 = H256(hex!("b3813568d9991fc951961fcb4c784893574240a28925604d09fc577c55bb7c32"));
+// The opaque data of a version 0 deposit is mint (32) || value (32) || gas (8) || is_creation (1) || data.
+const OPAQUE_DATA_MIN_LEN: usize = 73;
+const SYSTEM_TX_GAS: u64 = 150_000_000;
+const REGOLITH_SYSTEM_TX_GAS: u64 = 1_000_000;
+
+/// DepositTx is a transaction that originates on L1.
+#[derive(Debug, Clone)]
+pub struct DepositTx {
+	pub source_hash: H256,
+	pub from: H160,
+	pub to: Option<H160>,
+	pub mint: U256,
+	pub value: U256,
+	pub gas: u64,
+	pub is_system_tx: bool,
+	pub data: Vec<u8>,
+}
+
+impl DepositTx {
+	/// encode returns the EIP-2718 encoding of the deposit: 0x7E || rlp(fields)
+	pub fn encode(&self) -> Vec<u8> {
+		let mut s = RlpStream::new_list(8);
+		s.append(&self.source_hash);
+		s.append(&self.from);
+		match self.to {
+			Some(to) => s.append(&to),
+			None => s.append_empty_data(),
+		};
+		s.append(&self.mint);
+		s.append(&self.value);
+		s.append(&self.gas);
+		s.append(&u8::from(self.is_system_tx));
+		s.append(&self.data);
+		let mut out = vec![DEPOSIT_TX_TYPE];
+		out.extend_from_slice(&s.out());
+		out
+	}
+
+	pub fn hash(&self) -> H256 {
+		keccak(self.encode()).into()
+	}
+}
+
+impl From<DepositTx> for Transaction {
+	fn from(value: DepositTx) -> Self {
+		Transaction {
+			hash: value.hash(),
+			from: value.from,
+			to: value.to,
+			value: value.value,
+			gas: value.gas.into(),
+			input: Bytes::from(value.data),
+			transaction_type: Some(U64::from(DEPOSIT_TX_TYPE)),
+			..Default::default()
+		}
+	}
+}
+
+/// l1_attributes_tx creates the system deposit that sets the L1 block info on L2.
+/// It is the first transaction of every L2 block. `l2_timestamp` selects the Regolith rules.
+pub fn l1_attributes_tx(origin: &L1BlockRef, seq_number: u64, l2_timestamp: u64, config: &RollupConfig) -> Transaction {
+	let sys_config = config.system_config;
+	let batcher_address: H160 = sys_config.batcher_address.into();
+	let mut data = id(L1_INFO_FUNC_SIGNATURE).to_vec();
+	data.extend(encode(&[
+		Token::Uint(origin.number.into()),
+		Token::Uint(origin.time.into()),
+		Token::Uint(origin.base_fee.into()),
+		Token::FixedBytes(origin.hash.to_vec()),
+		Token::Uint(seq_number.into()),
+		Token::FixedBytes(H256::from(batcher_address).as_bytes().to_vec()),
+		Token::Uint(U256::from_big_endian(&sys_config.overhead.to_vec())),
+		Token::Uint(U256::from_big_endian(&sys_config.scalar.to_vec())),
+	]));
+
+	let regolith = config.regolith_time.is_some_and(|t| l2_timestamp >= t);
+	DepositTx {
+		source_hash: l1_info_source_hash(origin.hash, seq_number),
+		from: L1_INFO_DEPOSITOR,
+		to: Some(L1_BLOCK_ADDRESS),
+		mint: U256::zero(),
+		value: U256::zero(),
+		gas: if regolith { REGOLITH_SYSTEM_TX_GAS } else { SYSTEM_TX_GAS },
+		is_system_tx: !regolith,
+		data,
+	}
+	.into()
+}
+
+// l1_info_source_hash computes keccak(domain || keccak(l1 block hash || seq number)).
+fn l1_info_source_hash(l1_hash: Hash, seq_number: u64) -> H256 {
+	let mut deposit_id = [0u8; 64];
+	deposit_id[..32].copy_from_slice(&l1_hash.to_vec());
+	deposit_id[56..].copy_from_slice(&seq_number.to_be_bytes());
+	let mut input = [0u8; 64];
+	input[31] = L1_INFO_DEPOSIT_SOURCE_DOMAIN;
+	input[32..].copy_from_slice(&keccak(deposit_id).to_vec());
+	keccak(input).into()
+}
+
+/// user_deposits decodes the deposits that the portal emitted in the receipts of an L1 block in
+/// the order of the logs. Logs that are not valid version 0 deposits are skipped.
+pub fn user_deposits(receipts: &[Receipt], l1_hash: Hash, portal: Address) -> Vec<Transaction> {
+	let portal: H160 = portal.into();
+	receipts.iter()
+		.flat_map(|r| &r.logs)
+		.filter(|log| log.address == portal && log.topics.first() == Some(&TRANSACTION_DEPOSITED_TOPIC))
+		.filter_map(|log| user_deposit(log, l1_hash))
+		.map(Transaction::from)
+		.collect()
+}
+
+// user_deposit decodes a TransactionDeposited(from, to, version, opaque_data) log.
+fn user_deposit(log: &Log, l1_hash: Hash) -> Option<DepositTx> {
+	let [_, from, to, version] = log.topics[..] else {
+		return None;
+	};
+	if !version.is_zero() {
+		return None;
+	}
+	// The opaque data is abi encoded as bytes: offset || length || data
+	let data = &log.data;
+	if data.len() < 64 || U256::from_big_endian(&data[..32]) != U256::from(32) {
+		return None;
+	}
+	let len = U256::from_big_endian(&data[32..64]);
+	if len > U256::from(data.len() - 64) {
+		return None;
+	}
+	let opaque = &data[64..64 + len.as_usize()];
+	if opaque.len() < OPAQUE_DATA_MIN_LEN {
+		return None;
+	}
+	let is_creation = opaque[72] != 0;
+	Some(DepositTx {
+		source_hash: user_deposit_source_hash(l1_hash, log.log_index?),
+		from: H160::from(from),
+		to: (!is_creation).then(|| H160::from(to)),
+		mint: U256::from_big_endian(&opaque[..32]),
+		value: U256::from_big_endian(&opaque[32..64]),
+		gas: u64::from_be_bytes(opaque[64..72].try_into().expect("Should have checked the length")),
+		is_system_tx: false,
+		data: opaque[OPAQUE_DATA_MIN_LEN..].to_vec(),
+	})
+}
+
+// user_deposit_source_hash computes keccak(domain || keccak(l1 block hash || log index)).
+fn user_deposit_source_hash(l1_hash: Hash, log_index: U256) -> H256 {
+	let mut deposit_id = [0u8; 64];
+	deposit_id[..32].copy_from_slice(&l1_hash.to_vec());
+	log_index.to_big_endian(&mut deposit_id[32..]);
+	let mut input = [0u8; 64];
+	input[31] = USER_DEPOSIT_SOURCE_DOMAIN;
+	input[32..].copy_from_slice(&keccak(deposit_id).to_vec());
+	keccak(input).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use core::chain_config::GOERLI_CONFIG;
+	use core::hash_literal;
+
+	#[test]
+	fn test_l1_attributes_tx() {
+		let origin = L1BlockRef {
+			hash: hash_literal!("6ffc1bf3754c01f6bb9fe057c1578b87a8571ce2e9be5ca14bace6eccfd336c7"),
+			number: 8300214,
+			time: 1673550516,
+			base_fee: 7,
+			..Default::default()
+		};
+		let tx = l1_attributes_tx(&origin, 3, 0, &GOERLI_CONFIG);
+		let input = tx.input.to_vec();
+
+		assert_eq!(input[..4], hex!("015d8eb9"));
+		assert_eq!(input.len(), 4 + 8 * 32);
+		let word = |i: usize| &input[4 + i * 32..4 + (i + 1) * 32];
+		assert_eq!(U256::from_big_endian(word(0)), U256::from(origin.number));
+		assert_eq!(U256::from_big_endian(word(1)), U256::from(origin.time));
+		assert_eq!(U256::from_big_endian(word(2)), U256::from(origin.base_fee));
+		assert_eq!(word(3), origin.hash.to_vec());
+		assert_eq!(U256::from_big_endian(word(4)), U256::from(3u64));
+		assert_eq!(word(5)[12..], hex!("7431310e026B69BFC676C0013E12A1A11411EEc9"));
+		assert_eq!(U256::from_big_endian(word(6)), U256::from(0x834u64));
+		assert_eq!(U256::from_big_endian(word(7)), U256::from(0xf4240u64));
+
+		assert_eq!(tx.from, L1_INFO_DEPOSITOR);
+		assert_eq!(tx.to, Some(L1_BLOCK_ADDRESS));
+		assert_eq!(tx.transaction_type, Some(U64::from(DEPOSIT_TX_TYPE)));
+		assert_eq!(tx.gas, U256::from(SYSTEM_TX_GAS));
+	}
+}
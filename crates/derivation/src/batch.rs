@@ -1,10 +1,18 @@
+use crate::compression::decompress;
+use crate::error::DecodeError;
+use core::prelude::*;
 use ethers_core::{
 	types::H256, // Use ethers core H256 b/c it implements decodable
-	utils::rlp::{decode, Decodable, DecoderError, Rlp},
+	utils::rlp::{self, decode, Decodable, DecoderError, Encodable, Rlp, RlpStream},
 };
 use eyre::Result;
+use flate2::{write::ZlibEncoder, Compression};
+use std::io::Write;
 
-#[derive(Debug)]
+/// The version byte that prefixes an encoded batch.
+const BATCH_VERSION_0: u8 = 0;
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct BatchV1 {
 	pub parent_hash: H256,
 	pub epoch_num: u64,
@@ -13,7 +21,7 @@ pub struct BatchV1 {
 	pub transactions: Vec<Vec<u8>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Batch {
 	pub batch: BatchV1,
 	// TODO: Metadata here
@@ -23,7 +31,7 @@ impl Decodable for Batch {
 	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
 		// TODO: Make this more robust
 		let first = rlp.as_raw()[0];
-		if first != 0 {
+		if first != BATCH_VERSION_0 {
 			return Err(DecoderError::Custom("invalid version byte"));
 		}
 		let batch: BatchV1 = decode(&rlp.as_raw()[1..])?;
@@ -31,6 +39,86 @@ impl Decodable for Batch {
 	}
 }
 
+impl Batch {
+	/// new creates a batch for the L2 block at `timestamp` in the given epoch.
+	pub fn new(parent_hash: H256, epoch: BlockID, timestamp: u64, transactions: Vec<Vec<u8>>) -> Self {
+		Batch {
+			batch: BatchV1 {
+				parent_hash,
+				epoch_num: epoch.number,
+				epoch_hash: epoch.hash.into(),
+				timestamp,
+				transactions,
+			},
+		}
+	}
+
+	/// builder returns a BatchBuilder for an empty batch at timestamp 0 of the zero epoch.
+	pub fn builder() -> BatchBuilder {
+		BatchBuilder::default()
+	}
+
+	/// encode returns the encoding of the batch in a channel: version byte || rlp(batch)
+	pub fn encode(&self) -> Vec<u8> {
+		let mut out = vec![BATCH_VERSION_0];
+		out.extend_from_slice(&rlp::encode(&self.batch));
+		out
+	}
+}
+
+/// BatchBuilder synthesizes a batch field by field without going through the channel encoding.
+#[derive(Debug, Default)]
+pub struct BatchBuilder {
+	parent_hash: H256,
+	epoch: BlockID,
+	timestamp: u64,
+	transactions: Vec<Vec<u8>>,
+}
+
+impl BatchBuilder {
+	pub fn parent_hash(mut self, parent_hash: H256) -> Self {
+		self.parent_hash = parent_hash;
+		self
+	}
+
+	pub fn epoch(mut self, epoch: BlockID) -> Self {
+		self.epoch = epoch;
+		self
+	}
+
+	pub fn timestamp(mut self, timestamp: u64) -> Self {
+		self.timestamp = timestamp;
+		self
+	}
+
+	/// transaction appends a raw L2 transaction to the batch.
+	pub fn transaction(mut self, tx: Vec<u8>) -> Self {
+		self.transactions.push(tx);
+		self
+	}
+
+	/// transactions replaces the raw L2 transactions of the batch.
+	pub fn transactions(mut self, transactions: Vec<Vec<u8>>) -> Self {
+		self.transactions = transactions;
+		self
+	}
+
+	pub fn build(self) -> Batch {
+		Batch::new(self.parent_hash, self.epoch, self.timestamp, self.transactions)
+	}
+}
+
+impl Encodable for BatchV1 {
+	fn rlp_append(&self, s: &mut RlpStream) {
+		s.begin_list(5);
+		s.append(&self.parent_hash);
+		s.append(&self.epoch_num);
+		s.append(&self.epoch_hash);
+		s.append(&self.timestamp);
+		s.append_list::<Vec<u8>, _>(&self.transactions);
+	}
+}
+
 impl Decodable for BatchV1 {
 	fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
 		let parent_hash: H256 = rlp.val_at(0)?;
@@ -70,3 +158,47 @@ pub fn parse_batches(data: Vec<u8>) -> Vec<Batch> {
 	}
 	decoded_batches.iter().filter_map(|b| decode(b).ok()).collect()
 }
+
+/// batches_to_channel_bytes creates the channel data for the batches: each encoded batch is wrapped
+/// in an RLP string & the stream is zlib compressed. It is the inverse of `channel_bytes_to_batches`.
+pub fn batches_to_channel_bytes(batches: &[Batch]) -> Vec<u8> {
+	let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+	for batch in batches {
+		encoder.write_all(&rlp::encode(&batch.encode()))
+			.expect("Cannot fail to write to a vec");
+	}
+	encoder.finish().expect("Cannot fail to write to a vec")
+}
+
+/// channel_bytes_to_batches decompresses channel data & decodes the batches in it.
+pub fn channel_bytes_to_batches(data: &[u8]) -> Result<Vec<Batch>, DecodeError> {
+	Ok(parse_batches(decompress(data)?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_channel_bytes_round_trip() {
+		let batches: Vec<Batch> = (0..3u64)
+			.map(|i| Batch {
+				batch: BatchV1 {
+					parent_hash: H256::repeat_byte(i as u8),
+					epoch_num: 100 + i,
+					epoch_hash: H256::repeat_byte(0xee),
+					timestamp: 1_000 + 2 * i,
+					transactions: (0..i).map(|j| vec![j as u8; 40]).collect(),
+				},
+			})
+			.collect();
+		let data = batches_to_channel_bytes(&batches);
+		assert_eq!(channel_bytes_to_batches(&data).unwrap(), batches);
+		assert!(channel_bytes_to_batches(&batches_to_channel_bytes(&[])).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_empty_channel_has_no_batches() {
+		assert!(channel_bytes_to_batches(&[]).unwrap().is_empty());
+	}
+}
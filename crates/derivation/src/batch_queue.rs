@@ -1,18 +1,69 @@
-use ethers_core::{types::Transaction, utils::rlp::decode};
 use std::collections::{HashMap, VecDeque};
 
+use super::attributes::{l1_attributes_tx, DEPOSIT_TX_TYPE};
 use super::batch::Batch;
+use super::error::DerivationError;
+use super::transaction::decode_transaction;
 use core::prelude::*;
+use ethers_core::utils::rlp::DecoderError;
+use serde::Serialize;
+
+/// The maximum number of batches that are held in the queue. Once it is exceeded the batches
+/// with the oldest timestamp are evicted so that a stalled consumer cannot grow the queue forever.
+const MAX_QUEUED_BATCHES: usize = 10_000;
+
+/// AcceptDecision is the outcome of checking a batch against the current L2 head.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptDecision {
+	/// The batch builds the next L2 block.
+	Accept,
+	/// The batch can never become valid.
+	Drop(DropReason),
+	/// The batch is for a later block or its epoch's L1 block is not loaded yet.
+	Future,
+	/// The batch is for a block at or before the L2 head.
+	Past,
+}
+
+/// DropReason is the validity check that a dropped batch failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+	ParentHashMismatch,
+	EpochTooOld,
+	EpochTooFar,
+	EpochHashMismatch,
+	EpochWindowExpired,
+	TimestampBeforeOrigin,
+	SequencerDrift,
+	/// The batch contains a deposit transaction. Deposits can only be derived from L1.
+	DepositTransaction,
+	/// The batch contains a transaction that is signed for another chain than the L2 chain.
+	WrongChainId,
+	/// The batch contains a transaction that cannot be decoded.
+	InvalidTransaction,
+}
+
+/// BatchQueueCounts is a serializable summary of what the batch queue holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BatchQueueCounts {
+	pub l1_blocks: usize,
+	pub batches: usize,
+	/// The number of L1 blocks whose deposits have not been included in a candidate yet
+	pub pending_deposits: usize,
+}
 
 #[derive(Debug)]
 pub struct BatchQueue {
 	l1_blocks: VecDeque<L1BlockRef>,
 	// Map batch timestamp to batches in order that they were received
 	batches: HashMap<u64, VecDeque<Batch>>,
+	// Map L1 block number to the user deposits of the block
+	deposits: HashMap<u64, Vec<ethers_core::types::Transaction>>,
 
 	l2_block_time: u64,
 	// seq_window_size: u64,
 	// max_sequencer_drift: u64,
+	config: RollupConfig,
 }
 
 impl BatchQueue {
@@ -20,35 +71,694 @@ impl BatchQueue {
 		BatchQueue {
 			l1_blocks: VecDeque::default(),
 			batches: HashMap::default(),
+			deposits: HashMap::default(),
 			l2_block_time: cfg.l2_block_time,
 			// seq_window_size: cfg.seq_window_size,
 			// max_sequencer_drift: cfg.max_sequencer_drift,
+			config: cfg,
 		}
 	}
+	/// load_batches queues the batches of an L1 block. The block can be loaded more than once when
+	/// its batches & deposits are loaded separately.
 	pub fn load_batches(&mut self, batches: impl Iterator<Item = Batch>, l1_origin: L1BlockRef) {
-		self.l1_blocks.push_back(l1_origin);
+		self.load_l1_block(l1_origin);
 		for b in batches {
-			self.batches.entry(b.batch.timestamp).or_default().push_back(b);
+			let bucket = self.batches.entry(b.batch.timestamp).or_default();
+			// A batcher may resubmit the same batch, which must not be queued twice.
+			if !bucket.contains(&b) {
+				bucket.push_back(b);
+			}
 		}
+		self.evict();
 	}
 
-	pub fn get_block_candidate(&mut self, l2_head: L2BlockRef) -> Option<L2BlockCandidate> {
-		let next_timestamp = l2_head.time + self.l2_block_time;
-		if let Some(candidates) = self.batches.get(&next_timestamp) {
-			#[allow(clippy::never_loop)]
-			for b in candidates {
-				// TODO: Do this step earlier
-				let txns = b.batch.transactions.iter().map(|t| decode::<Transaction>(t).unwrap()).collect();
-				self.batches.remove(&next_timestamp);
-				// TODO: deposits, seq number, transactions from batches
-				return Some(L2BlockCandidate {
-					number: l2_head.number + 1,
-					timestamp: next_timestamp,
-					transactions: txns,
-				});
+	/// load_deposits records the user deposits of an L1 block. They are included in the first L2
+	/// block of the epoch of the L1 block.
+	pub fn load_deposits(&mut self, deposits: Vec<ethers_core::types::Transaction>, l1_origin: L1BlockRef) {
+		self.load_l1_block(l1_origin);
+		self.deposits.insert(l1_origin.number, deposits);
+	}
+
+	// load_l1_block records the L1 block unless it is the last loaded block. A block with the number of
+	// a loaded block but another hash replaces it & the blocks after it, as they were reorged out.
+	fn load_l1_block(&mut self, l1_origin: L1BlockRef) {
+		if self.l1_blocks
+			.back()
+			.is_some_and(|l1| l1.number == l1_origin.number && l1.hash == l1_origin.hash)
+		{
+			return;
+		}
+		self.l1_blocks.retain(|l1| l1.number < l1_origin.number);
+		self.l1_blocks.push_back(l1_origin);
+	}
+
+	fn evict(&mut self) {
+		while self.len() > MAX_QUEUED_BATCHES {
+			let oldest = *self.batches.keys().min().expect("Should have a batch to evict");
+			self.batches.remove(&oldest);
+		}
+	}
+
+	fn len(&self) -> usize {
+		self.batches.values().map(|b| b.len()).sum()
+	}
+
+	/// counts returns how many L1 blocks, batches & deposits are queued.
+	pub fn counts(&self) -> BatchQueueCounts {
+		BatchQueueCounts {
+			l1_blocks: self.l1_blocks.len(),
+			batches: self.len(),
+			pending_deposits: self.deposits.len(),
+		}
+	}
+
+	/// prune drops batches for blocks at or before the safe head & L1 blocks before its L1 origin.
+	pub fn prune(&mut self, safe_head: L2BlockRef) {
+		self.batches.retain(|timestamp, _| *timestamp > safe_head.time);
+		self.l1_blocks.retain(|l1| l1.number >= safe_head.l1_origin.number);
+		self.deposits.retain(|number, _| *number > safe_head.l1_origin.number);
+	}
+
+	/// would_accept runs the validity checks of a batch against the L2 head & the loaded L1 blocks
+	/// without modifying the queue. get_block_candidate builds candidates from the batches it accepts.
+	pub fn would_accept(&self, batch: &Batch, l2_head: &L2BlockRef) -> AcceptDecision {
+		match self.check_batch(batch, l2_head) {
+			Ok(_) => AcceptDecision::Accept,
+			Err(decision) => decision,
+		}
+	}
+
+	// check_batch returns the epoch's L1 block & the decoded transactions of a batch that builds the
+	// next L2 block, or the decision for any other batch.
+	fn check_batch(
+		&self,
+		batch: &Batch,
+		l2_head: &L2BlockRef,
+	) -> Result<(L1BlockRef, Vec<ethers_core::types::Transaction>), AcceptDecision> {
+		let batch = &batch.batch;
+		// No batch can follow a head whose successor's timestamp does not fit.
+		let Some(next_timestamp) = l2_head.time.checked_add(self.l2_block_time) else {
+			return Err(AcceptDecision::Past);
+		};
+		if batch.timestamp > next_timestamp {
+			return Err(AcceptDecision::Future);
+		}
+		if batch.timestamp < next_timestamp {
+			return Err(AcceptDecision::Past);
+		}
+		// The hash of a head that was derived but not executed yet is unknown, so the parent
+		// hash can only be checked against executed heads.
+		if let Some(hash) = l2_head.hash && Hash::from(batch.parent_hash) != hash {
+			return Err(AcceptDecision::Drop(DropReason::ParentHashMismatch));
+		}
+		if has_deposit_transaction(&batch.transactions) {
+			return Err(AcceptDecision::Drop(DropReason::DepositTransaction));
+		}
+		let Ok(transactions) = decode_transactions(&batch.transactions) else {
+			return Err(AcceptDecision::Drop(DropReason::InvalidTransaction));
+		};
+		if has_wrong_chain_id(&transactions, self.config.l2_chain_id) {
+			return Err(AcceptDecision::Drop(DropReason::WrongChainId));
+		}
+		if batch.epoch_num < l2_head.l1_origin.number {
+			return Err(AcceptDecision::Drop(DropReason::EpochTooOld));
+		}
+		if batch.epoch_num > l2_head.l1_origin.number.saturating_add(1) {
+			return Err(AcceptDecision::Drop(DropReason::EpochTooFar));
+		}
+		// Batches are held until their epoch's L1 block is loaded, but once the sequencing window
+		// of the epoch has passed without seeing it, the batch can never become valid.
+		let Some(origin) = self.l1_blocks.iter().find(|l1| l1.number == batch.epoch_num) else {
+			let latest = self.l1_blocks.back().map(|l1| l1.number);
+			if latest.is_some_and(|n| n >= batch.epoch_num.saturating_add(self.config.seq_window_size)) {
+				return Err(AcceptDecision::Drop(DropReason::EpochWindowExpired));
 			}
+			return Err(AcceptDecision::Future);
+		};
+		if Hash::from(batch.epoch_hash) != origin.hash {
+			return Err(AcceptDecision::Drop(DropReason::EpochHashMismatch));
+		}
+		if batch.timestamp < origin.time {
+			return Err(AcceptDecision::Drop(DropReason::TimestampBeforeOrigin));
+		}
+		if batch.timestamp > origin.time.saturating_add(self.config.max_sequencer_drift) {
+			return Err(AcceptDecision::Drop(DropReason::SequencerDrift));
+		}
+		Ok((*origin, transactions))
+	}
+
+	/// check_l2_head rejects an L2 head whose timestamp is not `l2_genesis_time + k * l2_block_time`.
+	/// Candidates built on such a head would never line up with the batches. A head without a
+	/// representable next timestamp is rejected as well.
+	pub fn check_l2_head(&self, l2_head: &L2BlockRef) -> Result<(), DerivationError> {
+		let genesis_time = self.config.l2_genesis_time;
+		if l2_head.time < genesis_time || (l2_head.time - genesis_time) % self.l2_block_time != 0 {
+			return Err(DerivationError::MisalignedL2Head { time: l2_head.time });
 		}
+		self.next_timestamp(l2_head)?;
+		Ok(())
+	}
+
+	// next_timestamp returns the timestamp of the block after the L2 head without wrapping around.
+	fn next_timestamp(&self, l2_head: &L2BlockRef) -> Result<u64, DerivationError> {
+		l2_head.time
+			.checked_add(self.l2_block_time)
+			.ok_or(DerivationError::TimestampOverflow { time: l2_head.time })
+	}
 
+	/// get_block_candidate builds the candidate on top of the L2 head from the first batch for it that
+	/// would_accept accepts. The batches that it drops are removed, the batches that wait for the L1
+	/// block of their epoch are kept.
+	pub fn get_block_candidate(&mut self, l2_head: L2BlockRef) -> Option<L2BlockCandidate> {
+		let next_timestamp = self.next_timestamp(&l2_head).ok()?;
+		let mut batches = self.batches.remove(&next_timestamp)?;
+		let mut pending = VecDeque::new();
+		while let Some(b) = batches.pop_front() {
+			match self.check_batch(&b, &l2_head) {
+				Ok((origin, transactions)) => return Some(self.build_candidate(&b, origin, transactions, l2_head)),
+				Err(AcceptDecision::Drop(_)) => {}
+				Err(_) => pending.push_back(b),
+			}
+		}
+		if !pending.is_empty() {
+			self.batches.insert(next_timestamp, pending);
+		}
 		None
 	}
+
+	// build_candidate builds the candidate of an accepted batch. The deposits of its epoch are
+	// included in the first block of the epoch.
+	fn build_candidate(
+		&mut self,
+		batch: &Batch,
+		origin: L1BlockRef,
+		transactions: Vec<ethers_core::types::Transaction>,
+		l2_head: L2BlockRef,
+	) -> L2BlockCandidate {
+		let sequence_number = if origin.number == l2_head.l1_origin.number {
+			l2_head.sequence_number + 1
+		} else {
+			0
+		};
+		let timestamp = batch.batch.timestamp;
+		let mut txns = vec![l1_attributes_tx(&origin, sequence_number, timestamp, &self.config)];
+		// User deposits follow the L1 attributes in the first block of the epoch.
+		if sequence_number == 0 {
+			txns.extend(self.deposits.remove(&origin.number).unwrap_or_default());
+		}
+		txns.extend(transactions);
+		L2BlockCandidate {
+			number: l2_head.number + 1,
+			timestamp,
+			transactions: txns,
+			l1_origin: origin.into(),
+			sequence_number,
+			parent_hash: batch.batch.parent_hash.into(),
+			gas_limit: self.config.system_config.gas_limit,
+			..Default::default()
+		}
+	}
+}
+
+// has_deposit_transaction checks if any of the encoded transactions of a batch is a deposit.
+fn has_deposit_transaction(transactions: &[Vec<u8>]) -> bool {
+	transactions.iter().any(|tx| tx.first() == Some(&DEPOSIT_TX_TYPE))
+}
+
+// decode_transactions decodes the encoded transactions of a batch. It fails if any of them is invalid.
+fn decode_transactions(transactions: &[Vec<u8>]) -> Result<Vec<ethers_core::types::Transaction>, DecoderError> {
+	transactions.iter().map(|tx| decode_transaction(tx)).collect()
+}
+
+// has_wrong_chain_id checks if any of the transactions of a batch is signed for another chain.
+// Legacy transactions without EIP-155 replay protection do not commit to a chain & are accepted.
+fn has_wrong_chain_id(transactions: &[ethers_core::types::Transaction], l2_chain_id: u64) -> bool {
+	transactions.iter().any(|tx| {
+		let chain_id = match tx.transaction_type {
+			Some(ty) if !ty.is_zero() => tx.chain_id,
+			_ => (tx.v.as_u64() >= 35).then(|| ((tx.v.as_u64() - 35) / 2).into()),
+		};
+		chain_id.is_some_and(|id| id != ethers_core::types::U256::from(l2_chain_id))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use core::chain_config::GOERLI_CONFIG;
+	use ethers_core::types::H256;
+
+	fn block(number: u64) -> L1BlockRef {
+		L1BlockRef {
+			number,
+			time: GOERLI_CONFIG.l2_genesis_time,
+			..Default::default()
+		}
+	}
+
+	fn batch(epoch_num: u64) -> Batch {
+		batch_at(epoch_num, GOERLI_CONFIG.l2_genesis_time + GOERLI_CONFIG.l2_block_time)
+	}
+
+	fn batch_at(epoch_num: u64, timestamp: u64) -> Batch {
+		let epoch = BlockID {
+			number: epoch_num,
+			..Default::default()
+		};
+		Batch::new(H256::zero(), epoch, timestamp, Vec::new())
+	}
+
+	fn genesis() -> L2BlockRef {
+		L2BlockRef {
+			time: GOERLI_CONFIG.l2_genesis_time,
+			..Default::default()
+		}
+	}
+
+	// head_at returns the genesis with the given L1 origin.
+	fn head_at(l1_origin: u64) -> L2BlockRef {
+		L2BlockRef {
+			l1_origin: block(l1_origin).into(),
+			..genesis()
+		}
+	}
+
+	#[test]
+	fn test_batch_deferred_until_epoch_is_loaded() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		queue.load_batches([batch(5)].into_iter(), block(4));
+		assert!(queue.get_block_candidate(head_at(4)).is_none());
+
+		queue.load_batches(std::iter::empty(), block(5));
+		let candidate = queue.get_block_candidate(head_at(4)).unwrap();
+		assert_eq!(candidate.l1_origin.number, 5);
+	}
+
+	#[test]
+	fn test_built_batch_through_queue() {
+		use ethers_core::types::{transaction::eip2718::TypedTransaction, Signature, TransactionRequest, U256};
+		use ethers_core::utils::keccak256;
+
+		let request = TransactionRequest::new()
+			.gas(21_000u64)
+			.nonce(1u64)
+			.chain_id(GOERLI_CONFIG.l2_chain_id);
+		let signature = Signature {
+			r: U256::from(1),
+			s: U256::from(2),
+			v: GOERLI_CONFIG.l2_chain_id * 2 + 35,
+		};
+		let raw = TypedTransaction::Legacy(request).rlp_signed(&signature).to_vec();
+
+		let timestamp = GOERLI_CONFIG.l2_genesis_time + GOERLI_CONFIG.l2_block_time;
+		let batch = Batch::builder()
+			.epoch(block(0).into())
+			.timestamp(timestamp)
+			.transaction(raw.clone())
+			.build();
+		assert_eq!(batch, Batch::new(H256::zero(), block(0).into(), timestamp, vec![raw.clone()]));
+
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		queue.load_batches([batch].into_iter(), block(0));
+		let candidate = queue.get_block_candidate(genesis()).unwrap();
+		assert_eq!(candidate.timestamp, timestamp);
+		assert_eq!(candidate.transactions.len(), 2);
+		assert_eq!(candidate.transactions[1].hash, H256(keccak256(&raw)));
+	}
+
+	#[test]
+	fn test_batch_with_deposit_transaction_is_dropped() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let mut sneaky = batch(0);
+		sneaky.batch.transactions.push(vec![DEPOSIT_TX_TYPE, 0xc0]);
+		assert_eq!(
+			queue.would_accept(&sneaky, &genesis()),
+			AcceptDecision::Drop(DropReason::DepositTransaction)
+		);
+
+		// The deposit batch is dropped & the honest batch for the same block is used instead.
+		queue.load_batches([sneaky, batch(0)].into_iter(), block(0));
+		let candidate = queue.get_block_candidate(genesis()).unwrap();
+		assert_eq!(candidate.transactions.len(), 1);
+		assert!(queue.batches.is_empty());
+
+		// A bucket whose batches are all dropped leaves no empty entry behind.
+		let mut invalid = batch(0);
+		invalid.batch.transactions.push(vec![0xc1]);
+		queue.load_batches([invalid].into_iter(), block(0));
+		assert!(queue.get_block_candidate(genesis()).is_none());
+		assert!(queue.batches.is_empty());
+	}
+
+	#[test]
+	fn test_batch_with_wrong_chain_id_is_dropped() {
+		use ethers_core::types::transaction::{eip2718::TypedTransaction, eip2930::*};
+		use ethers_core::types::{Signature, TransactionRequest, U256};
+		use ethers_core::utils::keccak256;
+
+		let signed = |chain_id: u64, typed: bool| {
+			let request = TransactionRequest::new().gas(21_000u64).nonce(1u64).chain_id(chain_id);
+			let (tx, v) = if typed {
+				(
+					TypedTransaction::Eip2930(Eip2930TransactionRequest::new(request, AccessList::default())),
+					1,
+				)
+			} else {
+				(TypedTransaction::Legacy(request), chain_id * 2 + 35)
+			};
+			let signature = Signature {
+				r: U256::from(1),
+				s: U256::from(2),
+				v,
+			};
+			tx.rlp_signed(&signature).to_vec()
+		};
+		let with_tx = |raw: Vec<u8>| {
+			let mut b = batch(0);
+			b.batch.transactions.push(raw);
+			b
+		};
+		let queue = BatchQueue::new(GOERLI_CONFIG);
+		for typed in [false, true] {
+			assert_eq!(
+				queue.would_accept(&with_tx(signed(1, typed)), &genesis()),
+				AcceptDecision::Drop(DropReason::WrongChainId)
+			);
+			// Without the L1 block of the epoch the batch would otherwise wait for it.
+			assert_eq!(
+				queue.would_accept(&with_tx(signed(GOERLI_CONFIG.l2_chain_id, typed)), &genesis()),
+				AcceptDecision::Future
+			);
+		}
+
+		// The replayed batch is dropped & the batch for the L2 chain is used instead.
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let replayed = with_tx(signed(1, false));
+		let valid = signed(GOERLI_CONFIG.l2_chain_id, false);
+		queue.load_batches([replayed, with_tx(valid.clone())].into_iter(), block(0));
+		let candidate = queue.get_block_candidate(genesis()).unwrap();
+		assert_eq!(candidate.transactions.len(), 2);
+		assert_eq!(candidate.transactions[1].hash, H256(keccak256(&valid)));
+		assert!(queue.batches.is_empty());
+	}
+
+	#[test]
+	fn test_batch_with_invalid_transaction_is_dropped() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let mut invalid = batch(0);
+		invalid.batch.transactions.push(vec![0xc1]);
+		assert_eq!(
+			queue.would_accept(&invalid, &genesis()),
+			AcceptDecision::Drop(DropReason::InvalidTransaction)
+		);
+
+		// The invalid batch is dropped instead of failing the candidate.
+		queue.load_batches([invalid, batch(0)].into_iter(), block(0));
+		let candidate = queue.get_block_candidate(genesis()).unwrap();
+		assert_eq!(candidate.transactions.len(), 1);
+		assert!(queue.batches.is_empty());
+
+		// A bucket whose batches are all dropped leaves no empty entry behind.
+		let mut invalid = batch(0);
+		invalid.batch.transactions.push(vec![0xc1]);
+		queue.load_batches([invalid].into_iter(), block(0));
+		assert!(queue.get_block_candidate(genesis()).is_none());
+		assert!(queue.batches.is_empty());
+	}
+
+	#[test]
+	fn test_access_list_transaction_round_trip() {
+		use ethers_core::types::transaction::{eip2718::TypedTransaction, eip2930::*};
+		use ethers_core::types::{Signature, TransactionRequest, H160, U256};
+		use ethers_core::utils::keccak256;
+
+		let access_list = AccessList(vec![AccessListItem {
+			address: H160::repeat_byte(0x11),
+			storage_keys: vec![H256::repeat_byte(0x22), H256::repeat_byte(0x33)],
+		}]);
+		let request = TransactionRequest::new()
+			.to(H160::repeat_byte(0x44))
+			.value(1_000u64)
+			.gas(50_000u64)
+			.gas_price(7u64)
+			.nonce(3u64)
+			.chain_id(GOERLI_CONFIG.l2_chain_id)
+			.data(vec![0xab, 0xcd]);
+		let tx = TypedTransaction::Eip2930(Eip2930TransactionRequest::new(request, access_list.clone()));
+		let signature = Signature {
+			r: U256::from(1),
+			s: U256::from(2),
+			v: 1,
+		};
+		let raw = tx.rlp_signed(&signature).to_vec();
+		assert_eq!(raw[0], 0x01);
+
+		let mut b = batch(0);
+		b.batch.transactions.push(raw.clone());
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		queue.load_batches([b].into_iter(), block(0));
+		let candidate = queue.get_block_candidate(genesis()).unwrap();
+
+		// The L1 attributes deposit is followed by the batch transaction.
+		let decoded = &candidate.transactions[1];
+		assert_eq!(decoded.transaction_type, Some(1u64.into()));
+		assert_eq!(decoded.access_list, Some(access_list));
+		assert_eq!(decoded.hash, H256(keccak256(&raw)));
+		assert_eq!(decoded.rlp().to_vec(), raw);
+	}
+
+	#[test]
+	fn test_would_accept() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let origin = |number| L1BlockRef {
+			number,
+			time: GOERLI_CONFIG.l2_genesis_time,
+			..Default::default()
+		};
+		queue.load_batches(std::iter::empty(), origin(0));
+		queue.load_batches(std::iter::empty(), origin(1));
+		let head = genesis();
+		let next = head.time + GOERLI_CONFIG.l2_block_time;
+		let decide = |b: Batch| queue.would_accept(&b, &head);
+
+		assert_eq!(decide(batch_at(0, next)), AcceptDecision::Accept);
+		assert_eq!(decide(batch_at(1, next)), AcceptDecision::Accept);
+		assert_eq!(decide(batch_at(0, next + GOERLI_CONFIG.l2_block_time)), AcceptDecision::Future);
+		assert_eq!(decide(batch_at(0, head.time)), AcceptDecision::Past);
+		assert_eq!(decide(batch_at(3, next)), AcceptDecision::Drop(DropReason::EpochTooFar));
+
+		// The parent hash is only checked once the hash of the head is known.
+		let child = || {
+			let mut b = batch_at(0, next);
+			b.batch.parent_hash = H256::repeat_byte(1);
+			b
+		};
+		assert_eq!(decide(child()), AcceptDecision::Accept);
+		let executed = L2BlockRef {
+			hash: Some(Hash::new([1; 32])),
+			..head
+		};
+		assert_eq!(queue.would_accept(&child(), &executed), AcceptDecision::Accept);
+		assert_eq!(
+			queue.would_accept(&batch_at(0, next), &executed),
+			AcceptDecision::Drop(DropReason::ParentHashMismatch)
+		);
+		let mut b = batch_at(0, next);
+		b.batch.epoch_hash = H256::repeat_byte(1);
+		assert_eq!(decide(b), AcceptDecision::Drop(DropReason::EpochHashMismatch));
+
+		let later = L2BlockRef {
+			l1_origin: BlockID {
+				number: 1,
+				..Default::default()
+			},
+			..head
+		};
+		assert_eq!(
+			queue.would_accept(&batch_at(0, next), &later),
+			AcceptDecision::Drop(DropReason::EpochTooOld)
+		);
+		assert_eq!(queue.would_accept(&batch_at(2, next), &later), AcceptDecision::Future);
+	}
+
+	#[test]
+	fn test_would_accept_origin_checks() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let head = genesis();
+		let next = head.time + GOERLI_CONFIG.l2_block_time;
+		queue.load_batches(
+			std::iter::empty(),
+			L1BlockRef {
+				number: 0,
+				time: next + 1,
+				..Default::default()
+			},
+		);
+		queue.load_batches(
+			std::iter::empty(),
+			L1BlockRef {
+				number: 1,
+				time: next - GOERLI_CONFIG.max_sequencer_drift - 1,
+				..Default::default()
+			},
+		);
+		assert_eq!(
+			queue.would_accept(&batch_at(0, next), &head),
+			AcceptDecision::Drop(DropReason::TimestampBeforeOrigin)
+		);
+		assert_eq!(
+			queue.would_accept(&batch_at(1, next), &head),
+			AcceptDecision::Drop(DropReason::SequencerDrift)
+		);
+
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		queue.load_batches(std::iter::empty(), block(GOERLI_CONFIG.seq_window_size + 1));
+		assert_eq!(
+			queue.would_accept(&batch_at(1, next), &head),
+			AcceptDecision::Drop(DropReason::EpochWindowExpired)
+		);
+	}
+
+	#[test]
+	fn test_candidate_only_from_accepted_batch() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let head = L2BlockRef {
+			hash: Some(Hash::new([1; 32])),
+			..genesis()
+		};
+		let next = head.time + GOERLI_CONFIG.l2_block_time;
+		let origin = L1BlockRef {
+			hash: Hash::new([2; 32]),
+			..block(0)
+		};
+		let batch = |parent: u8, epoch: u8| {
+			let epoch = BlockID {
+				hash: Hash::new([epoch; 32]),
+				number: 0,
+			};
+			Batch::new(H256::repeat_byte(parent), epoch, next, Vec::new())
+		};
+		let rejected = [
+			(batch(0, 2), DropReason::ParentHashMismatch),
+			(batch(1, 3), DropReason::EpochHashMismatch),
+		];
+		queue.load_batches(std::iter::empty(), origin);
+		for (b, reason) in &rejected {
+			assert_eq!(queue.would_accept(b, &head), AcceptDecision::Drop(*reason));
+		}
+
+		// Only the last batch passes every check.
+		let valid = batch(1, 2);
+		assert_eq!(queue.would_accept(&valid, &head), AcceptDecision::Accept);
+		queue.load_batches(rejected.into_iter().map(|(b, _)| b).chain([valid]), origin);
+		let candidate = queue.get_block_candidate(head).unwrap();
+		assert_eq!(Some(candidate.parent_hash), head.hash);
+		assert_eq!(candidate.l1_origin, BlockID::from(origin));
+		assert!(queue.batches.is_empty());
+
+		// A batch beyond the sequencer drift of its epoch is dropped rather than built.
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let late = L1BlockRef {
+			time: next - GOERLI_CONFIG.max_sequencer_drift - 1,
+			..origin
+		};
+		queue.load_batches([batch(1, 2)].into_iter(), late);
+		assert!(queue.get_block_candidate(head).is_none());
+		assert!(queue.batches[&next].is_empty());
+	}
+
+	#[test]
+	fn test_reorged_l1_block_is_replaced() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let reorged = L1BlockRef {
+			hash: Hash::new([1; 32]),
+			..block(1)
+		};
+		let canonical = L1BlockRef {
+			hash: Hash::new([2; 32]),
+			..block(1)
+		};
+		queue.load_batches(std::iter::empty(), block(0));
+		queue.load_batches(std::iter::empty(), reorged);
+		queue.load_deposits(Vec::new(), reorged);
+		assert_eq!(queue.counts().l1_blocks, 2);
+		queue.load_batches(std::iter::empty(), canonical);
+		assert_eq!(queue.counts().l1_blocks, 2);
+
+		let next = GOERLI_CONFIG.l2_genesis_time + GOERLI_CONFIG.l2_block_time;
+		let batch = |epoch: &L1BlockRef| Batch::new(H256::zero(), (*epoch).into(), next, Vec::new());
+		assert_eq!(
+			queue.would_accept(&batch(&reorged), &genesis()),
+			AcceptDecision::Drop(DropReason::EpochHashMismatch)
+		);
+		assert_eq!(queue.would_accept(&batch(&canonical), &genesis()), AcceptDecision::Accept);
+	}
+
+	#[test]
+	fn test_duplicate_batch_queued_once() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		queue.load_batches([batch(5), batch(5)].into_iter(), block(4));
+		queue.load_batches([batch(5), batch(6)].into_iter(), block(5));
+		assert_eq!(queue.batches[&batch(5).batch.timestamp], [batch(5), batch(6)]);
+	}
+
+	#[test]
+	fn test_oldest_batches_evicted() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let timestamp = |i: u64| GOERLI_CONFIG.l2_genesis_time + (i + 1) * GOERLI_CONFIG.l2_block_time;
+		// Load the first bucket last to check that eviction goes by timestamp rather than arrival.
+		queue.load_batches((1..MAX_QUEUED_BATCHES as u64).map(|i| batch_at(0, timestamp(i))), block(0));
+		queue.load_batches([batch_at(0, timestamp(0)), batch_at(1, timestamp(0))].into_iter(), block(1));
+		assert_eq!(queue.len(), MAX_QUEUED_BATCHES - 1);
+		assert!(!queue.batches.contains_key(&timestamp(0)));
+		assert!(queue.batches.contains_key(&timestamp(1)));
+		assert!(queue.batches.contains_key(&timestamp(MAX_QUEUED_BATCHES as u64 - 1)));
+	}
+
+	#[test]
+	fn test_batch_dropped_when_epoch_never_arrives() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		queue.load_batches([batch(5)].into_iter(), block(4));
+		for n in 6..5 + GOERLI_CONFIG.seq_window_size {
+			queue.load_batches(std::iter::empty(), block(n));
+		}
+		assert!(queue.get_block_candidate(head_at(4)).is_none());
+		assert_eq!(queue.batches[&batch(5).batch.timestamp].len(), 1);
+
+		queue.load_batches(std::iter::empty(), block(5 + GOERLI_CONFIG.seq_window_size));
+		assert!(queue.get_block_candidate(head_at(4)).is_none());
+		assert!(queue.batches[&batch(5).batch.timestamp].is_empty());
+	}
+
+	#[test]
+	fn test_check_l2_head() {
+		let queue = BatchQueue::new(GOERLI_CONFIG);
+		assert!(queue.check_l2_head(&genesis()).is_ok());
+		let mut head = genesis();
+		head.time += 5 * GOERLI_CONFIG.l2_block_time;
+		assert!(queue.check_l2_head(&head).is_ok());
+		head.time += 1;
+		assert_eq!(
+			queue.check_l2_head(&head),
+			Err(DerivationError::MisalignedL2Head { time: head.time })
+		);
+		head.time = GOERLI_CONFIG.l2_genesis_time - GOERLI_CONFIG.l2_block_time;
+		assert!(queue.check_l2_head(&head).is_err());
+	}
+
+	#[test]
+	fn test_timestamp_overflow() {
+		let mut queue = BatchQueue::new(GOERLI_CONFIG);
+		let head = L2BlockRef {
+			time: u64::MAX - 1,
+			..genesis()
+		};
+		assert_eq!(
+			queue.check_l2_head(&head),
+			Err(DerivationError::TimestampOverflow { time: head.time })
+		);
+		assert_eq!(queue.would_accept(&batch_at(0, u64::MAX), &head), AcceptDecision::Past);
+		queue.load_batches(std::iter::once(batch_at(0, u64::MAX)), block(0));
+		assert!(queue.get_block_candidate(head).is_none());
+	}
 }
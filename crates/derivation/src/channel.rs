@@ -1,8 +1,20 @@
-use crate::frame::Frame;
+use crate::error::ChannelError;
+use crate::frame::{Frame, FrameView};
 use core::prelude::*;
 use std::cmp::max;
 use std::collections::HashMap;
 
+/// ChannelStatus is the state of a channel as seen from an L1 block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelStatus {
+	/// Frames are still missing.
+	Incomplete,
+	/// All frames up to the closing frame are present.
+	Ready,
+	/// The channel timed out. Its data must be discarded even if it is complete.
+	TimedOut,
+}
+
 #[derive(Debug)]
 pub struct Channel {
 	frames: HashMap<u16, Frame>,
@@ -27,7 +39,8 @@ impl Channel {
 		}
 	}
 
-	pub fn add_frame(&mut self, frame: Frame, l1_block: BlockID) {
+	/// add_frame adds the frame to the channel. Only the data of frames that are accepted is copied.
+	pub fn add_frame(&mut self, frame: FrameView<'_>, l1_block: BlockID) {
 		// These checks are specififed & cannot be changed without a HF
 		if self.id != frame.id
 			|| self.closed() && frame.is_last
@@ -40,7 +53,7 @@ impl Channel {
 		if frame.is_last {
 			self.end_frame = Some(frame.number);
 			// Prune higher frames if this is the closing frame
-			if frame.number > self.highest_frame {
+			if frame.number < self.highest_frame {
 				self.frames.drain_filter(|k, _| *k > frame.number).for_each(|(_, v)| {
 					self.size -= v.size();
 				});
@@ -51,7 +64,7 @@ impl Channel {
 		self.highest_frame = max(self.highest_frame, frame.number);
 		self.highest_l1_block = max(self.highest_l1_block, l1_block);
 		self.size += frame.size();
-		self.frames.insert(frame.number, frame);
+		self.frames.insert(frame.number, frame.to_frame());
 	}
 
 	pub fn is_ready(&self) -> bool {
@@ -62,22 +75,195 @@ impl Channel {
 		(0..=last).map(|i| self.frames.contains_key(&i)).all(|a| a)
 	}
 
-	/// data returns the channel data. It will panic if `is_ready` is false.
-	/// This fully consumes the channel.
-	pub fn data(mut self) -> impl Iterator<Item = u8> {
-		(0..=self.end_frame.unwrap()).flat_map(move |i| self.frames.remove(&i).unwrap().data)
+	/// try_data assembles the channel data. It errors instead of panicking if the channel is not
+	/// ready or its frames are not exactly the frames `0..=end` of this channel in their positions.
+	/// The frames are only taken out of the channel on success.
+	pub fn try_data(&mut self) -> Result<Vec<u8>, ChannelError> {
+		let last = self.end_frame.ok_or(ChannelError::NotClosed)?;
+		for position in 0..=last {
+			let frame = self.frames.get(&position).ok_or(ChannelError::MissingFrame(position))?;
+			if frame.number != position || frame.id != self.id {
+				return Err(ChannelError::MisplacedFrame(position));
+			}
+		}
+		if self.frames.len() != last as usize + 1 {
+			return Err(ChannelError::FrameAfterEnd);
+		}
+		let data = (0..=last)
+			.flat_map(|i| self.frames.remove(&i).expect("Should have checked the frame").data)
+			.collect();
+		self.size = 0;
+		Ok(data)
 	}
 
-	fn closed(&self) -> bool {
+	pub fn closed(&self) -> bool {
 		self.end_frame.is_some()
 	}
 
+	/// is_timed_out returns true if the channel timed out at the highest L1 block it saw a frame in.
+	/// It uses the same boundary as is_timed_out_at: a channel is still live at exactly `timeout` blocks.
 	pub fn is_timed_out(&self, timeout: u64) -> bool {
-		// TODO: > or >= here?
-		self.highest_l1_block.number - self.lowest_l1_block.number > timeout
+		self.is_timed_out_at(self.highest_l1_block, timeout)
+	}
+
+	/// status returns the state of the channel at `current_l1`. A timeout takes precedence over readiness.
+	pub fn status(&self, current_l1: BlockID, timeout: u64) -> ChannelStatus {
+		if self.is_timed_out_at(current_l1, timeout) {
+			ChannelStatus::TimedOut
+		} else if self.is_ready() {
+			ChannelStatus::Ready
+		} else {
+			ChannelStatus::Incomplete
+		}
+	}
+
+	/// is_timed_out_at returns true if a frame from the given L1 block would arrive after the channel timed out.
+	pub fn is_timed_out_at(&self, l1_block: BlockID, timeout: u64) -> bool {
+		self.blocks_until_timeout(l1_block, timeout).is_none()
+	}
+
+	/// blocks_until_timeout returns how many L1 blocks after `current_l1` can still add frames before the
+	/// channel times out. It is None if the channel already timed out at `current_l1`.
+	pub fn blocks_until_timeout(&self, current_l1: BlockID, timeout: u64) -> Option<u64> {
+		timeout.checked_sub(self.age(current_l1))
 	}
 
 	pub fn size(&self) -> u64 {
 		self.size
 	}
+
+	/// age returns the number of L1 blocks since the channel was opened.
+	pub fn age(&self, l1_block: BlockID) -> u64 {
+		l1_block.number.saturating_sub(self.lowest_l1_block.number)
+	}
+
+	pub fn id(&self) -> ChannelID {
+		self.id
+	}
+
+	pub fn frame_count(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn highest_frame(&self) -> u16 {
+		self.highest_frame
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const ID: ChannelID = ChannelID::new([1; 16]);
+
+	fn frame(number: u16, is_last: bool) -> Frame {
+		Frame {
+			id: ID,
+			number,
+			data: vec![number as u8; 10],
+			is_last,
+		}
+	}
+
+	#[test]
+	fn test_lower_last_frame_prunes_higher_frames() {
+		let mut ch = Channel::new(ID, BlockID::default());
+		for n in [0, 2, 3] {
+			ch.add_frame(frame(n, false).view(), BlockID::default());
+		}
+		assert_eq!(ch.highest_frame, 3);
+		ch.add_frame(frame(1, true).view(), BlockID::default());
+		assert_eq!(ch.highest_frame, 1);
+		assert_eq!(ch.end_frame, Some(1));
+		assert_eq!(ch.size(), frame(0, false).size() + frame(1, true).size());
+		assert!(ch.is_ready());
+		assert_eq!(ch.try_data().unwrap(), [vec![0; 10], vec![1; 10]].concat());
+	}
+
+	#[test]
+	fn test_last_frame_with_duplicate_number_is_ignored() {
+		let mut ch = Channel::new(ID, BlockID::default());
+		for n in [0, 1, 2] {
+			ch.add_frame(frame(n, false).view(), BlockID::default());
+		}
+		let size = ch.size();
+		ch.add_frame(frame(1, true).view(), BlockID::default());
+		assert_eq!(ch.end_frame, None);
+		assert_eq!(ch.highest_frame, 2);
+		assert_eq!(ch.size(), size);
+	}
+
+	#[test]
+	fn test_status() {
+		let timeout = 10;
+		let at = |number| BlockID {
+			number,
+			..Default::default()
+		};
+		let mut ch = Channel::new(ID, at(0));
+		ch.add_frame(frame(0, false).view(), at(0));
+		assert_eq!(ch.status(at(1), timeout), ChannelStatus::Incomplete);
+		ch.add_frame(frame(1, true).view(), at(1));
+		assert_eq!(ch.status(at(1), timeout), ChannelStatus::Ready);
+		assert_eq!(ch.status(at(timeout), timeout), ChannelStatus::Ready);
+		assert_eq!(ch.status(at(timeout + 1), timeout), ChannelStatus::TimedOut);
+
+		let open = Channel::new(ID, at(0));
+		assert_eq!(open.status(at(timeout + 1), timeout), ChannelStatus::TimedOut);
+	}
+
+	#[test]
+	fn test_blocks_until_timeout() {
+		let timeout = 10;
+		let at = |number| BlockID {
+			number,
+			..Default::default()
+		};
+		let ch = Channel::new(ID, at(100));
+		assert_eq!(ch.blocks_until_timeout(at(99), timeout), Some(timeout));
+		assert_eq!(ch.blocks_until_timeout(at(100), timeout), Some(timeout));
+		assert_eq!(ch.blocks_until_timeout(at(103), timeout), Some(7));
+		assert_eq!(ch.blocks_until_timeout(at(110), timeout), Some(0));
+		assert!(!ch.is_timed_out_at(at(110), timeout));
+		assert_eq!(ch.blocks_until_timeout(at(111), timeout), None);
+		assert!(ch.is_timed_out_at(at(111), timeout));
+	}
+
+	#[test]
+	fn test_try_data() {
+		let mut ch = Channel::new(ID, BlockID::default());
+		assert_eq!(ch.try_data(), Err(ChannelError::NotClosed));
+		ch.add_frame(frame(0, false).view(), BlockID::default());
+		ch.add_frame(frame(2, true).view(), BlockID::default());
+		assert_eq!(ch.try_data(), Err(ChannelError::MissingFrame(1)));
+		assert_eq!(ch.frame_count(), 2);
+
+		ch.add_frame(frame(1, false).view(), BlockID::default());
+		assert_eq!(ch.try_data(), Ok([vec![0; 10], vec![1; 10], vec![2; 10]].concat()));
+	}
+
+	#[test]
+	fn test_try_data_rejects_inconsistent_frames() {
+		let mut ch = Channel::new(ID, BlockID::default());
+		ch.add_frame(frame(0, false).view(), BlockID::default());
+		ch.add_frame(frame(1, true).view(), BlockID::default());
+		// A frame stored at a position that is not its number.
+		ch.frames.insert(0, frame(1, false));
+		assert_eq!(ch.try_data(), Err(ChannelError::MisplacedFrame(0)));
+
+		ch.frames.insert(
+			0,
+			Frame {
+				id: ChannelID::new([2; 16]),
+				..frame(0, false)
+			},
+		);
+		assert_eq!(ch.try_data(), Err(ChannelError::MisplacedFrame(0)));
+
+		ch.frames.insert(0, frame(0, false));
+		ch.frames.insert(2, frame(2, false));
+		assert_eq!(ch.try_data(), Err(ChannelError::FrameAfterEnd));
+		ch.frames.remove(&2);
+		assert_eq!(ch.try_data(), Ok([vec![0; 10], vec![1; 10]].concat()));
+	}
 }
@@ -1,14 +1,20 @@
-use crate::channel::Channel;
-use crate::frame::Frame;
+use crate::channel::{Channel, ChannelStatus};
+use crate::error::ChannelBankError;
+use crate::frame::FrameView;
 use core::prelude::*;
 
 use core::types::ChannelID;
+use ethers_core::utils::hex;
+use serde::Serialize;
 use std::collections::{HashMap, VecDeque};
 
 const MAX_CHANNEL_BANK_SIZE: u64 = 100_000_000;
 
 #[derive(Debug)]
 /// ChannelBank stores all pending transactions
+/// Channels are read strictly in the order they were created: a ready channel is held back while an
+/// older channel is incomplete. The older channel stops blocking once it times out, as a timed-out
+/// channel is dropped when it reaches the front.
 pub struct ChannelBank {
 	channels_map: HashMap<ChannelID, Channel>,
 	channels_by_creation: VecDeque<ChannelID>,
@@ -26,33 +32,97 @@ impl ChannelBank {
 	/// load_frame adds a frame to the channel bank.
 	/// The caller must maintain the invariant that get_ready_channel is called until there
 	/// are no more ready channels before adding more frames.
-	/// This function will panic (via assert) if this invariant is not maintained.
-	pub fn load_frame(&mut self, frame: Frame, l1_block: BlockID) {
-		assert!(
-			!self.peek().is_some_and(|c| c.is_ready()),
+	/// Debug builds panic (via debug_assert) if this invariant is not maintained. Release builds
+	/// do not load the frame & return [ChannelBankError::ReadyChannelNotPulled] so the caller can
+	/// pull the ready channels & load it again.
+	/// Frames for a channel that has already timed out are dropped so that a reused channel id
+	/// cannot merge new data into an old channel.
+	pub fn load_frame(&mut self, frame: FrameView<'_>, l1_block: BlockID) -> Result<(), ChannelBankError> {
+		let not_pulled = self.peek().is_some_and(|c| c.is_ready());
+		debug_assert!(
+			!not_pulled,
 			"Specs Violation: must pull data before loading more in the channel bank"
 		);
+		if not_pulled {
+			return Err(ChannelBankError::ReadyChannelNotPulled(frame.number));
+		}
 
-		self.channels_map
-			.entry(frame.id)
-			.or_insert_with(|| {
-				self.channels_by_creation.push_back(frame.id);
-				Channel::new(frame.id, l1_block)
-			})
-			.add_frame(frame, l1_block);
-		self.prune();
+		let channel = self.channels_map.entry(frame.id).or_insert_with(|| {
+			self.channels_by_creation.push_back(frame.id);
+			Channel::new(frame.id, l1_block)
+		});
+		if channel.is_timed_out_at(l1_block, self.channel_timeout) {
+			return Ok(());
+		}
+		channel.add_frame(frame, l1_block);
+		self.prune(l1_block);
+		Ok(())
 	}
 
-	/// get_ready_channel returns the first ready channel.
-	pub fn get_ready_channel(&mut self) -> Option<Channel> {
-		// TODO: this should be a while loop. See if there is a test or fuzzing to catch this.
-		if self.peek()?.is_ready() {
-			let ch = self.remove().unwrap();
-			if !ch.is_timed_out(self.channel_timeout) {
-				return Some(ch);
+	/// get_ready_channel returns the first channel if it is ready at `l1_block`.
+	/// Channels that timed out are discarded, even if they are complete, so that a channel which
+	/// never closes does not block the channels behind it past its timeout.
+	pub fn get_ready_channel(&mut self, l1_block: BlockID) -> Option<Channel> {
+		loop {
+			match self.peek()?.status(l1_block, self.channel_timeout) {
+				ChannelStatus::Ready => return self.remove(),
+				ChannelStatus::TimedOut => {
+					self.remove();
+				}
+				ChannelStatus::Incomplete => return None,
 			}
 		}
-		None
+	}
+
+	/// drain_ready pulls the data of every channel that is ready at `l1_block` until the front
+	/// channel is not ready. Channels whose frames cannot be assembled are dropped.
+	pub fn drain_ready(&mut self, l1_block: BlockID) -> Vec<Vec<u8>> {
+		let mut data = Vec::new();
+		while let Some(mut channel) = self.get_ready_channel(l1_block) {
+			data.extend(channel.try_data().ok());
+		}
+		data
+	}
+
+	/// unclosed_channels returns the ids of channels that have not seen their closing frame for more
+	/// than `min_age` L1 blocks at `l1_block`. They point at a batcher that stopped mid channel.
+	pub fn unclosed_channels(&self, l1_block: BlockID, min_age: u64) -> Vec<ChannelID> {
+		self.channel_ids()
+			.into_iter()
+			.filter(|id| self.channels_map.get(id).is_some_and(|c| !c.closed() && c.age(l1_block) > min_age))
+			.collect()
+	}
+
+	/// channel_ids returns the ids of all pending channels in the order they were created.
+	pub fn channel_ids(&self) -> Vec<ChannelID> {
+		self.channels_by_creation.iter().copied().collect()
+	}
+
+	/// snapshot captures the state of every pending channel in creation order for inspection.
+	pub fn snapshot(&self) -> ChannelBankSnapshot {
+		let channels = self
+			.channel_ids()
+			.iter()
+			.filter_map(|id| self.channels_map.get(id))
+			.map(|c| ChannelSnapshot {
+				id: hex::encode(c.id().to_vec()),
+				frame_count: c.frame_count(),
+				highest_frame: c.highest_frame(),
+				size: c.size(),
+				ready: c.is_ready(),
+				timed_out: c.is_timed_out(self.channel_timeout),
+			})
+			.collect();
+		ChannelBankSnapshot {
+			channel_timeout: self.channel_timeout,
+			total_size: self.total_size(),
+			channels,
+		}
+	}
+
+	/// to_json dumps the channel bank state. It is meant for debugging a stuck pipeline.
+	pub fn to_json(&self) -> serde_json::Value {
+		serde_json::to_value(self.snapshot()).expect("Cannot serialize the channel bank snapshot")
 	}
 
 	fn peek(&self) -> Option<&Channel> {
@@ -63,7 +133,12 @@ impl ChannelBank {
 		self.channels_map.remove(&self.channels_by_creation.pop_front()?)
 	}
 
-	fn prune(&mut self) {
+	// prune drops the timed out channels at the front, with the same boundary as get_ready_channel, then
+	// drops the oldest channels until the bank fits in MAX_CHANNEL_BANK_SIZE.
+	fn prune(&mut self, l1_block: BlockID) {
+		while self.peek().is_some_and(|c| c.is_timed_out_at(l1_block, self.channel_timeout)) {
+			self.remove();
+		}
 		while self.total_size() > MAX_CHANNEL_BANK_SIZE {
 			self.remove().expect("Should have removed a channel");
 		}
@@ -74,37 +149,262 @@ impl ChannelBank {
 	}
 }
 
-/// ChannelBankAdapter providers an iterator for outputting ready channels.
-pub struct ChannelBankAdapter<'a, I> {
+/// ChannelBankSnapshot is a serializable view of the channel bank.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelBankSnapshot {
+	pub channel_timeout: u64,
+	pub total_size: u64,
+	pub channels: Vec<ChannelSnapshot>,
+}
+
+/// ChannelSnapshot is a serializable view of a pending channel.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelSnapshot {
+	/// The hex encoded channel id
+	pub id: String,
+	pub frame_count: usize,
+	pub highest_frame: u16,
+	pub size: u64,
+	pub ready: bool,
+	pub timed_out: bool,
+}
+
+/// ChannelBankAdapter providers an iterator for outputting the data of ready channels.
+pub struct ChannelBankAdapter<'a, 'f, I> {
 	inner: I,
 	cb: &'a mut ChannelBank,
 	l1_block: BlockID,
+	ready: VecDeque<Vec<u8>>,
+	// pending is a frame that the channel bank rejected to be loaded after pulling the ready channels.
+	pending: Option<FrameView<'f>>,
 }
 
-impl<'a, I: Iterator<Item = Frame>> Iterator for ChannelBankAdapter<'a, I> {
-	type Item = Channel;
+impl<'a, 'f, I: Iterator<Item = FrameView<'f>>> Iterator for ChannelBankAdapter<'a, 'f, I> {
+	type Item = Vec<u8>;
 
 	fn next(&mut self) -> Option<Self::Item> {
 		loop {
-			if let Some(ch) = self.cb.get_ready_channel() {
-				return Some(ch);
+			if let Some(data) = self.ready.pop_front() {
+				return Some(data);
+			}
+			self.ready.extend(self.cb.drain_ready(self.l1_block));
+			if self.ready.is_empty() {
+				let frame = self.pending.take().or_else(|| self.inner.next())?;
+				if let Err(ChannelBankError::ReadyChannelNotPulled(_)) = self.cb.load_frame(frame, self.l1_block) {
+					self.pending = Some(frame);
+				}
 			}
-			self.cb.load_frame(self.inner.next()?, self.l1_block);
 		}
 	}
 }
 
-impl<'a, I> ChannelBankAdapter<'a, I> {
+impl<'a, 'f, I> ChannelBankAdapter<'a, 'f, I> {
 	pub fn new(iter: I, cb: &'a mut ChannelBank, l1_block: BlockID) -> Self {
-		Self { inner: iter, cb, l1_block }
+		Self {
+			inner: iter,
+			cb,
+			l1_block,
+			ready: VecDeque::new(),
+			pending: None,
+		}
 	}
 }
 
-/// ChannelBankAdapterIteratorExt allows ChannelBankAdapter to be chained onto an iterator of frames.
-pub trait ChannelBankAdapterIteratorExt<'a, I>: Iterator<Item = Frame> + Sized {
-	fn reassemble_channels(self, cb: &'a mut ChannelBank, l1_block: BlockID) -> ChannelBankAdapter<'a, Self> {
+/// ChannelBankAdapterIteratorExt allows ChannelBankAdapter to be chained onto an iterator of frame views.
+pub trait ChannelBankAdapterIteratorExt<'a, 'f>: Iterator<Item = FrameView<'f>> + Sized {
+	fn reassemble_channels(self, cb: &'a mut ChannelBank, l1_block: BlockID) -> ChannelBankAdapter<'a, 'f, Self> {
 		ChannelBankAdapter::new(self, cb, l1_block)
 	}
 }
 
-impl<'a, I: Iterator<Item = Frame>> ChannelBankAdapterIteratorExt<'a, I> for I {}
+impl<'a, 'f, I: Iterator<Item = FrameView<'f>>> ChannelBankAdapterIteratorExt<'a, 'f> for I {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::frame::Frame;
+	use core::chain_config::GOERLI_CONFIG;
+
+	fn frame(id: u8, number: u16, is_last: bool) -> Frame {
+		Frame {
+			id: ChannelID::new([id; 16]),
+			number,
+			data: vec![number as u8],
+			is_last,
+		}
+	}
+
+	fn block(number: u64) -> BlockID {
+		BlockID {
+			number,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_reused_channel_id_after_timeout() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		// The channel is already timed out by the time the closing frame arrives so it is dropped.
+		cb.load_frame(frame(1, 1, true).view(), block(GOERLI_CONFIG.channel_timeout + 1))
+			.unwrap();
+		assert!(cb.get_ready_channel(block(GOERLI_CONFIG.channel_timeout + 1)).is_none());
+	}
+
+	#[test]
+	fn test_channel_frames_within_timeout() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(1, 1, true).view(), block(GOERLI_CONFIG.channel_timeout))
+			.unwrap();
+		let mut ch = cb
+			.get_ready_channel(block(GOERLI_CONFIG.channel_timeout))
+			.expect("channel should be ready");
+		assert_eq!(ch.try_data().unwrap(), vec![0, 1]);
+	}
+
+	#[test]
+	fn test_channel_ids_in_creation_order() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		for id in [3, 1, 2] {
+			cb.load_frame(frame(id, 0, false).view(), block(0)).unwrap();
+		}
+		cb.load_frame(frame(1, 1, false).view(), block(0)).unwrap();
+		let ids = [3, 1, 2].map(|id| ChannelID::new([id; 16]));
+		assert_eq!(cb.channel_ids(), ids);
+	}
+
+	#[test]
+	fn test_to_json() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(1, 2, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(2, 0, true).view(), block(1)).unwrap();
+
+		let json = cb.to_json();
+		assert_eq!(json["channel_timeout"], GOERLI_CONFIG.channel_timeout);
+		assert_eq!(json["total_size"], cb.total_size());
+		let channels = json["channels"].as_array().unwrap();
+		assert_eq!(channels.len(), 2);
+		assert_eq!(channels[0]["id"], "01".repeat(16));
+		assert_eq!(channels[0]["frame_count"], 2);
+		assert_eq!(channels[0]["highest_frame"], 2);
+		assert_eq!(channels[0]["ready"], false);
+		assert_eq!(channels[0]["timed_out"], false);
+		assert_eq!(channels[1]["id"], "02".repeat(16));
+		assert_eq!(channels[1]["frame_count"], 1);
+		assert_eq!(channels[1]["ready"], true);
+	}
+
+	#[test]
+	fn test_unclosed_channel_is_evicted_at_timeout() {
+		let timeout = GOERLI_CONFIG.channel_timeout;
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(1, 1, false).view(), block(1)).unwrap();
+		cb.load_frame(frame(2, 0, true).view(), block(1)).unwrap();
+		// The unclosed channel at the front blocks the ready channel until it times out.
+		assert!(cb.get_ready_channel(block(timeout)).is_none());
+		assert_eq!(cb.unclosed_channels(block(timeout), timeout / 2), [ChannelID::new([1; 16])]);
+		assert!(cb.unclosed_channels(block(timeout), timeout).is_empty());
+
+		let mut ch = cb.get_ready_channel(block(timeout + 1)).expect("channel 2 should be ready");
+		assert_eq!(ch.try_data().unwrap(), vec![0]);
+		assert!(cb.channel_ids().is_empty());
+	}
+
+	#[test]
+	fn test_prune_and_eviction_share_timeout_boundary() {
+		let timeout = GOERLI_CONFIG.channel_timeout;
+		let ch1 = ChannelID::new([1; 16]);
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(1, 1, false).view(), block(timeout)).unwrap();
+		// At exactly `timeout` blocks the channel is kept by prune, by eviction & by the snapshot.
+		cb.load_frame(frame(2, 0, false).view(), block(timeout)).unwrap();
+		assert!(cb.get_ready_channel(block(timeout)).is_none());
+		assert_eq!(cb.channel_ids(), [ch1, ChannelID::new([2; 16])]);
+		assert!(!cb.snapshot().channels[0].timed_out);
+
+		// One block later both paths drop it.
+		let mut evicted = ChannelBank::new(GOERLI_CONFIG);
+		evicted.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		assert!(evicted.get_ready_channel(block(timeout + 1)).is_none());
+		assert!(evicted.channel_ids().is_empty());
+
+		cb.load_frame(frame(3, 0, false).view(), block(timeout + 1)).unwrap();
+		assert_eq!(cb.channel_ids(), [ChannelID::new([2; 16]), ChannelID::new([3; 16])]);
+	}
+
+	#[test]
+	fn test_timed_out_front_is_skipped_even_if_ready() {
+		let timeout = GOERLI_CONFIG.channel_timeout;
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(2, 0, true).view(), block(2)).unwrap();
+		cb.load_frame(frame(3, 0, false).view(), block(3)).unwrap();
+		cb.load_frame(frame(1, 1, true).view(), block(3)).unwrap();
+		// The front channel is complete but timed out, so it is dropped & the next channel is read.
+		let mut ch = cb.get_ready_channel(block(timeout + 1)).expect("channel 2 should be ready");
+		assert_eq!(ch.id(), ChannelID::new([2; 16]));
+		assert_eq!(ch.try_data().unwrap(), vec![0]);
+		// The incomplete channel that has not timed out blocks again.
+		assert!(cb.get_ready_channel(block(timeout + 1)).is_none());
+		assert_eq!(cb.channel_ids(), [ChannelID::new([3; 16])]);
+	}
+
+	#[test]
+	fn test_drain_ready() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(2, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(2, 1, true).view(), block(0)).unwrap();
+		cb.load_frame(frame(1, 1, true).view(), block(0)).unwrap();
+		// Both channels are complete, the second one was held back by the first.
+		assert_eq!(cb.drain_ready(block(0)), [vec![0, 1], vec![0, 1]]);
+		assert!(cb.channel_ids().is_empty());
+
+		cb.load_frame(frame(3, 0, false).view(), block(0)).unwrap();
+		assert!(cb.drain_ready(block(0)).is_empty());
+		assert_eq!(cb.channel_ids(), [ChannelID::new([3; 16])]);
+	}
+
+	#[test]
+	fn test_channels_are_read_in_creation_order() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, false).view(), block(0)).unwrap();
+		cb.load_frame(frame(2, 0, true).view(), block(0)).unwrap();
+		assert!(cb.get_ready_channel(block(0)).is_none(), "the incomplete front channel must block");
+
+		cb.load_frame(frame(1, 1, true).view(), block(1)).unwrap();
+		let mut first = cb.get_ready_channel(block(1)).unwrap();
+		assert_eq!(first.try_data().unwrap(), vec![0, 1]);
+		let mut second = cb.get_ready_channel(block(1)).unwrap();
+		assert_eq!(second.try_data().unwrap(), vec![0]);
+		assert!(cb.get_ready_channel(block(1)).is_none());
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "Specs Violation")]
+	fn test_load_frame_before_pulling_panics_in_debug() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, true).view(), block(0)).unwrap();
+		let _ = cb.load_frame(frame(2, 0, true).view(), block(0));
+	}
+
+	#[test]
+	#[cfg(not(debug_assertions))]
+	fn test_load_frame_before_pulling_hands_back_frame_in_release() {
+		let mut cb = ChannelBank::new(GOERLI_CONFIG);
+		cb.load_frame(frame(1, 0, true).view(), block(0)).unwrap();
+		assert_eq!(
+			cb.load_frame(frame(2, 0, true).view(), block(0)),
+			Err(ChannelBankError::ReadyChannelNotPulled(0))
+		);
+		assert_eq!(cb.channel_ids(), [ChannelID::new([1; 16])]);
+		assert_eq!(cb.drain_ready(block(0)), [vec![0]]);
+		cb.load_frame(frame(2, 0, true).view(), block(0)).unwrap();
+		assert_eq!(cb.drain_ready(block(0)), [vec![0]]);
+	}
+}
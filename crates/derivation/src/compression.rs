@@ -0,0 +1,275 @@
+use crate::error::DecodeError;
+use flate2::{Decompress, FlushDecompress, Status};
+use std::{
+	cell::Cell,
+	io::{BufRead, BufReader, Read},
+	rc::Rc,
+};
+
+// Zlib streams are identified by the compression method in the lower nibble of the first byte.
+const ZLIB_CM8: u8 = 8;
+const ZLIB_CM15: u8 = 15;
+// Brotli channels are prefixed with a version byte that is not part of the brotli stream.
+const CHANNEL_VERSION_BROTLI: u8 = 1;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+// Zlib supports windows of 2^9 to 2^15 bytes. Standard zlib streams use the largest window.
+const ZLIB_MIN_WINDOW_BITS: u8 = 9;
+const ZLIB_MAX_WINDOW_BITS: u8 = 15;
+
+/// The maximum number of decompressed bytes per compressed byte. Channels that expand further
+/// are treated as decompression bombs & rejected before all of their output is produced.
+const MAX_DECOMPRESSION_RATIO: usize = 1000;
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// ChannelVersion is the leading byte of a channel & selects the algorithm that the channel is
+/// compressed with. Zlib channels have no version byte of their own, they are recognized by the
+/// compression method in the zlib header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelVersion {
+	Zlib,
+	Brotli,
+	Unknown(u8),
+}
+
+impl From<u8> for ChannelVersion {
+	fn from(value: u8) -> Self {
+		match value {
+			CHANNEL_VERSION_BROTLI => ChannelVersion::Brotli,
+			b if b & 0x0f == ZLIB_CM8 || b & 0x0f == ZLIB_CM15 => ChannelVersion::Zlib,
+			b => ChannelVersion::Unknown(b),
+		}
+	}
+}
+
+/// ZlibConfig holds the zlib settings of the batcher that compressed the channels.
+/// The default is standard zlib: the largest window & no preset dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZlibConfig {
+	window_bits: u8,
+	dictionary: Option<Vec<u8>>,
+}
+
+impl Default for ZlibConfig {
+	fn default() -> Self {
+		Self {
+			window_bits: ZLIB_MAX_WINDOW_BITS,
+			dictionary: None,
+		}
+	}
+}
+
+impl ZlibConfig {
+	/// new returns the settings for streams with a window of 2^window_bits bytes.
+	/// It returns None if zlib does not support the window size.
+	pub fn new(window_bits: u8) -> Option<Self> {
+		(ZLIB_MIN_WINDOW_BITS..=ZLIB_MAX_WINDOW_BITS).contains(&window_bits).then(|| Self {
+			window_bits,
+			dictionary: None,
+		})
+	}
+
+	/// with_dictionary sets the preset dictionary that is supplied when a stream asks for one.
+	pub fn with_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+		self.dictionary = Some(dictionary);
+		self
+	}
+}
+
+/// decompress reads the leading byte of the channel to pick the decompressor and
+/// returns the fully decompressed channel data. An empty channel decompresses to no data.
+pub fn decompress(r: impl Read) -> Result<Vec<u8>, DecodeError> {
+	decompress_with(r, &ZlibConfig::default())
+}
+
+/// decompress_with is decompress for zlib channels that were compressed with the given settings.
+pub fn decompress_with(mut r: impl Read, zlib: &ZlibConfig) -> Result<Vec<u8>, DecodeError> {
+	let mut first = [0u8; 1];
+	match r.read_exact(&mut first) {
+		Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(Vec::new()),
+		res => res?,
+	}
+	let consumed = Rc::new(Cell::new(first.len()));
+	let r = CountingReader {
+		inner: r,
+		count: consumed.clone(),
+	};
+	match ChannelVersion::from(first[0]) {
+		// The first byte is part of the zlib header so it must be passed through.
+		ChannelVersion::Zlib => read_bounded(ZlibReader::new(BufReader::new((&first[..]).chain(r)), zlib), &consumed),
+		ChannelVersion::Brotli => read_bounded(brotli::Decompressor::new(r, BROTLI_BUFFER_SIZE), &consumed),
+		ChannelVersion::Unknown(b) => Err(DecodeError::UnknownCompression(b)),
+	}
+}
+
+// read_bounded reads the decompressor to the end while checking the output against the
+// number of compressed bytes that have been consumed so far.
+fn read_bounded(mut decoder: impl Read, consumed: &Cell<usize>) -> Result<Vec<u8>, DecodeError> {
+	let mut buffer = Vec::default();
+	let mut chunk = [0u8; READ_CHUNK_SIZE];
+	loop {
+		let n = match decoder.read(&mut chunk) {
+			Ok(0) => return Ok(buffer),
+			Ok(n) => n,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e.into()),
+		};
+		buffer.extend_from_slice(&chunk[..n]);
+		if buffer.len() > consumed.get().saturating_mul(MAX_DECOMPRESSION_RATIO) {
+			return Err(DecodeError::DecompressionRatioExceeded);
+		}
+	}
+}
+
+// ZlibReader inflates a zlib stream & supplies the preset dictionary when the stream asks for it.
+// The read::ZlibDecoder of flate2 cannot do this as it does not expose its decompressor.
+struct ZlibReader<'a, R> {
+	inner: R,
+	decompress: Decompress,
+	dictionary: Option<&'a [u8]>,
+	done: bool,
+}
+
+impl<'a, R: BufRead> ZlibReader<'a, R> {
+	fn new(inner: R, zlib: &'a ZlibConfig) -> Self {
+		Self {
+			inner,
+			decompress: Decompress::new_with_window_bits(true, zlib.window_bits),
+			dictionary: zlib.dictionary.as_deref(),
+			done: false,
+		}
+	}
+}
+
+impl<R: BufRead> Read for ZlibReader<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		while !self.done && !buf.is_empty() {
+			let input = self.inner.fill_buf()?;
+			let eof = input.is_empty();
+			let flush = if eof { FlushDecompress::Finish } else { FlushDecompress::None };
+			let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+			let status = self.decompress.decompress(input, buf, flush);
+			self.inner.consume((self.decompress.total_in() - total_in) as usize);
+			let written = (self.decompress.total_out() - total_out) as usize;
+			match status {
+				Ok(Status::StreamEnd) => self.done = true,
+				Ok(_) if written == 0 && eof => {
+					return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated zlib stream"));
+				}
+				Ok(_) => {}
+				Err(e) => match (e.needs_dictionary(), self.dictionary) {
+					// A wrong dictionary is rejected by zlib as it does not match the id in the stream.
+					(Some(_), Some(dictionary)) => {
+						self.decompress
+							.set_dictionary(dictionary)
+							.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+					}
+					_ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+				},
+			}
+			if written > 0 {
+				return Ok(written);
+			}
+		}
+		Ok(0)
+	}
+}
+
+// CountingReader counts the bytes read from the inner reader.
+struct CountingReader<R> {
+	inner: R,
+	count: Rc<Cell<usize>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		self.count.set(self.count.get() + n);
+		Ok(n)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use flate2::{write::ZlibEncoder, Compress, Compression as ZlibLevel, FlushCompress};
+	use std::io::Write;
+
+	const DATA: &[u8] = b"some batch data that is compressed into a channel";
+
+	#[test]
+	fn test_decompress_zlib() {
+		let mut encoder = ZlibEncoder::new(Vec::new(), ZlibLevel::best());
+		encoder.write_all(DATA).unwrap();
+		let channel = encoder.finish().unwrap();
+		assert_eq!(decompress(&channel[..]).unwrap(), DATA);
+	}
+
+	#[test]
+	fn test_decompress_zlib_with_custom_settings() {
+		let dictionary = b"batch data channel".to_vec();
+		let mut compress = Compress::new_with_window_bits(ZlibLevel::best(), true, 10);
+		compress.set_dictionary(&dictionary).unwrap();
+		let mut channel = Vec::with_capacity(1024);
+		assert_eq!(
+			compress.compress_vec(DATA, &mut channel, FlushCompress::Finish).unwrap(),
+			Status::StreamEnd
+		);
+
+		let zlib = ZlibConfig::new(10).unwrap().with_dictionary(dictionary);
+		assert_eq!(decompress_with(&channel[..], &zlib).unwrap(), DATA);
+		// The stream asks for a dictionary that the default settings do not have.
+		assert!(matches!(decompress(&channel[..]), Err(DecodeError::Decompression(..))));
+		let wrong = ZlibConfig::new(10).unwrap().with_dictionary(b"another dictionary".to_vec());
+		assert!(matches!(decompress_with(&channel[..], &wrong), Err(DecodeError::Decompression(..))));
+		// A window that is smaller than the window of the stream is rejected.
+		let small = ZlibConfig::new(9).unwrap().with_dictionary(b"batch data channel".to_vec());
+		assert!(matches!(decompress_with(&channel[..], &small), Err(DecodeError::Decompression(..))));
+
+		assert_eq!(ZlibConfig::new(8), None);
+		assert_eq!(ZlibConfig::new(16), None);
+	}
+
+	#[test]
+	fn test_decompress_brotli() {
+		let mut channel = vec![CHANNEL_VERSION_BROTLI];
+		{
+			let mut writer = brotli::CompressorWriter::new(&mut channel, BROTLI_BUFFER_SIZE, 11, 22);
+			writer.write_all(DATA).unwrap();
+		}
+		assert_eq!(decompress(&channel[..]).unwrap(), DATA);
+	}
+
+	#[test]
+	fn test_decompress_ratio_exceeded() {
+		// Deflate tops out at roughly 1030:1 on long runs of the same byte.
+		let data = vec![0u8; 10_000_000];
+		let mut encoder = ZlibEncoder::new(Vec::new(), ZlibLevel::best());
+		encoder.write_all(&data).unwrap();
+		let channel = encoder.finish().unwrap();
+		assert!(data.len() > channel.len() * MAX_DECOMPRESSION_RATIO);
+		assert!(matches!(decompress(&channel[..]), Err(DecodeError::DecompressionRatioExceeded)));
+	}
+
+	#[test]
+	fn test_decompress_empty_channel() {
+		assert!(decompress(&[][..]).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_channel_version_from_byte() {
+		assert_eq!(ChannelVersion::from(CHANNEL_VERSION_BROTLI), ChannelVersion::Brotli);
+		for b in [0x78, 0x68, 0x08, 0x0f] {
+			assert_eq!(ChannelVersion::from(b), ChannelVersion::Zlib);
+		}
+		for b in [0x00, 0x02, 0x80, 0xf1] {
+			assert_eq!(ChannelVersion::from(b), ChannelVersion::Unknown(b));
+		}
+	}
+
+	#[test]
+	fn test_decompress_unknown_algorithm() {
+		assert!(matches!(decompress(&[0x02, 0x00][..]), Err(DecodeError::UnknownCompression(0x02))));
+	}
+}
@@ -1,74 +1,1006 @@
-use crate::batch::parse_batches;
+use crate::attributes::user_deposits;
+use crate::batch::{parse_batches, Batch};
 use crate::batch_queue::*;
 use crate::channel_bank::*;
-use crate::frame::parse_frames;
+use crate::compression::{decompress_with, ZlibConfig};
+use crate::error::DerivationError;
+use crate::frame::parse_frame_views;
 use crate::read_adapter::ReadAdpater;
 
 use core::prelude::*;
+use ethers_core::utils::hex;
+use eyre::{bail, Result};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{info, warn};
 
-use flate2::read::ZlibDecoder;
-use std::io::Read;
+/// The number of derived L2 candidates that are kept around to answer queries about recent blocks.
+const MAX_RECENT_CANDIDATES: usize = 128;
+
+/// BatchSource selects where the batcher data of an L1 block is read from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchSource {
+	/// The calldata of batcher transactions to the batch inbox.
+	#[default]
+	Calldata,
+	/// The data of logs that a batch inbox contract emits with the given first topic.
+	InboxEvent { topic: Hash },
+}
 
 #[derive(Debug)]
 pub struct Derivation {
 	channel_bank: ChannelBank,
 	batch_queue: BatchQueue,
 	config: RollupConfig,
+	recent_candidates: VecDeque<L2BlockCandidate>,
+	l2_safe_head: L2BlockRef,
+	last_l1_block: Option<L1BlockRef>,
+	recover_senders: bool,
+	check_batcher_nonces: bool,
+	batch_source: BatchSource,
+	zlib: ZlibConfig,
+	// missing_l1_part is the part of the last L1 block that has not been loaded yet.
+	missing_l1_part: Option<L1Part>,
+	// pending_inbox is the half of the inbox event data of the last L1 block that waits for the other half.
+	pending_inbox: Option<PendingInbox>,
+	max_candidates_per_load: Option<usize>,
+	// pending_candidates are derived but not returned yet. pending_head is the head that the next
+	// candidate builds on, the last pending candidate or the safe head if there is none.
+	pending_candidates: VecDeque<L2BlockCandidate>,
+	pending_head: L2BlockRef,
+	// candidate_budget is the number of candidates that may still be returned before the next L1 block.
+	candidate_budget: usize,
+}
+
+// PendingInbox is what one half of an L1 block contributes to its inbox event data. The events of
+// the receipts are only read once the batcher transactions that emitted them are authenticated.
+#[derive(Debug)]
+enum PendingInbox {
+	// The hashes of the batcher transactions of the block
+	Batchers(HashSet<Hash>),
+	// The receipts of the block
+	Receipts(Vec<Receipt>),
+}
+
+// L1Part is the half of the data of an L1 block that is loaded in one call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum L1Part {
+	Transactions,
+	Receipts,
+}
+
+impl L1Part {
+	fn other(self) -> Self {
+		match self {
+			L1Part::Transactions => L1Part::Receipts,
+			L1Part::Receipts => L1Part::Transactions,
+		}
+	}
 }
 
 impl Derivation {
 	pub fn new(cfg: RollupConfig) -> Self {
+		let genesis = L2BlockRef {
+			hash: Some(cfg.l2_genesis.hash),
+			number: cfg.l2_genesis.number,
+			time: cfg.l2_genesis_time,
+			l1_origin: cfg.l1_genesis,
+			..Default::default()
+		};
 		Self {
 			channel_bank: ChannelBank::new(cfg),
 			batch_queue: BatchQueue::new(cfg),
 			config: cfg,
+			recent_candidates: VecDeque::with_capacity(MAX_RECENT_CANDIDATES),
+			l2_safe_head: genesis,
+			last_l1_block: None,
+			recover_senders: false,
+			check_batcher_nonces: false,
+			batch_source: BatchSource::default(),
+			zlib: ZlibConfig::default(),
+			missing_l1_part: None,
+			pending_inbox: None,
+			max_candidates_per_load: None,
+			pending_candidates: VecDeque::new(),
+			pending_head: genesis,
+			candidate_budget: usize::MAX,
 		}
 	}
-	pub fn load_l1_data(&mut self, l1_block: L1BlockRef, transactions: Vec<Transaction>, _receipts: Vec<Receipt>) {
-		// TODO: update system config from receipts
 
-		let sys_config = self.config.system_config;
+	/// set_sender_recovery selects if the sender of batcher transactions is recovered from the
+	/// signature instead of trusting the `from` field reported by the L1 provider.
+	pub fn set_sender_recovery(&mut self, enabled: bool) {
+		self.recover_senders = enabled;
+	}
+
+	/// set_batcher_nonce_check selects if batcher transactions whose nonce does not increase over the
+	/// batcher transaction before them in the L1 block are dropped. Such transactions point to
+	/// inconsistent data from the L1 provider.
+	pub fn set_batcher_nonce_check(&mut self, enabled: bool) {
+		self.check_batcher_nonces = enabled;
+	}
+
+	/// set_batch_source selects where batcher data is read from. It defaults to transaction calldata.
+	pub fn set_batch_source(&mut self, source: BatchSource) {
+		self.batch_source = source;
+	}
+
+	/// set_zlib_config selects the zlib settings that channels are decompressed with. It defaults to standard zlib.
+	pub fn set_zlib_config(&mut self, zlib: ZlibConfig) {
+		self.zlib = zlib;
+	}
+
+	/// set_max_candidates_per_load caps the number of candidates that next_l2_attributes returns after
+	/// each L1 block, so that an L1 block with batches for many L2 blocks does not hold up the driver.
+	/// The remaining candidates stay buffered & are returned after the next L1 block is loaded, ahead
+	/// of the candidates of that block. It defaults to no limit.
+	pub fn set_max_candidates_per_load(&mut self, max: Option<usize>) {
+		self.max_candidates_per_load = max;
+		self.candidate_budget = max.unwrap_or(usize::MAX);
+	}
+
+	/// load_l1_data feeds the batcher transactions & the deposits of the next L1 block into the pipeline
+	/// & buffers the candidates that they complete. L1 blocks must be loaded in order: skipping a block
+	/// would break the sequencing window, so it is rejected with [DerivationError::L1Gap] & nothing is loaded.
+	pub fn load_l1_data(
+		&mut self,
+		l1_block: L1BlockRef,
+		transactions: Vec<Transaction>,
+		receipts: Vec<Receipt>,
+	) -> Result<(), DerivationError> {
+		self.load_l1_transactions(l1_block, transactions)?;
+		self.load_l1_receipts(l1_block, receipts)?;
+		self.buffer_candidates();
+		Ok(())
+	}
 
-		let batches = transactions
-			.into_iter()
-			.filter(|tx| tx.to == Some(self.config.batch_inbox_address))
-			.filter(|tx| tx.from == sys_config.batcher_address)
-			.flat_map(|tx| parse_frames(&tx.input))
+	// buffer_candidates derives every candidate that the batch queue can build on top of the pending head.
+	fn buffer_candidates(&mut self) {
+		while let Some(candidate) = self.batch_queue.get_block_candidate(self.pending_head) {
+			advance_head(&mut self.pending_head, &candidate);
+			self.pending_candidates.push_back(candidate);
+		}
+	}
+
+	/// load_l1_transactions is the transactions half of load_l1_data. The transactions & receipts of
+	/// an L1 block can be loaded in either order, but both must be loaded before the next L1 block.
+	/// No candidates are derived while the receipts of the block are missing.
+	pub fn load_l1_transactions(&mut self, l1_block: L1BlockRef, mut transactions: Vec<Transaction>) -> Result<(), DerivationError> {
+		self.begin_l1_part(l1_block, L1Part::Transactions)?;
+		// Frames must be read in the order of the transactions in the block. The provider is
+		// not trusted to return them in that order, so sort by index when it is known.
+		let data = match self.batch_source {
+			BatchSource::Calldata => {
+				// Most transactions of a large block do not go to the batch inbox, so they are dropped before sorting.
+				let inbox = Some(self.config.batch_inbox_address);
+				transactions.retain(|tx| tx.to == inbox);
+				transactions.sort_by_key(|tx| tx.transaction_index);
+				transactions.retain(|tx| self.is_batcher_transaction(tx));
+				if self.check_batcher_nonces {
+					retain_increasing_nonces(&l1_block, &mut transactions);
+				}
+				transactions.into_iter().map(|tx| tx.input).collect()
+			}
+			BatchSource::InboxEvent { topic } => {
+				let batchers = transactions
+					.iter()
+					.filter(|tx| self.is_batcher_transaction(tx))
+					.map(|tx| tx.hash)
+					.collect();
+				match self.pending_inbox.take() {
+					Some(PendingInbox::Receipts(receipts)) => self.inbox_event_data(&receipts, topic, &batchers),
+					_ => {
+						self.pending_inbox = Some(PendingInbox::Batchers(batchers));
+						Vec::new()
+					}
+				}
+			}
+		};
+		self.load_batcher_data(l1_block, data);
+		Ok(())
+	}
+
+	/// load_l1_receipts is the receipts half of load_l1_data. It loads the user deposits of the L1 block,
+	/// which are included in the first L2 block of its epoch, & the batcher data of inbox events.
+	pub fn load_l1_receipts(&mut self, l1_block: L1BlockRef, mut receipts: Vec<Receipt>) -> Result<(), DerivationError> {
+		self.begin_l1_part(l1_block, L1Part::Receipts)?;
+		// TODO: update system config from receipts
+		receipts.sort_by_key(|r| r.transaction_index);
+		let deposits = user_deposits(&receipts, l1_block.hash, self.config.deposit_contract_address);
+		self.batch_queue.load_deposits(deposits, l1_block);
+		let data = match self.batch_source {
+			BatchSource::Calldata => Vec::new(),
+			BatchSource::InboxEvent { topic } => match self.pending_inbox.take() {
+				Some(PendingInbox::Batchers(batchers)) => self.inbox_event_data(&receipts, topic, &batchers),
+				_ => {
+					self.pending_inbox = Some(PendingInbox::Receipts(receipts));
+					Vec::new()
+				}
+			},
+		};
+		self.load_batcher_data(l1_block, data);
+		Ok(())
+	}
+
+	// load_batcher_data runs the batcher data of an L1 block through the frame & channel stages into the batch queue.
+	fn load_batcher_data(&mut self, l1_block: L1BlockRef, data: Vec<Vec<u8>>) {
+		let batches = data
+			.iter()
+			.flat_map(|d| parse_frame_views(d))
 			.reassemble_channels(&mut self.channel_bank, l1_block.into())
-			.map(|c| c.data())
-			.map(ReadAdpater::new)
-			.map(decompress)
+			.map(|data| ReadAdpater::new(data.into_iter()))
+			.filter_map(|r| decompress_with(r, &self.zlib).ok())
 			.flat_map(parse_batches);
 		self.batch_queue.load_batches(batches, l1_block);
 	}
 
-	pub fn next_l2_attributes(&mut self, l2_head: L2BlockRef) -> Option<L2BlockCandidate> {
-		self.batch_queue.get_block_candidate(l2_head)
+	// begin_l1_part records that a part of an L1 block is loaded. It is either the missing part of the
+	// last block or the first part of the next block, which requires the last block to be complete.
+	fn begin_l1_part(&mut self, l1_block: L1BlockRef, part: L1Part) -> Result<(), DerivationError> {
+		match (self.missing_l1_part, self.last_l1_block) {
+			(Some(missing), Some(last)) if missing == part && last.hash == l1_block.hash => {
+				self.missing_l1_part = None;
+				Ok(())
+			}
+			(Some(_), Some(last)) => Err(DerivationError::IncompleteL1Block { number: last.number }),
+			_ => {
+				self.advance_l1_block(l1_block)?;
+				self.missing_l1_part = Some(part.other());
+				Ok(())
+			}
+		}
+	}
+
+	// is_batcher_transaction checks that the transaction was sent by the batcher to the batch inbox.
+	// With sender recovery a transaction is rejected if the signature does not recover to the batcher.
+	fn is_batcher_transaction(&self, tx: &Transaction) -> bool {
+		let batcher = self.config.system_config.batcher_address;
+		if tx.to != Some(self.config.batch_inbox_address) || tx.from != batcher {
+			return false;
+		}
+		!self.recover_senders || tx.signature.and_then(|s| s.recover()) == Some(batcher)
+	}
+
+	/// load_batches_direct loads batches into the batch queue without the frame & channel stages.
+	/// It is meant for testing the batch queue in isolation.
+	pub fn load_batches_direct(&mut self, batches: Vec<Batch>, l1_origin: L1BlockRef) -> Result<(), DerivationError> {
+		self.advance_l1_block(l1_origin)?;
+		self.batch_queue.load_batches(batches.into_iter(), l1_origin);
+		Ok(())
+	}
+
+	// advance_l1_block records the L1 block that is loaded next & rejects it if it skips a block.
+	fn advance_l1_block(&mut self, l1_block: L1BlockRef) -> Result<(), DerivationError> {
+		if let Some(last) = self.last_l1_block && l1_block.number != last.number + 1 {
+			return Err(DerivationError::L1Gap {
+				expected: last.number + 1,
+				got: l1_block.number,
+			});
+		}
+		self.last_l1_block = Some(l1_block);
+		self.candidate_budget = self.max_candidates_per_load.unwrap_or(usize::MAX);
+		Ok(())
+	}
+
+	// inbox_event_data returns the data of the logs that the batch inbox emitted with the topic in
+	// the batcher transactions. They are authenticated like batcher transactions with calldata.
+	fn inbox_event_data(&self, receipts: &[Receipt], topic: Hash, batchers: &HashSet<Hash>) -> Vec<Vec<u8>> {
+		let topic = ethers_core::types::H256::from(topic);
+		receipts.iter()
+			.filter(|r| batchers.contains(&Hash::from(r.transaction_hash)))
+			.flat_map(|r| r.logs.iter())
+			.filter(|log| Address::from(log.address) == self.config.batch_inbox_address && log.topics.first() == Some(&topic))
+			.map(|log| log.data.to_vec())
+			.collect()
+	}
+
+	/// next_l2_attributes returns the candidate on top of the current L2 safe head & advances the head to it.
+	/// Buffered candidates are returned first. Once set_max_candidates_per_load allows no more candidates
+	/// for the last L1 block, it returns None until the next L1 block is loaded.
+	pub fn next_l2_attributes(&mut self) -> Option<L2BlockCandidate> {
+		// The candidate of an epoch must not be built before its deposits or batches are loaded.
+		if self.missing_l1_part.is_some() || self.candidate_budget == 0 {
+			return None;
+		}
+		if self.pending_candidates.is_empty() {
+			self.buffer_candidates();
+		}
+		let candidate = self.pending_candidates.pop_front()?;
+		self.candidate_budget -= 1;
+		info!(
+			number = candidate.number,
+			timestamp = candidate.timestamp,
+			transactions = candidate.transactions.len(),
+			sequence_number = candidate.sequence_number,
+			l1_origin = candidate.l1_origin.number,
+			l1_origin_hash = ?candidate.l1_origin.hash,
+			"derived L2 candidate"
+		);
+		advance_head(&mut self.l2_safe_head, &candidate);
+		self.remember_candidate(candidate.clone());
+		Some(candidate)
+	}
+
+	/// l2_safe_head returns the L2 block that the last derived candidate builds.
+	/// It starts at the L2 genesis. The block hash is not known until the candidate is executed.
+	pub fn l2_safe_head(&self) -> L2BlockRef {
+		self.l2_safe_head
+	}
+
+	/// mark_block_derived records the block that the execution engine built from a candidate as the
+	/// new safe head. Derivation continues on top of it & data that it made obsolete is pruned.
+	/// A block whose timestamp is not on the L2 block time grid is rejected.
+	pub fn mark_block_derived(&mut self, block: L2BlockRef) -> Result<(), DerivationError> {
+		self.batch_queue.check_l2_head(&block)?;
+		self.l2_safe_head = block;
+		self.discard_stale_candidates();
+		self.batch_queue.prune(block);
+		Ok(())
+	}
+
+	/// mark_candidate_executed records the hash of the block that the execution engine built from the
+	/// last derived candidate. The next candidate links to it as its parent.
+	pub fn mark_candidate_executed(&mut self, hash: Hash) {
+		self.l2_safe_head.hash = Some(hash);
+		self.discard_stale_candidates();
+	}
+
+	// discard_stale_candidates drops the buffered candidates that do not build on the safe head: those
+	// that it already covers & those that were derived before its hash was known with another parent.
+	fn discard_stale_candidates(&mut self) {
+		let head = self.l2_safe_head;
+		self.pending_candidates.retain(|c| c.number > head.number);
+		let stale = self
+			.pending_candidates
+			.front()
+			.is_some_and(|c| c.number != head.number + 1 || head.hash.is_some_and(|hash| c.parent_hash != hash));
+		if stale {
+			warn!(
+				l2_safe_head = head.number,
+				candidates = self.pending_candidates.len(),
+				"dropping buffered candidates that do not build on the safe head"
+			);
+			self.pending_candidates.clear();
+		}
+		if self.pending_candidates.is_empty() {
+			self.pending_head = head;
+		}
+	}
+
+	/// channel_bank_json dumps the state of the pending channels for debugging a stuck pipeline.
+	pub fn channel_bank_json(&self) -> serde_json::Value {
+		self.channel_bank.to_json()
+	}
+
+	/// unclosed_channels returns the ids of pending channels that did not see their closing frame
+	/// within `min_age` L1 blocks of being opened.
+	pub fn unclosed_channels(&self, min_age: u64) -> Vec<ChannelID> {
+		let l1_block = self.last_l1_block.map_or(self.config.l1_genesis, BlockID::from);
+		self.channel_bank.unclosed_channels(l1_block, min_age)
+	}
+
+	/// recent_candidate returns the most recently derived candidate for the given L2 block number
+	/// if it is still in the recent candidates buffer.
+	pub fn recent_candidate(&self, number: u64) -> Option<&L2BlockCandidate> {
+		self.recent_candidates.iter().rev().find(|c| c.number == number)
+	}
+
+	fn remember_candidate(&mut self, candidate: L2BlockCandidate) {
+		if self.recent_candidates.len() == MAX_RECENT_CANDIDATES {
+			self.recent_candidates.pop_front();
+		}
+		self.recent_candidates.push_back(candidate);
+	}
+
+	/// replay feeds a pre-collected sequence of L1 blocks through the pipeline & returns every
+	/// candidate derived along the way. It reproduces a derivation run without an L1 provider.
+	pub fn replay(
+		&mut self,
+		blocks: Vec<(L1BlockRef, Vec<Transaction>, Vec<Receipt>)>,
+	) -> Result<Vec<L2BlockCandidate>, DerivationError> {
+		let mut candidates = Vec::new();
+		for (l1_block, transactions, receipts) in blocks {
+			self.load_l1_data(l1_block, transactions, receipts)?;
+			while let Some(candidate) = self.next_l2_attributes() {
+				candidates.push(candidate);
+			}
+		}
+		Ok(candidates)
+	}
+
+	/// seek_to loads L1 blocks from the provider until the L2 block `l2_number` is derived so that the
+	/// next candidate builds the block after it. The candidates up to it are dropped as they were already
+	/// executed. L1 blocks are loaded from the block after the last loaded one, or from the L1 genesis.
+	pub fn seek_to(&mut self, l2_number: u64, l1_provider: &mut impl client::Provider) -> Result<()> {
+		if self.l2_safe_head.number > l2_number {
+			bail!("cannot seek back to L2 block {l2_number} from {}", self.l2_safe_head.number);
+		}
+		let mut next_l1 = self.last_l1_block.map_or(self.config.l1_genesis.number, |b| b.number + 1);
+		while self.l2_safe_head.number < l2_number {
+			if self.next_l2_attributes().is_some() {
+				continue;
+			}
+			let header = l1_provider.get_header_by_number(next_l1)?;
+			let transactions = l1_provider.get_transactions_by_root(header.transactions_root.into())?;
+			let receipts = l1_provider.get_receipts(&header)?;
+			self.load_l1_data(header.into(), transactions, receipts)?;
+			next_l1 += 1;
+		}
+		Ok(())
 	}
 
 	pub fn run(&mut self, start_l1_block: u64, end_l1_block: u64, l1_provider: &mut impl client::Provider) {
-		for i in start_l1_block..end_l1_block {
-			let header = l1_provider.get_header_by_number(i).unwrap();
+		self.run_until_shutdown(start_l1_block, end_l1_block, l1_provider, &AtomicBool::new(false));
+	}
+
+	/// run_until_shutdown is run that stops before the next L1 block once `shutdown` is set. The L1
+	/// block that is in flight is always finished. It returns the number of the next L1 block to load.
+	pub fn run_until_shutdown(
+		&mut self,
+		start_l1_block: u64,
+		end_l1_block: u64,
+		l1_provider: &mut impl client::Provider,
+		shutdown: &AtomicBool,
+	) -> u64 {
+		self.run_with_observer(start_l1_block, end_l1_block, l1_provider, shutdown, |_| {})
+	}
+
+	/// run_with_observer is run_until_shutdown that calls `observer` after every L1 block, for example to
+	/// publish the state of the pipeline. After each L1 block it derives at most the candidates that
+	/// set_max_candidates_per_load allows.
+	pub fn run_with_observer(
+		&mut self,
+		start_l1_block: u64,
+		end_l1_block: u64,
+		l1_provider: &mut impl client::Provider,
+		shutdown: &AtomicBool,
+		mut observer: impl FnMut(&Self),
+	) -> u64 {
+		for next in start_l1_block..end_l1_block {
+			if shutdown.load(Ordering::SeqCst) {
+				return next;
+			}
+			let header = l1_provider.get_header_by_number(next).unwrap();
 			let transactions = l1_provider.get_transactions_by_root(header.transactions_root.into()).unwrap();
-			self.load_l1_data(header.into(), transactions, Vec::default());
-			let mut l2_head = L2BlockRef {
-				time: self.config.l2_genesis_time,
+			let receipts = l1_provider.get_receipts(&header).unwrap();
+			self.load_l1_data(header.into(), transactions, receipts).unwrap();
+			while self.next_l2_attributes().is_some() {}
+			observer(self);
+		}
+		end_l1_block
+	}
+
+	/// snapshot captures the position of the pipeline & its pending channels.
+	pub fn snapshot(&self) -> DerivationSnapshot {
+		DerivationSnapshot {
+			l2_safe_head: self.l2_safe_head.number,
+			l2_safe_head_hash: self.l2_safe_head.hash.map(|hash| hex::encode(hash.to_vec())),
+			l2_safe_head_time: self.l2_safe_head.time,
+			last_l1_block: self.last_l1_block.map(|b| b.number),
+			channel_bank: self.channel_bank.snapshot(),
+			batch_queue: self.batch_queue.counts(),
+			pending_candidates: self.pending_candidates.len(),
+		}
+	}
+
+	/// flush writes the snapshot as JSON to `path`. The file is written to `path` with a `.tmp` suffix
+	/// & then moved into place so that an interrupted flush does not leave a partial snapshot behind.
+	pub fn flush(&self, path: impl AsRef<Path>) -> Result<()> {
+		let path = path.as_ref();
+		let tmp = tmp_path(path);
+		std::fs::write(&tmp, serde_json::to_vec_pretty(&self.snapshot())?)?;
+		std::fs::rename(&tmp, path)?;
+		Ok(())
+	}
+}
+
+// advance_head moves the head to the candidate. Its parent is the parent of the candidate, its own
+// hash is unknown until it is executed & the parent hash of the next batch is not checked before.
+fn advance_head(head: &mut L2BlockRef, candidate: &L2BlockCandidate) {
+	head.parent_hash = candidate.parent_hash;
+	head.hash = None;
+	head.number = candidate.number;
+	head.time = candidate.timestamp;
+	head.l1_origin = candidate.l1_origin;
+	head.sequence_number = candidate.sequence_number;
+}
+
+// tmp_path appends a `.tmp` suffix to the file name of `path`, so that no two targets share it.
+fn tmp_path(path: &Path) -> PathBuf {
+	let mut tmp = path.as_os_str().to_owned();
+	tmp.push(".tmp");
+	PathBuf::from(tmp)
+}
+
+// retain_increasing_nonces drops the batcher transactions whose nonce is not above the nonce of the
+// batcher transaction before them & logs a warning for each of them.
+fn retain_increasing_nonces(l1_block: &L1BlockRef, transactions: &mut Vec<Transaction>) {
+	let mut last = None;
+	transactions.retain(|tx| {
+		if last.is_some_and(|nonce| tx.nonce <= nonce) {
+			warn!(
+				l1_block = l1_block.number,
+				nonce = tx.nonce,
+				previous_nonce = last,
+				"dropping batcher transaction with an out of order nonce"
+			);
+			return false;
+		}
+		last = Some(tx.nonce);
+		true
+	});
+}
+
+/// DerivationSnapshot is a serializable view of the pipeline state. It is flushed to disk on shutdown.
+#[derive(Debug, Clone, Serialize)]
+pub struct DerivationSnapshot {
+	pub l2_safe_head: u64,
+	/// The hex encoded hash of the L2 safe head or None while it was not executed yet
+	pub l2_safe_head_hash: Option<String>,
+	pub l2_safe_head_time: u64,
+	/// The number of the last L1 block that was loaded
+	pub last_l1_block: Option<u64>,
+	pub channel_bank: ChannelBankSnapshot,
+	pub batch_queue: BatchQueueCounts,
+	/// The number of candidates that are derived but not returned yet
+	pub pending_candidates: usize,
+}
+
+/// DerivationBuilder configures a Derivation from a rollup config with individual overrides.
+/// Every setting that is not overridden is taken from the rollup config.
+#[derive(Debug, Default)]
+pub struct DerivationBuilder {
+	config: Option<RollupConfig>,
+	channel_timeout: Option<u64>,
+	batcher_address: Option<Address>,
+	seq_window: Option<u64>,
+	l2_block_time: Option<u64>,
+	sender_recovery: bool,
+	batch_source: BatchSource,
+}
+
+impl DerivationBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// config sets the rollup config. It is required.
+	pub fn config(mut self, config: RollupConfig) -> Self {
+		self.config = Some(config);
+		self
+	}
+
+	pub fn channel_timeout(mut self, channel_timeout: u64) -> Self {
+		self.channel_timeout = Some(channel_timeout);
+		self
+	}
+
+	pub fn batcher_address(mut self, batcher_address: Address) -> Self {
+		self.batcher_address = Some(batcher_address);
+		self
+	}
+
+	pub fn seq_window(mut self, seq_window: u64) -> Self {
+		self.seq_window = Some(seq_window);
+		self
+	}
+
+	pub fn l2_block_time(mut self, l2_block_time: u64) -> Self {
+		self.l2_block_time = Some(l2_block_time);
+		self
+	}
+
+	pub fn sender_recovery(mut self, enabled: bool) -> Self {
+		self.sender_recovery = enabled;
+		self
+	}
+
+	pub fn batch_source(mut self, source: BatchSource) -> Self {
+		self.batch_source = source;
+		self
+	}
+
+	/// build applies the overrides to the rollup config & creates the Derivation.
+	/// It errors if no rollup config was provided or the resulting timing params are zero.
+	pub fn build(self) -> Result<Derivation> {
+		let Some(mut cfg) = self.config else {
+			bail!("a rollup config is required to build a derivation pipeline");
+		};
+		cfg.channel_timeout = self.channel_timeout.unwrap_or(cfg.channel_timeout);
+		cfg.system_config.batcher_address = self.batcher_address.unwrap_or(cfg.system_config.batcher_address);
+		cfg.seq_window_size = self.seq_window.unwrap_or(cfg.seq_window_size);
+		cfg.l2_block_time = self.l2_block_time.unwrap_or(cfg.l2_block_time);
+		if cfg.l2_block_time == 0 {
+			bail!("l2 block time must be non-zero");
+		}
+		if cfg.seq_window_size == 0 {
+			bail!("sequencing window must be non-zero");
+		}
+		let mut derivation = Derivation::new(cfg);
+		derivation.set_sender_recovery(self.sender_recovery);
+		derivation.set_batch_source(self.batch_source);
+		Ok(derivation)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use core::{address_literal, chain_config::GOERLI_CONFIG};
+	use ethers_core::{types::Log, utils::rlp};
+	use hex_literal::hex;
+
+	// The signed transaction from the EIP-155 example, sent by the key 0x4646..46.
+	const SIGNED_TX: [u8; 110] = hex!("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+	const SIGNER: Address = address_literal!("9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F");
+	const INBOX: Address = address_literal!("3535353535353535353535353535353535353535");
+
+	fn signed_tx() -> Transaction {
+		rlp::decode::<ethers_core::types::Transaction>(&SIGNED_TX).unwrap().into()
+	}
+
+	fn derivation(batcher_address: Address) -> Derivation {
+		let mut cfg = GOERLI_CONFIG;
+		cfg.batch_inbox_address = INBOX;
+		cfg.system_config.batcher_address = batcher_address;
+		let mut derivation = Derivation::new(cfg);
+		derivation.set_sender_recovery(true);
+		derivation
+	}
+
+	#[test]
+	fn test_sender_recovery_accepts_signed_batcher_tx() {
+		let tx = signed_tx();
+		assert_eq!(tx.signature.and_then(|s| s.recover()), Some(SIGNER));
+		assert!(derivation(SIGNER).is_batcher_transaction(&tx));
+	}
+
+	#[test]
+	fn test_sender_recovery_rejects_forged_from() {
+		let batcher = GOERLI_CONFIG.system_config.batcher_address;
+		let mut tx = signed_tx();
+		tx.from = batcher;
+		let mut derivation = derivation(batcher);
+		assert!(!derivation.is_batcher_transaction(&tx));
+		derivation.set_sender_recovery(false);
+		assert!(derivation.is_batcher_transaction(&tx));
+
+		tx.signature = None;
+		derivation.set_sender_recovery(true);
+		assert!(!derivation.is_batcher_transaction(&tx));
+	}
+
+	#[test]
+	fn test_l1_gap_is_rejected() {
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		let block = |number| L1BlockRef {
+			number,
+			..Default::default()
+		};
+		derivation.load_l1_data(block(10), Vec::new(), Vec::new()).unwrap();
+		derivation.load_l1_data(block(11), Vec::new(), Vec::new()).unwrap();
+		assert_eq!(
+			derivation.load_l1_data(block(13), Vec::new(), Vec::new()),
+			Err(DerivationError::L1Gap { expected: 12, got: 13 })
+		);
+		assert_eq!(derivation.last_l1_block.map(|b| b.number), Some(11));
+		assert_eq!(
+			derivation.load_l1_data(block(11), Vec::new(), Vec::new()),
+			Err(DerivationError::L1Gap { expected: 12, got: 11 })
+		);
+		derivation.load_l1_data(block(12), Vec::new(), Vec::new()).unwrap();
+	}
+
+	#[test]
+	fn test_misaligned_head_is_rejected() {
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		let genesis = derivation.l2_safe_head();
+		let misaligned = L2BlockRef {
+			number: genesis.number + 1,
+			time: genesis.time + GOERLI_CONFIG.l2_block_time + 1,
+			..genesis
+		};
+		assert_eq!(
+			derivation.mark_block_derived(misaligned),
+			Err(DerivationError::MisalignedL2Head { time: misaligned.time })
+		);
+		assert_eq!(derivation.l2_safe_head().number, genesis.number);
+	}
+
+	// frame_tx creates a batcher transaction holding the first frame of a channel that is still open.
+	fn frame_tx(id: u8, index: u64) -> Transaction {
+		let mut input = vec![0];
+		input.extend_from_slice(&[id; 16]);
+		input.extend_from_slice(&0u16.to_be_bytes());
+		input.extend_from_slice(&1u32.to_be_bytes());
+		input.extend_from_slice(&[id, 0]);
+		Transaction {
+			hash: Hash::default(),
+			to: Some(GOERLI_CONFIG.batch_inbox_address),
+			from: GOERLI_CONFIG.system_config.batcher_address,
+			nonce: index,
+			input,
+			signature: None,
+			transaction_index: Some(index),
+		}
+	}
+
+	#[test]
+	fn test_frames_are_read_in_transaction_order() {
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		let transactions = vec![frame_tx(3, 2), frame_tx(1, 0), frame_tx(2, 1)];
+		derivation.load_l1_data(L1BlockRef::default(), transactions, Vec::new()).unwrap();
+		let ids = [1, 2, 3].map(|id| ChannelID::new([id; 16]));
+		assert_eq!(derivation.channel_bank.channel_ids(), ids);
+	}
+
+	#[test]
+	fn test_batcher_nonce_check_drops_out_of_order_nonces() {
+		let with_nonce = |id: u8, index: u64, nonce: u64| Transaction {
+			nonce,
+			..frame_tx(id, index)
+		};
+		let transactions = vec![with_nonce(1, 0, 5), with_nonce(2, 1, 5), with_nonce(3, 2, 4), with_nonce(4, 3, 6)];
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		derivation
+			.load_l1_data(L1BlockRef::default(), transactions.clone(), Vec::new())
+			.unwrap();
+		assert_eq!(derivation.channel_bank.channel_ids().len(), 4);
+
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		derivation.set_batcher_nonce_check(true);
+		derivation.load_l1_data(L1BlockRef::default(), transactions, Vec::new()).unwrap();
+		let ids = [1, 4].map(|id| ChannelID::new([id; 16]));
+		assert_eq!(derivation.channel_bank.channel_ids(), ids);
+	}
+
+	#[test]
+	fn test_flush_after_shutdown() {
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		let block = L1BlockRef {
+			number: 10,
+			..Default::default()
+		};
+		derivation
+			.load_l1_data(block, vec![frame_tx(1, 0), frame_tx(2, 1)], Vec::new())
+			.unwrap();
+
+		let path = std::env::temp_dir().join(format!("derivation-snapshot-{}.json", std::process::id()));
+		derivation.flush(&path).unwrap();
+		let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(!path.with_extension("json.tmp").exists());
+
+		assert_eq!(json["l2_safe_head"], GOERLI_CONFIG.l2_genesis.number);
+		assert_eq!(json["l2_safe_head_hash"], hex::encode(GOERLI_CONFIG.l2_genesis.hash.to_vec()));
+		assert_eq!(json["last_l1_block"], 10);
+		assert_eq!(json["channel_bank"]["channels"].as_array().unwrap().len(), 2);
+		assert_eq!(json["channel_bank"]["channels"][0]["id"], "01".repeat(16));
+		assert_eq!(json["batch_queue"]["l1_blocks"], 1);
+		assert_eq!(json["batch_queue"]["batches"], 0);
+	}
+
+	#[test]
+	fn test_flush_to_tmp_path() {
+		let derivation = Derivation::new(GOERLI_CONFIG);
+		let path = std::env::temp_dir().join(format!("derivation-snapshot-{}.tmp", std::process::id()));
+		// The temporary file of a `.tmp` target is not the target itself.
+		assert_eq!(tmp_path(&path), path.with_extension("tmp.tmp"));
+		assert_ne!(tmp_path(Path::new("snapshot")), tmp_path(Path::new("snapshot.json")));
+
+		derivation.flush(&path).unwrap();
+		let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(!tmp_path(&path).exists());
+		assert_eq!(json["l2_safe_head"], GOERLI_CONFIG.l2_genesis.number);
+	}
+
+	#[test]
+	fn test_frames_from_inbox_events() {
+		let topic = Hash::new([0x42; 32]);
+		let mut derivation = DerivationBuilder::new()
+			.config(GOERLI_CONFIG)
+			.batch_source(BatchSource::InboxEvent { topic })
+			.build()
+			.unwrap();
+		let log = |id: u8, topic: Hash| Log {
+			address: GOERLI_CONFIG.batch_inbox_address.into(),
+			topics: vec![topic.into()],
+			data: frame_tx(id, 0).input.into(),
+			..Default::default()
+		};
+		let tx = |hash: u8, id: u8, index: u64| Transaction {
+			hash: Hash::new([hash; 32]),
+			..frame_tx(id, index)
+		};
+		let other = Transaction {
+			from: INBOX,
+			..tx(0x0c, 7, 2)
+		};
+		let transactions = vec![tx(0x0a, 5, 0), tx(0x0b, 6, 1), other];
+		let receipt = |hash: u8, index: u64, logs: Vec<Log>| Receipt {
+			transaction_hash: Hash::new([hash; 32]).into(),
+			transaction_index: index.into(),
+			logs,
+			..Default::default()
+		};
+		let receipts = vec![
+			receipt(0x0b, 1, vec![log(2, topic), log(3, Hash::default())]),
+			receipt(0x0a, 0, vec![log(1, topic)]),
+			receipt(0x0c, 2, vec![log(4, topic)]),
+		];
+		// Calldata is ignored when reading from inbox events.
+		derivation.load_l1_data(L1BlockRef::default(), transactions, receipts).unwrap();
+		let ids = [1, 2].map(|id| ChannelID::new([id; 16]));
+		assert_eq!(derivation.channel_bank.channel_ids(), ids);
+	}
+
+	#[test]
+	fn test_inbox_events_authenticate_batcher() {
+		let topic = Hash::new([0x42; 32]);
+		let signed = signed_tx();
+		let forged = Transaction {
+			hash: Hash::new([0x0f; 32]),
+			signature: None,
+			..signed_tx()
+		};
+		let receipt = |tx: &Transaction, id: u8| Receipt {
+			transaction_hash: tx.hash.into(),
+			logs: vec![Log {
+				address: INBOX.into(),
+				topics: vec![topic.into()],
+				data: frame_tx(id, 0).input.into(),
 				..Default::default()
-			};
-			while let Some(candidate) = self.next_l2_attributes(l2_head) {
-				println!("{:?}", candidate);
-				l2_head.time = candidate.timestamp;
+			}],
+			..Default::default()
+		};
+		let receipts = vec![receipt(&signed, 1), receipt(&forged, 2)];
+		// The receipts can be loaded before the transactions that authenticate their events.
+		for receipts_first in [false, true] {
+			let mut derivation = derivation(SIGNER);
+			derivation.set_batch_source(BatchSource::InboxEvent { topic });
+			let transactions = vec![signed.clone(), forged.clone()];
+			if receipts_first {
+				derivation.load_l1_receipts(L1BlockRef::default(), receipts.clone()).unwrap();
+				assert!(derivation.channel_bank.channel_ids().is_empty());
+				derivation.load_l1_transactions(L1BlockRef::default(), transactions).unwrap();
+			} else {
+				derivation
+					.load_l1_data(L1BlockRef::default(), transactions, receipts.clone())
+					.unwrap();
 			}
+			assert_eq!(derivation.channel_bank.channel_ids(), [ChannelID::new([1; 16])]);
 		}
 	}
-}
 
-fn decompress(r: impl Read) -> Vec<u8> {
-	let mut decomp = ZlibDecoder::new(r);
-	let mut buffer = Vec::default();
+	// CapturedEvents records the fields of every tracing event as debug strings.
+	#[derive(Clone, Default)]
+	struct CapturedEvents(std::sync::Arc<std::sync::Mutex<Vec<std::collections::HashMap<String, String>>>>);
+
+	struct Fields(std::collections::HashMap<String, String>);
 
-	// TODO: Handle this error
-	// Decompress the passed data with zlib
-	decomp.read_to_end(&mut buffer).unwrap();
-	buffer
+	impl tracing::field::Visit for Fields {
+		fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+			self.0.insert(field.name().to_string(), format!("{value:?}"));
+		}
+	}
+
+	impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedEvents {
+		fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+			let mut fields = Fields(Default::default());
+			event.record(&mut fields);
+			self.0.lock().unwrap().push(fields.0);
+		}
+	}
+
+	#[test]
+	fn test_candidate_event() {
+		use tracing_subscriber::prelude::*;
+
+		let events = CapturedEvents::default();
+		let subscriber = tracing_subscriber::registry().with(events.clone());
+		let origin = L1BlockRef {
+			hash: GOERLI_CONFIG.l1_genesis.hash,
+			number: GOERLI_CONFIG.l1_genesis.number,
+			time: GOERLI_CONFIG.l2_genesis_time,
+			..Default::default()
+		};
+		let batch = Batch {
+			batch: crate::batch::BatchV1 {
+				parent_hash: GOERLI_CONFIG.l2_genesis.hash.into(),
+				epoch_num: origin.number,
+				epoch_hash: origin.hash.into(),
+				timestamp: GOERLI_CONFIG.l2_genesis_time + GOERLI_CONFIG.l2_block_time,
+				transactions: Vec::new(),
+			},
+		};
+		tracing::subscriber::with_default(subscriber, || {
+			let mut derivation = Derivation::new(GOERLI_CONFIG);
+			derivation.load_batches_direct(vec![batch], origin).unwrap();
+			derivation.next_l2_attributes().unwrap();
+		});
+
+		let events = events.0.lock().unwrap();
+		assert_eq!(events.len(), 1);
+		let event = &events[0];
+		assert_eq!(event["message"], "derived L2 candidate");
+		assert_eq!(event["number"], (GOERLI_CONFIG.l2_genesis.number + 1).to_string());
+		assert_eq!(
+			event["timestamp"],
+			(GOERLI_CONFIG.l2_genesis_time + GOERLI_CONFIG.l2_block_time).to_string()
+		);
+		assert_eq!(event["transactions"], "1");
+		assert_eq!(event["sequence_number"], "1");
+		assert_eq!(event["l1_origin"], origin.number.to_string());
+		assert_eq!(event["l1_origin_hash"], format!("{:?}", origin.hash));
+	}
+
+	fn candidate(number: u64) -> L2BlockCandidate {
+		L2BlockCandidate {
+			number,
+			timestamp: GOERLI_CONFIG.l2_genesis_time + number * GOERLI_CONFIG.l2_block_time,
+			transactions: Vec::new(),
+			l1_origin: BlockID::default(),
+			sequence_number: 0,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_builder_defaults_from_config() {
+		let derivation = DerivationBuilder::new()
+			.config(GOERLI_CONFIG)
+			.channel_timeout(10)
+			.seq_window(20)
+			.build()
+			.unwrap();
+		assert_eq!(derivation.config.channel_timeout, 10);
+		assert_eq!(derivation.config.seq_window_size, 20);
+		assert_eq!(derivation.config.l2_block_time, GOERLI_CONFIG.l2_block_time);
+		assert_eq!(
+			derivation.config.system_config.batcher_address,
+			GOERLI_CONFIG.system_config.batcher_address
+		);
+		assert!(!derivation.recover_senders);
+	}
+
+	#[test]
+	fn test_builder_validation() {
+		assert!(DerivationBuilder::new().channel_timeout(10).build().is_err());
+		assert!(DerivationBuilder::new().config(GOERLI_CONFIG).l2_block_time(0).build().is_err());
+		let derivation = DerivationBuilder::new()
+			.config(GOERLI_CONFIG)
+			.batcher_address(INBOX)
+			.sender_recovery(true)
+			.build()
+			.unwrap();
+		assert_eq!(derivation.config.system_config.batcher_address, INBOX);
+		assert!(derivation.recover_senders);
+	}
+
+	#[test]
+	fn test_recent_candidates() {
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		for i in 0..10 {
+			derivation.remember_candidate(candidate(i));
+		}
+		assert_eq!(derivation.recent_candidate(3).map(|c| c.number), Some(3));
+		assert_eq!(derivation.recent_candidate(9).map(|c| c.timestamp), Some(candidate(9).timestamp));
+		assert!(derivation.recent_candidate(10).is_none());
+	}
+
+	#[test]
+	fn test_recent_candidates_eviction() {
+		let mut derivation = Derivation::new(GOERLI_CONFIG);
+		let total = MAX_RECENT_CANDIDATES as u64 + 2;
+		for i in 0..total {
+			derivation.remember_candidate(candidate(i));
+		}
+		assert!(derivation.recent_candidate(0).is_none());
+		assert!(derivation.recent_candidate(1).is_none());
+		assert!(derivation.recent_candidate(2).is_some());
+		assert!(derivation.recent_candidate(total - 1).is_some());
+	}
 }
@@ -0,0 +1,105 @@
+/// DecodeError is returned when channel data cannot be turned into batches.
+#[derive(Debug)]
+pub enum DecodeError {
+	/// The leading byte of the channel does not name a known compression algorithm.
+	UnknownCompression(u8),
+	/// The decompressor failed on the channel data.
+	Decompression(std::io::Error),
+	/// The channel expands to much more data than an honest batcher would produce.
+	DecompressionRatioExceeded,
+}
+
+impl std::fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DecodeError::UnknownCompression(b) => f.write_fmt(format_args!("unknown compression algorithm byte {b:#04x}")),
+			DecodeError::Decompression(err) => f.write_fmt(format_args!("failed to decompress channel: {err}")),
+			DecodeError::DecompressionRatioExceeded => f.write_str("channel exceeds the maximum decompression ratio"),
+		}
+	}
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<std::io::Error> for DecodeError {
+	fn from(value: std::io::Error) -> Self {
+		DecodeError::Decompression(value)
+	}
+}
+
+/// DerivationError is returned when L1 data cannot be fed into the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivationError {
+	/// The L1 block does not directly follow the last L1 block that was loaded.
+	L1Gap { expected: u64, got: u64 },
+	/// The timestamp of the L2 head is not on the block time grid that starts at the L2 genesis.
+	MisalignedL2Head { time: u64 },
+	/// The timestamp of the block after the L2 head does not fit in a u64.
+	TimestampOverflow { time: u64 },
+	/// Only the transactions or the receipts of the last L1 block are loaded.
+	IncompleteL1Block { number: u64 },
+}
+
+impl std::fmt::Display for DerivationError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DerivationError::L1Gap { expected, got } => f.write_fmt(format_args!("expected L1 block {expected}, got {got}")),
+			DerivationError::MisalignedL2Head { time } => {
+				f.write_fmt(format_args!("L2 head timestamp {time} is not aligned to the L2 block time"))
+			}
+			DerivationError::TimestampOverflow { time } => {
+				f.write_fmt(format_args!("timestamp after L2 head timestamp {time} overflows"))
+			}
+			DerivationError::IncompleteL1Block { number } => {
+				f.write_fmt(format_args!("L1 block {number} is missing its transactions or receipts"))
+			}
+		}
+	}
+}
+
+impl std::error::Error for DerivationError {}
+
+/// ChannelError is returned when the data of a channel cannot be assembled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError {
+	/// The closing frame of the channel has not been seen.
+	NotClosed,
+	/// A frame before the closing frame is missing.
+	MissingFrame(u16),
+	/// The frame stored at the position belongs to another position or channel.
+	MisplacedFrame(u16),
+	/// A frame past the closing frame is stored.
+	FrameAfterEnd,
+}
+
+impl std::fmt::Display for ChannelError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ChannelError::NotClosed => f.write_str("channel is not closed"),
+			ChannelError::MissingFrame(n) => f.write_fmt(format_args!("channel is missing frame {n}")),
+			ChannelError::MisplacedFrame(n) => f.write_fmt(format_args!("channel frame {n} is misplaced")),
+			ChannelError::FrameAfterEnd => f.write_str("channel has frames past its closing frame"),
+		}
+	}
+}
+
+impl std::error::Error for ChannelError {}
+
+/// ChannelBankError is returned when a frame cannot be loaded into the channel bank.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChannelBankError {
+	/// The front channel is ready & has to be pulled first. The frame with the number is not loaded.
+	ReadyChannelNotPulled(u16),
+}
+
+impl std::fmt::Display for ChannelBankError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ChannelBankError::ReadyChannelNotPulled(number) => {
+				f.write_fmt(format_args!("frame {number} was loaded before pulling the ready channel"))
+			}
+		}
+	}
+}
+
+impl std::error::Error for ChannelBankError {}
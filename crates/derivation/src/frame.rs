@@ -2,12 +2,32 @@ use core::types::ChannelID;
 use nom::{
 	branch::alt,
 	bytes::complete::{tag, take},
-	combinator::{map, map_res},
+	combinator::{all_consuming, map, map_res},
 	multi::many0,
-	number::complete::{be_u16, be_u32},
+	number::complete::{be_u16, be_u32, u8},
 	IResult,
 };
 
+/// DERIVATION_VERSION_0 is the version byte of batcher transaction data that holds a list of frames.
+pub const DERIVATION_VERSION_0: u8 = 0;
+
+/// FrameVersion is the version byte that prefixes the frames in batcher transaction data.
+/// Versions that this pipeline does not know are kept as Unknown so they can be skipped explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameVersion {
+	V0,
+	Unknown(u8),
+}
+
+impl From<u8> for FrameVersion {
+	fn from(value: u8) -> Self {
+		match value {
+			DERIVATION_VERSION_0 => FrameVersion::V0,
+			b => FrameVersion::Unknown(b),
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct Frame {
 	pub id: ChannelID,
@@ -20,34 +40,73 @@ impl Frame {
 	pub fn size(&self) -> u64 {
 		self.data.len() as u64 + 200
 	}
+
+	/// view borrows the frame as a FrameView.
+	pub fn view(&self) -> FrameView<'_> {
+		FrameView {
+			id: self.id,
+			number: self.number,
+			data: &self.data,
+			is_last: self.is_last,
+		}
+	}
+}
+
+/// FrameView is a frame that borrows its data from the transaction input.
+/// It is turned into an owned Frame once the frame is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameView<'a> {
+	pub id: ChannelID,
+	pub number: u16,
+	pub data: &'a [u8],
+	pub is_last: bool,
+}
+
+impl<'a> FrameView<'a> {
+	/// size is the size of the frame once it is turned into a Frame.
+	pub fn size(&self) -> u64 {
+		self.data.len() as u64 + 200
+	}
+
+	pub fn to_frame(&self) -> Frame {
+		Frame {
+			id: self.id,
+			number: self.number,
+			data: self.data.to_vec(),
+			is_last: self.is_last,
+		}
+	}
 }
 
 pub fn parse_frames(tx_data: &[u8]) -> Vec<Frame> {
+	parse_frame_views(tx_data).iter().map(FrameView::to_frame).collect()
+}
+
+/// parse_frame_views parses the frames in the transaction data without copying the frame data.
+pub fn parse_frame_views(tx_data: &[u8]) -> Vec<FrameView<'_>> {
 	parse_frames_nom(tx_data).map(|(_, frames)| frames).unwrap_or_default()
 }
 
-fn parse_frames_nom(i: &[u8]) -> IResult<&[u8], Vec<Frame>> {
-	let (i, _) = tag([0])(i)?;
-	let (i, frames) = many0(parse_frame)(i)?;
-	Ok((i, frames))
+// parse_frames_nom dispatches on the version byte of the transaction data. Data with a version that
+// is not known yet carries no frames for this pipeline & is skipped. The frames must span the whole
+// data: if one frame is malformed, e.g. its data is shorter than its declared length, all frames of
+// the transaction are rejected.
+fn parse_frames_nom(i: &[u8]) -> IResult<&[u8], Vec<FrameView<'_>>> {
+	let (i, version) = map(u8, FrameVersion::from)(i)?;
+	match version {
+		FrameVersion::V0 => all_consuming(many0(parse_frame))(i),
+		FrameVersion::Unknown(_) => Ok((i, Vec::new())),
+	}
 }
 
-fn parse_frame(i: &[u8]) -> IResult<&[u8], Frame> {
-	let (i, id) = map_res(take(4usize), ChannelID::try_from)(i)?;
+fn parse_frame(i: &[u8]) -> IResult<&[u8], FrameView<'_>> {
+	let (i, id) = map_res(take(16usize), ChannelID::try_from)(i)?;
 	let (i, number) = be_u16(i)?;
 	let (i, data_len) = be_u32(i)?;
 	// TODO: Validate data_len against MAX_DATA_LEN
 	let (i, data) = take(data_len as usize)(i)?;
 	let (i, is_last) = parse_bool(i)?;
-	Ok((
-		i,
-		Frame {
-			id,
-			number,
-			data: data.to_vec(),
-			is_last,
-		},
-	))
+	Ok((i, FrameView { id, number, data, is_last }))
 }
 
 fn parse_bool(i: &[u8]) -> IResult<&[u8], bool> {
@@ -58,6 +117,89 @@ fn parse_bool(i: &[u8]) -> IResult<&[u8], bool> {
 mod tests {
 	use super::*;
 
+	#[test]
+	fn test_parse_frame_reads_full_channel_id() {
+		let id: [u8; 16] = std::array::from_fn(|i| i as u8);
+		let mut tx_data = vec![0];
+		tx_data.extend_from_slice(&id);
+		tx_data.extend_from_slice(&3u16.to_be_bytes());
+		tx_data.extend_from_slice(&1u32.to_be_bytes());
+		tx_data.extend_from_slice(&[0xaa, 1]);
+		let frames = parse_frames(&tx_data);
+		assert_eq!(frames.len(), 1);
+		assert_eq!(frames[0].id, ChannelID::new(id));
+		assert_eq!(frames[0].number, 3);
+		assert_eq!(frames[0].data, [0xaa]);
+	}
+
+	#[test]
+	fn test_frame_views_match_frames() {
+		let mut tx_data = vec![0];
+		for (number, data, is_last) in [(0u16, &b"abc"[..], false), (1, &b""[..], false), (2, &b"defg"[..], true)] {
+			tx_data.extend_from_slice(&[7; 16]);
+			tx_data.extend_from_slice(&number.to_be_bytes());
+			tx_data.extend_from_slice(&(data.len() as u32).to_be_bytes());
+			tx_data.extend_from_slice(data);
+			tx_data.push(is_last as u8);
+		}
+		let views = parse_frame_views(&tx_data);
+		let frames = parse_frames(&tx_data);
+		assert_eq!(views.len(), 3);
+		assert_eq!(frames.len(), 3);
+		for (view, frame) in views.iter().zip(frames.iter()) {
+			assert_eq!(view.id, frame.id);
+			assert_eq!(view.number, frame.number);
+			assert_eq!(view.data, &frame.data[..]);
+			assert_eq!(view.is_last, frame.is_last);
+			// The view points into the transaction data rather than a copy.
+			assert!(tx_data.as_ptr_range().contains(&view.data.as_ptr()) || view.data.is_empty());
+		}
+	}
+
+	#[test]
+	fn test_frame_version_from_byte() {
+		assert_eq!(FrameVersion::from(DERIVATION_VERSION_0), FrameVersion::V0);
+		for b in [1, 0x80, 0xff] {
+			assert_eq!(FrameVersion::from(b), FrameVersion::Unknown(b));
+		}
+	}
+
+	#[test]
+	fn test_unknown_version_is_skipped() {
+		let mut frame = vec![7; 16];
+		frame.extend_from_slice(&0u16.to_be_bytes());
+		frame.extend_from_slice(&1u32.to_be_bytes());
+		frame.extend_from_slice(&[0xaa, 1]);
+
+		let known = [&[DERIVATION_VERSION_0][..], &frame].concat();
+		assert_eq!(parse_frame_views(&known).len(), 1);
+		for version in [1, 0x80, 0xff] {
+			let unknown = [&[version][..], &frame].concat();
+			assert!(parse_frame_views(&unknown).is_empty());
+		}
+		assert!(parse_frame_views(&[]).is_empty());
+	}
+
+	#[test]
+	fn test_frame_with_truncated_data_is_rejected() {
+		let frame = |declared_len: u32, data: &[u8]| {
+			let mut frame = vec![7; 16];
+			frame.extend_from_slice(&0u16.to_be_bytes());
+			frame.extend_from_slice(&declared_len.to_be_bytes());
+			frame.extend_from_slice(data);
+			frame.push(1);
+			frame
+		};
+		let valid = frame(3, b"abc");
+		assert_eq!(parse_frame_views(&[&[DERIVATION_VERSION_0][..], &valid].concat()).len(), 1);
+
+		// A frame whose data does not match its declared length is rejected along with the frames before it.
+		for malformed in [frame(5, b"abc"), frame(2, b"abc")] {
+			assert!(parse_frame_views(&[&[DERIVATION_VERSION_0][..], &malformed].concat()).is_empty());
+			assert!(parse_frame_views(&[&[DERIVATION_VERSION_0][..], &valid, &malformed].concat()).is_empty());
+		}
+	}
+
 	#[test]
 	fn test_parse_bool_true() {
 		assert_eq!(parse_bool(&[1]), Ok((&[][..], true)));
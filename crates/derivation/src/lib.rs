@@ -1,11 +1,17 @@
 #![feature(hash_drain_filter)]
 #![feature(let_chains)]
 
+pub mod batch;
+pub mod batch_queue;
 pub mod derivation;
+pub mod error;
+pub mod frame;
+pub mod transaction;
 
-mod batch;
-mod batch_queue;
+mod attributes;
 mod channel;
 mod channel_bank;
-mod frame;
+mod compression;
 mod read_adapter;
+
+pub use compression::{ChannelVersion, ZlibConfig};
@@ -0,0 +1,152 @@
+use ethers_core::types::{Transaction, H256, U256, U64};
+use ethers_core::utils::{
+	keccak256,
+	rlp::{self, DecoderError, Rlp, RlpStream},
+};
+
+/// The EIP-2718 type of EIP-4844 blob transactions.
+pub const BLOB_TX_TYPE: u8 = 0x03;
+const BLOB_TX_FIELDS: usize = 14;
+
+// The keys that the blob fields are kept under in the other fields of a transaction, as in an RPC response.
+const MAX_FEE_PER_BLOB_GAS: &str = "maxFeePerBlobGas";
+const BLOB_VERSIONED_HASHES: &str = "blobVersionedHashes";
+
+/// decode_transaction decodes a transaction of a batch. Blob transactions are not supported by the
+/// ethers transaction type, so they are decoded here & their blob fields are kept in `other`.
+/// The hash of every transaction is the hash of its encoding in the batch.
+pub fn decode_transaction(raw: &[u8]) -> Result<Transaction, DecoderError> {
+	match raw.split_first() {
+		Some((&BLOB_TX_TYPE, payload)) => decode_blob_transaction(raw, &Rlp::new(payload)),
+		_ => rlp::decode(raw),
+	}
+}
+
+/// encode_transaction is the inverse of decode_transaction. It returns None for a blob transaction
+/// that misses its recipient or its blob fields.
+pub fn encode_transaction(tx: &Transaction) -> Option<Vec<u8>> {
+	if tx.transaction_type != Some(U64::from(BLOB_TX_TYPE)) {
+		return Some(tx.rlp().to_vec());
+	}
+	let max_fee_per_blob_gas: U256 = tx.other.get_deserialized(MAX_FEE_PER_BLOB_GAS)?.ok()?;
+	let blob_versioned_hashes: Vec<H256> = tx.other.get_deserialized(BLOB_VERSIONED_HASHES)?.ok()?;
+	let mut s = RlpStream::new_list(BLOB_TX_FIELDS);
+	s.append(&tx.chain_id.unwrap_or_default());
+	s.append(&tx.nonce);
+	s.append(&tx.max_priority_fee_per_gas.unwrap_or_default());
+	s.append(&tx.max_fee_per_gas.unwrap_or_default());
+	s.append(&tx.gas);
+	s.append(&tx.to?);
+	s.append(&tx.value);
+	s.append(&tx.input.to_vec());
+	s.append(&tx.access_list.clone().unwrap_or_default());
+	s.append(&max_fee_per_blob_gas);
+	s.append_list(&blob_versioned_hashes);
+	s.append(&tx.v);
+	s.append(&tx.r);
+	s.append(&tx.s);
+	Some([&[BLOB_TX_TYPE][..], &s.out()].concat())
+}
+
+// decode_blob_transaction decodes the payload of a blob transaction:
+// rlp([chain_id, nonce, max_priority_fee_per_gas, max_fee_per_gas, gas_limit, to, value, data,
+//      access_list, max_fee_per_blob_gas, blob_versioned_hashes, y_parity, r, s])
+fn decode_blob_transaction(raw: &[u8], rlp: &Rlp) -> Result<Transaction, DecoderError> {
+	if rlp.item_count()? != BLOB_TX_FIELDS {
+		return Err(DecoderError::RlpIncorrectListLen);
+	}
+	let mut tx = Transaction {
+		hash: H256(keccak256(raw)),
+		transaction_type: Some(U64::from(BLOB_TX_TYPE)),
+		chain_id: Some(rlp.val_at(0)?),
+		nonce: rlp.val_at(1)?,
+		max_priority_fee_per_gas: Some(rlp.val_at(2)?),
+		max_fee_per_gas: Some(rlp.val_at(3)?),
+		gas: rlp.val_at(4)?,
+		to: Some(rlp.val_at(5)?),
+		value: rlp.val_at(6)?,
+		input: rlp.val_at::<Vec<u8>>(7)?.into(),
+		access_list: Some(rlp.val_at(8)?),
+		v: rlp.val_at(11)?,
+		r: rlp.val_at(12)?,
+		s: rlp.val_at(13)?,
+		..Default::default()
+	};
+	let max_fee_per_blob_gas: U256 = rlp.val_at(9)?;
+	let blob_versioned_hashes: Vec<H256> = rlp.list_at(10)?;
+	tx.other = serde_json::from_value(serde_json::json!({
+		MAX_FEE_PER_BLOB_GAS: max_fee_per_blob_gas,
+		BLOB_VERSIONED_HASHES: blob_versioned_hashes,
+	}))
+	.map_err(|_| DecoderError::Custom("cannot store the blob fields"))?;
+	Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::batch::{batches_to_channel_bytes, channel_bytes_to_batches, Batch, BatchV1};
+	use ethers_core::types::{transaction::eip2930::*, H160};
+
+	fn blob_tx(access_list: &AccessList, hashes: &[H256]) -> Vec<u8> {
+		let mut s = RlpStream::new_list(BLOB_TX_FIELDS);
+		s.append(&U256::from(420));
+		s.append(&U256::from(7));
+		s.append(&U256::from(1_000_000_000u64));
+		s.append(&U256::from(30_000_000_000u64));
+		s.append(&U256::from(21_000));
+		s.append(&H160::repeat_byte(0x44));
+		s.append(&U256::from(1));
+		s.append(&vec![0xde_u8, 0xad]);
+		s.append(access_list);
+		s.append(&U256::from(3));
+		s.append_list(hashes);
+		s.append(&U64::one());
+		s.append(&U256::from(11));
+		s.append(&U256::from(12));
+		[&[BLOB_TX_TYPE][..], &s.out()].concat()
+	}
+
+	#[test]
+	fn test_blob_transaction_round_trip() {
+		let access_list = AccessList(vec![AccessListItem {
+			address: H160::repeat_byte(0x11),
+			storage_keys: vec![H256::repeat_byte(0x22)],
+		}]);
+		let hashes = [H256::repeat_byte(0x01), H256::repeat_byte(0x02)];
+		let raw = blob_tx(&access_list, &hashes);
+
+		// The transaction is read back out of the channel data of a batch.
+		let batch = Batch {
+			batch: BatchV1 {
+				parent_hash: H256::zero(),
+				epoch_num: 1,
+				epoch_hash: H256::zero(),
+				timestamp: 2,
+				transactions: vec![raw.clone()],
+			},
+		};
+		let batches = channel_bytes_to_batches(&batches_to_channel_bytes(&[batch])).unwrap();
+		let tx = decode_transaction(&batches[0].batch.transactions[0]).unwrap();
+
+		assert_eq!(tx.hash, H256(keccak256(&raw)));
+		assert_eq!(tx.transaction_type, Some(U64::from(BLOB_TX_TYPE)));
+		assert_eq!(tx.nonce, U256::from(7));
+		assert_eq!(tx.to, Some(H160::repeat_byte(0x44)));
+		assert_eq!(tx.access_list, Some(access_list));
+		assert_eq!(
+			tx.other.get_deserialized::<Vec<H256>>(BLOB_VERSIONED_HASHES).unwrap().unwrap(),
+			hashes
+		);
+		assert_eq!(encode_transaction(&tx), Some(raw));
+	}
+
+	#[test]
+	fn test_blob_transaction_field_count() {
+		let mut s = RlpStream::new_list(2);
+		s.append(&1u64);
+		s.append(&2u64);
+		let raw = [&[BLOB_TX_TYPE][..], &s.out()].concat();
+		assert_eq!(decode_transaction(&raw).unwrap_err(), DecoderError::RlpIncorrectListLen);
+	}
+}
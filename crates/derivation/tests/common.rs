@@ -0,0 +1,169 @@
+//! Fixture generation shared between the pipeline tests & benchmarks.
+#![allow(dead_code)]
+
+use core::prelude::*;
+use derivation::derivation::Derivation;
+use ethers_core::{
+	abi::{self, AbiEncode},
+	types::{Log, H160, H256, U256},
+	utils::rlp::{self, RlpStream},
+};
+use flate2::{write::ZlibEncoder, Compression};
+use hex_literal::hex;
+use std::io::Write;
+
+/// The maximum amount of channel data put into a single frame.
+pub const MAX_FRAME_DATA: usize = 1_000;
+
+/// origin returns the L1 block that all fixture batches use as their epoch.
+pub fn origin(cfg: &RollupConfig) -> L1BlockRef {
+	L1BlockRef {
+		hash: cfg.l1_genesis.hash,
+		number: cfg.l1_genesis.number,
+		time: cfg.l2_genesis_time,
+		..Default::default()
+	}
+}
+
+/// parent_hashes returns the parent hashes of `count` consecutive batches on top of the L2 genesis.
+/// Only the genesis hash is known, the blocks after it are not executed.
+pub fn parent_hashes(cfg: &RollupConfig, count: u64) -> Vec<Hash> {
+	let mut parents = vec![Hash::default(); count as usize];
+	if let Some(first) = parents.first_mut() {
+		*first = cfg.l2_genesis.hash;
+	}
+	parents
+}
+
+/// encode_batch encodes an empty batch the same way the batcher does (version byte || rlp(batch)).
+pub fn encode_batch(parent: Hash, epoch: &L1BlockRef, timestamp: u64) -> Vec<u8> {
+	let mut s = RlpStream::new_list(5);
+	s.append(&H256::from(parent));
+	s.append(&epoch.number);
+	s.append(&H256::from(epoch.hash));
+	s.append(&timestamp);
+	s.begin_list(0);
+	let mut out = vec![0];
+	out.extend_from_slice(&s.out());
+	out
+}
+
+/// channel_data creates the compressed channel data for `count` consecutive L2 blocks.
+pub fn channel_data(cfg: &RollupConfig, count: u64) -> Vec<u8> {
+	epoch_channel_data(cfg, &origin(cfg), &parent_hashes(cfg, count))
+}
+
+/// epoch_channel_data is channel_data for batches of the given epoch with the given parent hashes.
+pub fn epoch_channel_data(cfg: &RollupConfig, epoch: &L1BlockRef, parents: &[Hash]) -> Vec<u8> {
+	let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+	for (i, parent) in (1..).zip(parents) {
+		let batch = encode_batch(*parent, epoch, cfg.l2_genesis_time + i * cfg.l2_block_time);
+		encoder.write_all(&rlp::encode(&batch)).unwrap();
+	}
+	encoder.finish().unwrap()
+}
+
+/// batcher_transactions splits the channel data into frames and wraps each frame in a batcher transaction.
+pub fn batcher_transactions(cfg: &RollupConfig, count: u64) -> Vec<Transaction> {
+	epoch_batcher_transactions(cfg, &origin(cfg), count)
+}
+
+/// epoch_batcher_transactions is batcher_transactions for batches of the given epoch.
+pub fn epoch_batcher_transactions(cfg: &RollupConfig, epoch: &L1BlockRef, count: u64) -> Vec<Transaction> {
+	chained_batcher_transactions(cfg, epoch, &parent_hashes(cfg, count))
+}
+
+/// chained_batcher_transactions is epoch_batcher_transactions for one batch per parent hash, for
+/// batches that build on executed blocks.
+pub fn chained_batcher_transactions(cfg: &RollupConfig, epoch: &L1BlockRef, parents: &[Hash]) -> Vec<Transaction> {
+	let data = epoch_channel_data(cfg, epoch, parents);
+	let chunks: Vec<&[u8]> = data.chunks(MAX_FRAME_DATA).collect();
+	chunks.iter()
+		.enumerate()
+		.map(|(i, chunk)| {
+			let mut input = vec![0];
+			input.extend_from_slice(&[0xaa; 16]);
+			input.extend_from_slice(&(i as u16).to_be_bytes());
+			input.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+			input.extend_from_slice(chunk);
+			input.push((i == chunks.len() - 1) as u8);
+			Transaction {
+				hash: Hash::default(),
+				to: Some(cfg.batch_inbox_address),
+				from: cfg.system_config.batcher_address,
+				nonce: i as u64,
+				input,
+				signature: None,
+				transaction_index: Some(i as u64),
+			}
+		})
+		.collect()
+}
+
+/// large_block mixes the batcher transactions into `noise` unrelated transactions, as in a busy L1 block.
+/// Some of the unrelated transactions are sent to the batch inbox by another sender.
+pub fn large_block(cfg: &RollupConfig, count: u64, noise: usize) -> Vec<Transaction> {
+	let batcher = batcher_transactions(cfg, count);
+	let every = noise / batcher.len().max(1) + 1;
+	let mut transactions: Vec<Transaction> = (0..noise)
+		.map(|i| Transaction {
+			hash: Hash::default(),
+			to: Some(if i % 10 == 0 {
+				cfg.batch_inbox_address
+			} else {
+				Address::new([0x11; 20])
+			}),
+			from: Address::new([0x22; 20]),
+			nonce: i as u64,
+			input: vec![0; 100],
+			signature: None,
+			transaction_index: None,
+		})
+		.collect();
+	for (i, tx) in batcher.into_iter().enumerate() {
+		transactions.insert((i * every).min(transactions.len()), tx);
+	}
+	for (i, tx) in transactions.iter_mut().enumerate() {
+		tx.transaction_index = Some(i as u64);
+	}
+	transactions
+}
+
+/// deposit_receipt creates the receipt of a portal transaction that deposits `value` to `to` with the
+/// given calldata. The deposit is emitted by the log at `log_index` in the L1 block.
+pub fn deposit_receipt(cfg: &RollupConfig, from: Address, to: Address, value: u64, data: &[u8], log_index: u64) -> Receipt {
+	let mut opaque = [0u8; 32].to_vec();
+	opaque.extend_from_slice(&U256::from(value).encode());
+	opaque.extend_from_slice(&100_000u64.to_be_bytes());
+	opaque.push(0);
+	opaque.extend_from_slice(data);
+	let log = Log {
+		address: cfg.deposit_contract_address.into(),
+		topics: vec![
+			H256(hex!("b3813568d9991fc951961fcb4c784893574240a28925604d09fc577c55bb7c32")),
+			H160::from(from).into(),
+			H160::from(to).into(),
+			H256::zero(),
+		],
+		data: abi::encode(&[abi::Token::Bytes(opaque)]).into(),
+		log_index: Some(log_index.into()),
+		..Default::default()
+	};
+	Receipt {
+		logs: vec![log],
+		..Default::default()
+	}
+}
+
+/// derive_all runs the transactions through the full pipeline & returns every derived candidate.
+pub fn derive_all(cfg: RollupConfig, transactions: Vec<Transaction>) -> Vec<L2BlockCandidate> {
+	let mut derivation = Derivation::new(cfg);
+	let origin = origin(&cfg);
+	derivation.load_l1_data(origin, transactions, Vec::new()).unwrap();
+
+	let mut candidates = Vec::new();
+	while let Some(candidate) = derivation.next_l2_attributes() {
+		candidates.push(candidate);
+	}
+	candidates
+}
@@ -0,0 +1,477 @@
+use client::Provider;
+use core::chain_config::GOERLI_CONFIG;
+use core::prelude::*;
+use core::types::Header;
+use derivation::batch::{Batch, BatchV1};
+use derivation::derivation::Derivation;
+use derivation::error::DerivationError;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+mod common;
+
+#[test]
+fn test_pipeline_processes_fixture() {
+	let transactions = common::batcher_transactions(&GOERLI_CONFIG, 100);
+	assert!(transactions.len() > 1, "fixture should span multiple frames");
+
+	let candidates = common::derive_all(GOERLI_CONFIG, transactions);
+	assert_eq!(candidates.len(), 100);
+	for (i, candidate) in candidates.iter().enumerate() {
+		let i = i as u64 + 1;
+		assert_eq!(candidate.number, GOERLI_CONFIG.l2_genesis.number + i);
+		assert_eq!(candidate.timestamp, GOERLI_CONFIG.l2_genesis_time + i * GOERLI_CONFIG.l2_block_time);
+		// Only the L1 attributes deposit as the fixture batches are empty.
+		assert_eq!(candidate.transactions.len(), 1);
+	}
+}
+
+#[test]
+fn test_pipeline_skips_unrelated_transactions() {
+	let expected = common::derive_all(GOERLI_CONFIG, common::batcher_transactions(&GOERLI_CONFIG, 100));
+	let candidates = common::derive_all(GOERLI_CONFIG, common::large_block(&GOERLI_CONFIG, 100, 5_000));
+	assert_eq!(candidates.len(), expected.len());
+	for (candidate, expected) in candidates.iter().zip(expected.iter()) {
+		assert_eq!(candidate.number, expected.number);
+		assert_eq!(candidate.timestamp, expected.timestamp);
+		assert_eq!(candidate.l1_origin, expected.l1_origin);
+	}
+}
+
+#[test]
+fn test_pipeline_tracks_safe_head() {
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	assert_eq!(derivation.l2_safe_head().number, GOERLI_CONFIG.l2_genesis.number);
+	derivation
+		.load_l1_data(
+			common::origin(&GOERLI_CONFIG),
+			common::batcher_transactions(&GOERLI_CONFIG, 10),
+			Vec::new(),
+		)
+		.unwrap();
+
+	for _ in 0..10 {
+		let head = derivation.l2_safe_head();
+		let candidate = derivation.next_l2_attributes().unwrap();
+		let next = derivation.l2_safe_head();
+		assert_eq!(next.number, head.number + 1);
+		assert_eq!(next.number, candidate.number);
+		assert_eq!(next.time, head.time + GOERLI_CONFIG.l2_block_time);
+	}
+	assert!(derivation.next_l2_attributes().is_none());
+	assert_eq!(derivation.l2_safe_head().number, GOERLI_CONFIG.l2_genesis.number + 10);
+}
+
+#[test]
+fn test_pipeline_builds_on_marked_block() {
+	let origin = common::origin(&GOERLI_CONFIG);
+	let mut parents = common::parent_hashes(&GOERLI_CONFIG, 10);
+	parents[1] = Hash::new([0x11; 32]);
+	parents[6] = Hash::new([0x22; 32]);
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation
+		.load_l1_data(
+			origin,
+			common::chained_batcher_transactions(&GOERLI_CONFIG, &origin, &parents),
+			Vec::new(),
+		)
+		.unwrap();
+
+	let first = derivation.next_l2_attributes().unwrap();
+	let executed = L2BlockRef {
+		hash: Some(Hash::new([0x11; 32])),
+		number: first.number,
+		time: first.timestamp,
+		l1_origin: first.l1_origin,
+		sequence_number: first.sequence_number,
+		..Default::default()
+	};
+	derivation.mark_block_derived(executed).unwrap();
+	assert_eq!(derivation.l2_safe_head().hash, executed.hash);
+
+	let second = derivation.next_l2_attributes().unwrap();
+	assert_eq!(second.number, executed.number + 1);
+	assert_eq!(second.timestamp, executed.time + GOERLI_CONFIG.l2_block_time);
+	assert_eq!(second.sequence_number, executed.sequence_number + 1);
+	assert_eq!(Some(derivation.l2_safe_head().parent_hash), executed.hash);
+
+	// Marking a later block skips the batches that it already covers.
+	let skipped = L2BlockRef {
+		hash: Some(Hash::new([0x22; 32])),
+		number: executed.number + 5,
+		time: executed.time + 5 * GOERLI_CONFIG.l2_block_time,
+		..executed
+	};
+	derivation.mark_block_derived(skipped).unwrap();
+	let next = derivation.next_l2_attributes().unwrap();
+	assert_eq!(next.number, skipped.number + 1);
+	assert_eq!(next.timestamp, skipped.time + GOERLI_CONFIG.l2_block_time);
+}
+
+#[test]
+fn test_pipeline_links_executed_blocks() {
+	let origin = common::origin(&GOERLI_CONFIG);
+	// Each batch builds on the block that was executed from the batch before it.
+	let parents: Vec<Hash> = std::iter::once(GOERLI_CONFIG.l2_genesis.hash)
+		.chain((1..5u8).map(|i| Hash::new([i; 32])))
+		.collect();
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation
+		.load_l1_data(
+			origin,
+			common::chained_batcher_transactions(&GOERLI_CONFIG, &origin, &parents),
+			Vec::new(),
+		)
+		.unwrap();
+
+	let mut parent = derivation.l2_safe_head();
+	assert_eq!(parent.hash, Some(GOERLI_CONFIG.l2_genesis.hash));
+	for i in 1..=5u8 {
+		let candidate = derivation.next_l2_attributes().unwrap();
+		// The hash of the derived block is unknown until it is executed.
+		assert_eq!(derivation.l2_safe_head().hash, None);
+		derivation.mark_candidate_executed(Hash::new([i; 32]));
+		let head = derivation.l2_safe_head();
+		assert_eq!(head.number, candidate.number);
+		assert_eq!(head.number, parent.number + 1);
+		assert_eq!(Some(head.parent_hash), parent.hash);
+		assert_eq!(head.hash, Some(Hash::new([i; 32])));
+		parent = head;
+	}
+}
+
+#[test]
+fn test_pipeline_replay() {
+	let mut transactions = common::batcher_transactions(&GOERLI_CONFIG, 100);
+	let second = transactions.split_off(transactions.len() / 2);
+	let origin = common::origin(&GOERLI_CONFIG);
+	let next = L1BlockRef {
+		number: origin.number + 1,
+		parent_hash: origin.hash,
+		..Default::default()
+	};
+
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	let candidates = derivation
+		.replay(vec![(origin, transactions, Vec::new()), (next, second, Vec::new())])
+		.unwrap();
+	assert_eq!(candidates.len(), 100);
+	for (i, candidate) in candidates.iter().enumerate() {
+		let i = i as u64 + 1;
+		assert_eq!(candidate.number, GOERLI_CONFIG.l2_genesis.number + i);
+		assert_eq!(candidate.timestamp, GOERLI_CONFIG.l2_genesis_time + i * GOERLI_CONFIG.l2_block_time);
+		assert_eq!(candidate.l1_origin, BlockID::from(origin));
+	}
+	assert_eq!(derivation.l2_safe_head().number, GOERLI_CONFIG.l2_genesis.number + 100);
+}
+
+#[test]
+fn test_pipeline_load_batches_direct() {
+	let origin = common::origin(&GOERLI_CONFIG);
+	// Only the first batch builds on an executed block, the genesis.
+	let batch = |i: u64| Batch {
+		batch: BatchV1 {
+			parent_hash: GOERLI_CONFIG.l2_genesis.hash.into(),
+			epoch_num: origin.number,
+			epoch_hash: origin.hash.into(),
+			timestamp: GOERLI_CONFIG.l2_genesis_time + i * GOERLI_CONFIG.l2_block_time,
+			transactions: Vec::new(),
+		},
+	};
+
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation.load_batches_direct(vec![batch(2), batch(1), batch(3)], origin).unwrap();
+	for i in 1..=3 {
+		let candidate = derivation.next_l2_attributes().unwrap();
+		assert_eq!(candidate.number, GOERLI_CONFIG.l2_genesis.number + i);
+		assert_eq!(candidate.timestamp, batch(i).batch.timestamp);
+	}
+	assert!(derivation.next_l2_attributes().is_none());
+}
+
+// FixtureProvider serves L1 blocks starting at the L1 genesis with the given batcher transactions
+// & receipts. Blocks without receipts in the fixture have none.
+struct FixtureProvider {
+	blocks: Vec<Vec<Transaction>>,
+	receipts: Vec<Vec<Receipt>>,
+	fetched: usize,
+}
+
+impl FixtureProvider {
+	// header returns the header of the L1 block `n`. The fixture batches use the first block as their epoch.
+	fn header(n: u64) -> Header {
+		let mut header = Header {
+			number: n,
+			timestamp: GOERLI_CONFIG.l2_genesis_time,
+			..Default::default()
+		};
+		// The roots only need to identify the block to the fixture.
+		header.transactions_root.0[..8].copy_from_slice(&n.to_be_bytes());
+		header.receipts_root.0[..8].copy_from_slice(&n.to_be_bytes());
+		header
+	}
+
+	// batcher_transactions returns the batcher transactions for `count` L2 blocks in the epoch of the first block.
+	fn batcher_transactions(count: u64) -> Vec<Transaction> {
+		Self::epoch_batcher_transactions(GOERLI_CONFIG.l1_genesis.number, count)
+	}
+
+	// epoch_batcher_transactions is batcher_transactions for the epoch of the L1 block `epoch`.
+	fn epoch_batcher_transactions(epoch: u64, count: u64) -> Vec<Transaction> {
+		common::epoch_batcher_transactions(&GOERLI_CONFIG, &Self::header(epoch).into(), count)
+	}
+
+	// block_offset returns the offset of the block that the root identifies.
+	fn block_offset(&self, root: Hash) -> eyre::Result<usize> {
+		self.offset(u64::from_be_bytes(root.to_vec()[..8].try_into().unwrap()))
+	}
+
+	fn offset(&self, n: u64) -> eyre::Result<usize> {
+		let offset = n.checked_sub(GOERLI_CONFIG.l1_genesis.number).map(|o| o as usize);
+		offset.filter(|&o| o < self.blocks.len())
+			.ok_or_else(|| eyre::eyre!("no L1 block {n}"))
+	}
+}
+
+impl client::Provider for FixtureProvider {
+	fn get_header(&mut self, _hash: Hash) -> eyre::Result<Header> {
+		eyre::bail!("headers are only served by number")
+	}
+
+	fn get_header_by_number(&mut self, n: u64) -> eyre::Result<Header> {
+		self.offset(n)?;
+		self.fetched += 1;
+		Ok(Self::header(n))
+	}
+
+	fn get_receipts_by_root(&self, root: Hash) -> eyre::Result<Vec<Receipt>> {
+		Ok(self.receipts.get(self.block_offset(root)?).cloned().unwrap_or_default())
+	}
+
+	fn get_transactions_by_root(&self, root: Hash) -> eyre::Result<Vec<Transaction>> {
+		Ok(self.blocks[self.block_offset(root)?].clone())
+	}
+}
+
+// ShutdownProvider is a FixtureProvider that sets `shutdown` once the header of the L1 block `at` is fetched.
+struct ShutdownProvider<'a> {
+	inner: FixtureProvider,
+	shutdown: &'a AtomicBool,
+	at: u64,
+}
+
+impl client::Provider for ShutdownProvider<'_> {
+	fn get_header(&mut self, hash: Hash) -> eyre::Result<Header> {
+		self.inner.get_header(hash)
+	}
+
+	fn get_header_by_number(&mut self, n: u64) -> eyre::Result<Header> {
+		if n == self.at {
+			self.shutdown.store(true, Ordering::SeqCst);
+		}
+		self.inner.get_header_by_number(n)
+	}
+
+	fn get_receipts_by_root(&self, root: Hash) -> eyre::Result<Vec<Receipt>> {
+		self.inner.get_receipts_by_root(root)
+	}
+
+	fn get_transactions_by_root(&self, root: Hash) -> eyre::Result<Vec<Transaction>> {
+		self.inner.get_transactions_by_root(root)
+	}
+}
+
+#[test]
+fn test_pipeline_run_until_shutdown() {
+	let mut first = FixtureProvider::batcher_transactions(100);
+	let second = first.split_off(first.len() / 2);
+	let start = GOERLI_CONFIG.l1_genesis.number;
+	let shutdown = AtomicBool::new(false);
+	let mut provider = ShutdownProvider {
+		inner: FixtureProvider {
+			blocks: vec![first, second, Vec::new()],
+			receipts: Vec::new(),
+			fetched: 0,
+		},
+		shutdown: &shutdown,
+		at: start,
+	};
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+
+	// The L1 block that is in flight when the flag is set is finished before the loop stops.
+	let next = derivation.run_until_shutdown(start, start + 3, &mut provider, &shutdown);
+	assert_eq!(next, start + 1);
+	assert_eq!(provider.inner.fetched, 1);
+	let snapshot = derivation.snapshot();
+	assert_eq!(snapshot.last_l1_block, Some(start));
+	assert_eq!(snapshot.l2_safe_head, GOERLI_CONFIG.l2_genesis.number);
+	assert_eq!(snapshot.channel_bank.channels.len(), 1);
+	assert_eq!(snapshot.channel_bank.channels[0].id, "aa".repeat(16));
+
+	// A restart from the returned block completes the channel.
+	shutdown.store(false, Ordering::SeqCst);
+	assert_eq!(derivation.run_until_shutdown(next, start + 3, &mut provider, &shutdown), start + 3);
+	let snapshot = derivation.snapshot();
+	assert_eq!(snapshot.last_l1_block, Some(start + 2));
+	assert_eq!(snapshot.l2_safe_head, GOERLI_CONFIG.l2_genesis.number + 100);
+	assert!(snapshot.channel_bank.channels.is_empty());
+}
+
+#[test]
+fn test_pipeline_seek_to() {
+	let mut first = FixtureProvider::batcher_transactions(100);
+	let second = first.split_off(first.len() / 2);
+	let mut provider = FixtureProvider {
+		blocks: vec![first, second, Vec::new()],
+		receipts: Vec::new(),
+		fetched: 0,
+	};
+
+	let target = GOERLI_CONFIG.l2_genesis.number + 50;
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation.seek_to(target, &mut provider).unwrap();
+	assert_eq!(derivation.l2_safe_head().number, target);
+	// The channel is only complete once the second L1 block is loaded.
+	assert_eq!(provider.fetched, 2);
+
+	let next = derivation.next_l2_attributes().unwrap();
+	assert_eq!(next.number, target + 1);
+	assert_eq!(next.timestamp, GOERLI_CONFIG.l2_genesis_time + 51 * GOERLI_CONFIG.l2_block_time);
+	assert!(derivation.seek_to(target, &mut provider).is_err());
+}
+
+#[test]
+fn test_pipeline_run_loads_receipts() {
+	let start = GOERLI_CONFIG.l1_genesis.number;
+	let (from, to) = (Address::new([0x33; 20]), Address::new([0x44; 20]));
+	let mut provider = FixtureProvider {
+		blocks: vec![Vec::new(), FixtureProvider::epoch_batcher_transactions(start + 1, 2)],
+		receipts: vec![Vec::new(), vec![common::deposit_receipt(&GOERLI_CONFIG, from, to, 5, &[], 0)]],
+		fetched: 0,
+	};
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation.run(start, start + 2, &mut provider);
+
+	// The deposit of the epoch's L1 block is included in its first L2 block.
+	let genesis = GOERLI_CONFIG.l2_genesis.number;
+	let first = derivation.recent_candidate(genesis + 1).unwrap();
+	assert_eq!(first.l1_origin.number, start + 1);
+	assert_eq!(first.transactions.len(), 2);
+	assert_eq!(Address::from(first.transactions[1].from), from);
+	assert_eq!(derivation.recent_candidate(genesis + 2).unwrap().transactions.len(), 1);
+}
+
+#[test]
+fn test_pipeline_caps_candidates_per_load() {
+	let mut provider = FixtureProvider {
+		blocks: vec![FixtureProvider::batcher_transactions(100), Vec::new(), Vec::new()],
+		receipts: Vec::new(),
+		fetched: 0,
+	};
+	let start = GOERLI_CONFIG.l1_genesis.number;
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation.set_max_candidates_per_load(Some(30));
+	let mut rounds = Vec::new();
+	let shutdown = AtomicBool::new(false);
+	derivation.run_with_observer(start, start + 3, &mut provider, &shutdown, |d| {
+		let snapshot = d.snapshot();
+		rounds.push((snapshot.l2_safe_head, snapshot.last_l1_block.unwrap(), snapshot.pending_candidates));
+	});
+
+	// Each L1 block releases at most the cap of the buffered candidates.
+	let genesis = GOERLI_CONFIG.l2_genesis.number;
+	assert_eq!(
+		rounds,
+		vec![
+			(genesis + 30, start, 70),
+			(genesis + 60, start + 1, 40),
+			(genesis + 90, start + 2, 10)
+		]
+	);
+	assert_eq!(provider.fetched, 3);
+	assert!(derivation.next_l2_attributes().is_none());
+	assert_eq!(derivation.snapshot().pending_candidates, 10);
+}
+
+#[test]
+fn test_pipeline_buffers_candidates_beyond_cap() {
+	let origin = common::origin(&GOERLI_CONFIG);
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation.set_max_candidates_per_load(Some(30));
+	derivation
+		.load_l1_data(origin, common::batcher_transactions(&GOERLI_CONFIG, 100), Vec::new())
+		.unwrap();
+
+	let genesis = GOERLI_CONFIG.l2_genesis.number;
+	let mut parent = origin;
+	let mut derived = 0;
+	for expected in [30, 30, 30, 10] {
+		if derived > 0 {
+			// The L1 blocks after the origin carry no batches, the remainder comes from the buffer.
+			let next = L1BlockRef {
+				hash: Hash::new([derived as u8; 32]),
+				number: parent.number + 1,
+				parent_hash: parent.hash,
+				..parent
+			};
+			derivation.load_l1_data(next, Vec::new(), Vec::new()).unwrap();
+			parent = next;
+		}
+		for _ in 0..expected {
+			derived += 1;
+			let candidate = derivation.next_l2_attributes().unwrap();
+			assert_eq!(candidate.number, genesis + derived);
+			assert_eq!(candidate.l1_origin, BlockID::from(origin));
+		}
+		assert!(derivation.next_l2_attributes().is_none());
+		assert_eq!(derivation.snapshot().pending_candidates, 100 - derived as usize);
+	}
+	assert_eq!(derivation.l2_safe_head().number, genesis + 100);
+}
+
+#[test]
+fn test_pipeline_loads_transactions_and_receipts_separately() {
+	let genesis = common::origin(&GOERLI_CONFIG);
+	let next = L1BlockRef {
+		hash: Hash::new([0x01; 32]),
+		number: genesis.number + 1,
+		parent_hash: genesis.hash,
+		time: genesis.time,
+		..Default::default()
+	};
+	let (from, to) = (Address::new([0x33; 20]), Address::new([0x44; 20]));
+	let mut derivation = Derivation::new(GOERLI_CONFIG);
+	derivation.load_l1_data(genesis, Vec::new(), Vec::new()).unwrap();
+
+	derivation
+		.load_l1_transactions(next, common::epoch_batcher_transactions(&GOERLI_CONFIG, &next, 2))
+		.unwrap();
+	assert!(
+		derivation.next_l2_attributes().is_none(),
+		"the deposits of the epoch are still missing"
+	);
+	let after = L1BlockRef {
+		number: next.number + 1,
+		..next
+	};
+	assert_eq!(
+		derivation.load_l1_transactions(after, Vec::new()),
+		Err(DerivationError::IncompleteL1Block { number: next.number })
+	);
+	let receipts = vec![common::deposit_receipt(&GOERLI_CONFIG, from, to, 5, &[0xca, 0xfe], 3)];
+	derivation.load_l1_receipts(next, receipts).unwrap();
+
+	// The L1 attributes are followed by the deposit in the first block of the epoch.
+	let first = derivation.next_l2_attributes().unwrap();
+	assert_eq!(first.sequence_number, 0);
+	assert_eq!(first.transactions.len(), 2);
+	let deposit = &first.transactions[1];
+	assert_eq!(Address::from(deposit.from), from);
+	assert_eq!(deposit.to.map(Address::from), Some(to));
+	assert_eq!(deposit.value, 5.into());
+	assert_eq!(deposit.input.to_vec(), [0xca, 0xfe]);
+
+	let second = derivation.next_l2_attributes().unwrap();
+	assert_eq!(second.sequence_number, 1);
+	assert_eq!(second.transactions.len(), 1);
+	assert!(derivation.next_l2_attributes().is_none());
+}
@@ -0,0 +1,130 @@
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use mpt::MPT;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// CountingAlloc counts the allocations of the benchmark so that the allocations per insert can be reported.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// Allocations is a criterion measurement of the number of allocations instead of the wall time.
+struct Allocations;
+
+impl Measurement for Allocations {
+	type Intermediate = usize;
+	type Value = usize;
+
+	fn start(&self) -> usize {
+		ALLOCATIONS.load(Ordering::Relaxed)
+	}
+
+	fn end(&self, start: usize) -> usize {
+		ALLOCATIONS.load(Ordering::Relaxed) - start
+	}
+
+	fn add(&self, a: &usize, b: &usize) -> usize {
+		a + b
+	}
+
+	fn zero(&self) -> usize {
+		0
+	}
+
+	fn to_f64(&self, value: &usize) -> f64 {
+		*value as f64
+	}
+
+	fn formatter(&self) -> &dyn ValueFormatter {
+		&AllocationFormatter
+	}
+}
+
+// AllocationFormatter reports allocation counts as is & per element when the throughput is set.
+struct AllocationFormatter;
+
+impl ValueFormatter for AllocationFormatter {
+	fn scale_values(&self, _: f64, _: &mut [f64]) -> &'static str {
+		"allocations"
+	}
+
+	fn scale_throughputs(&self, _: f64, throughput: &Throughput, values: &mut [f64]) -> &'static str {
+		match throughput {
+			Throughput::Elements(count) => {
+				values.iter_mut().for_each(|v| *v /= *count as f64);
+				"allocations/insert"
+			}
+			_ => "allocations",
+		}
+	}
+
+	fn scale_for_machines(&self, _: &mut [f64]) -> &'static str {
+		"allocations"
+	}
+}
+
+// keys returns keys that share long prefixes so that inserts go through deep extension nodes.
+fn keys(count: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+	(0..count)
+		.map(|i| {
+			let mut key = vec![0xab; 24];
+			key.extend_from_slice(&i.to_be_bytes());
+			(key, i.to_be_bytes().to_vec())
+		})
+		.collect()
+}
+
+fn insert_all(keys: Vec<(Vec<u8>, Vec<u8>)>) -> MPT {
+	let mut mpt = MPT::default();
+	for (k, v) in keys {
+		mpt.insert(k, v).unwrap();
+	}
+	mpt
+}
+
+fn bench_insert(c: &mut Criterion) {
+	let mut group = c.benchmark_group("mpt");
+	for count in [100, 1_000, 10_000] {
+		let keys = keys(count);
+		group.bench_function(format!("insert/{count}"), |b| {
+			b.iter_batched(|| keys.clone(), insert_all, BatchSize::SmallInput)
+		});
+	}
+	group.finish();
+}
+
+// bench_insert_allocations reports the allocations per insert. The keys are cloned outside of the measurement.
+fn bench_insert_allocations(c: &mut Criterion<Allocations>) {
+	let mut group = c.benchmark_group("mpt");
+	for count in [100, 1_000, 10_000] {
+		let keys = keys(count);
+		group.throughput(Throughput::Elements(count as u64));
+		group.bench_function(format!("insert_allocations/{count}"), |b| {
+			b.iter_batched(|| keys.clone(), insert_all, BatchSize::SmallInput)
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, bench_insert);
+criterion_group! {
+	name = allocations;
+	config = Criterion::default().with_measurement(Allocations);
+	targets = bench_insert_allocations
+}
+criterion_main!(benches, allocations);
@@ -0,0 +1,97 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use mpt::{Hasher, Keccak, WalkControl, WalkNode, MPT};
+
+// SECURE_KEY_NIBBLES is the length of a keccak key in nibbles, which bounds the depth of a secure trie.
+const SECURE_KEY_NIBBLES: usize = 64;
+
+// raw_keys returns sequential keys as a state trie keyed by address would see from a deployer that
+// creates accounts in order. They share long prefixes & build deep paths.
+fn raw_keys(count: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+	(0..count)
+		.map(|i| {
+			let mut key = vec![0; 16];
+			key.extend_from_slice(&i.to_be_bytes());
+			(key, i.to_be_bytes().to_vec())
+		})
+		.collect()
+}
+
+// secure_keys returns the raw keys hashed as in a secure trie with the same values.
+fn secure_keys(count: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+	raw_keys(count).into_iter().map(|(k, v)| (Keccak::hash(&k).to_vec(), v)).collect()
+}
+
+fn insert_all(keys: Vec<(Vec<u8>, Vec<u8>)>) -> MPT {
+	let mut mpt = MPT::default();
+	for (k, v) in keys {
+		mpt.insert(k, v).unwrap();
+	}
+	mpt
+}
+
+// Depth is the average & maximum number of nodes above the values of a trie, and the maximum length
+// of the nibble path of a value.
+struct Depth {
+	average_nodes: f64,
+	max_nodes: usize,
+	max_nibbles: usize,
+}
+
+// depth walks the trie & measures the depth of its values.
+fn depth(mpt: &MPT) -> Depth {
+	// ancestors holds the path lengths of the nodes above the visited node.
+	let mut ancestors: Vec<usize> = Vec::new();
+	let (mut values, mut total_nodes, mut max_nodes, mut max_nibbles) = (0, 0, 0, 0);
+	mpt.walk(&mut |path, node| {
+		while ancestors.last().is_some_and(|&len| len >= path.len()) {
+			ancestors.pop();
+		}
+		let has_value = matches!(node, WalkNode::Value(..) | WalkNode::Branch { value: Some(..) });
+		if has_value {
+			values += 1;
+			total_nodes += ancestors.len();
+			max_nodes = max_nodes.max(ancestors.len());
+			max_nibbles = max_nibbles.max(path.len());
+		}
+		ancestors.push(path.len());
+		WalkControl::Continue
+	})
+	.expect("Cannot miss a node in an in-memory trie");
+	Depth {
+		average_nodes: total_nodes as f64 / values.max(1) as f64,
+		max_nodes,
+		max_nibbles,
+	}
+}
+
+fn bench_secure(c: &mut Criterion) {
+	let mut group = c.benchmark_group("mpt");
+	for count in [1_000, 10_000] {
+		for (mode, keys) in [("raw", raw_keys(count)), ("secure", secure_keys(count))] {
+			let mut mpt = insert_all(keys.clone());
+			let depth = depth(&mpt);
+			println!(
+				"{mode}/{count}: {:.1} nodes above a value on average, at most {} nodes & {} nibbles",
+				depth.average_nodes, depth.max_nodes, depth.max_nibbles
+			);
+			if mode == "secure" {
+				assert!(
+					depth.max_nibbles <= SECURE_KEY_NIBBLES,
+					"secure trie values are {} nibbles deep",
+					depth.max_nibbles
+				);
+			}
+
+			group.bench_function(format!("{mode}/insert/{count}"), |b| {
+				b.iter_batched(|| keys.clone(), insert_all, BatchSize::SmallInput)
+			});
+			group.bench_function(format!("{mode}/get/{count}"), |b| {
+				b.iter(|| keys.iter().filter(|(k, _)| mpt.get_ref(k).unwrap().is_some()).count())
+			});
+		}
+	}
+	group.finish();
+}
+
+criterion_group!(benches, bench_secure);
+criterion_main!(benches);
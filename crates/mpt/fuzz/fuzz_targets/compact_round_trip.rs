@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpt::{compact_to_nibbles, nibbles_to_compact};
+
+fuzz_target!(|input: (Vec<u8>, bool)| {
+	let (bytes, extension) = input;
+	let nibbles: Vec<u8> = bytes.iter().map(|b| b & 0x0f).collect();
+	let compact = nibbles_to_compact(&nibbles, extension);
+	assert_eq!(compact.len(), nibbles.len() / 2 + 1, "compact encoding has the wrong length");
+	assert_eq!(compact_to_nibbles(&compact), (nibbles, extension), "compact encoding does not round trip");
+});
@@ -9,10 +9,10 @@ fuzz_target!(|input: Vec<(Vec<u8>, Vec<u8>)>| {
 	let mut mpt = MPT::default();
 	for (k, v) in input.iter() {
 		map.insert(k.clone(), v.clone());
-		mpt.insert(k.clone(), v.clone());
+		mpt.insert(k.clone(), v.clone()).unwrap();
 	}
 	for (k, v) in map {
-		let stored = mpt.get(k.clone()).unwrap();
-		assert_eq!(stored, &v[..], "MPT value != input value");
+		let stored = mpt.get(k.clone()).unwrap().unwrap();
+		assert_eq!(stored, v, "MPT value != input value");
 	}
 });
@@ -0,0 +1,101 @@
+use crate::decode::{rlp_item, rlp_list};
+use crate::misc::RLPEncodeableWrapper;
+use crate::{Hasher, InsertError, NodeDb, EMPTY_TRIE_ROOT, MPT};
+use core::{
+	hash_literal,
+	types::{Address, Hash},
+};
+use reth_primitives::U256;
+use reth_rlp::Encodable;
+
+/// EMPTY_CODE_HASH is the keccak hash of empty code.
+pub const EMPTY_CODE_HASH: Hash = hash_literal!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+
+/// AccountState is an account as it is stored in the state trie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountState {
+	pub nonce: u64,
+	pub balance: U256,
+	pub storage_root: Hash,
+	pub code_hash: Hash,
+}
+
+impl Default for AccountState {
+	fn default() -> Self {
+		Self {
+			nonce: 0,
+			balance: U256::default(),
+			storage_root: EMPTY_TRIE_ROOT,
+			code_hash: EMPTY_CODE_HASH,
+		}
+	}
+}
+
+impl AccountState {
+	/// rlp_bytes returns the encoding of the account: rlp([nonce, balance, storage_root, code_hash])
+	pub fn rlp_bytes(&self) -> Vec<u8> {
+		let list = [
+			RLPEncodeableWrapper::Raw(encoded(&self.nonce)),
+			RLPEncodeableWrapper::Raw(encoded(&self.balance)),
+			RLPEncodeableWrapper::Bytes(self.storage_root.to_vec()),
+			RLPEncodeableWrapper::Bytes(self.code_hash.to_vec()),
+		];
+		let mut out = Vec::new();
+		reth_rlp::encode_list(&list, &mut out);
+		out
+	}
+
+	/// decode is the inverse of rlp_bytes. It returns None if `raw` is not an encoded account.
+	pub fn decode(raw: &[u8]) -> Option<Self> {
+		let (true, payload, []) = rlp_item(raw).ok()? else {
+			return None;
+		};
+		let mut fields = Vec::with_capacity(4);
+		for item in rlp_list(payload).ok()? {
+			let (false, field, _) = rlp_item(item).ok()? else {
+				return None;
+			};
+			fields.push(field);
+		}
+		let [nonce, balance, storage_root, code_hash] = fields[..] else {
+			return None;
+		};
+		if nonce.len() > 8 {
+			return None;
+		}
+		Some(Self {
+			nonce: nonce.iter().fold(0, |n, b| n << 8 | *b as u64),
+			balance: U256::try_from_be_slice(balance)?,
+			storage_root: Hash::try_from(storage_root).ok()?,
+			code_hash: Hash::try_from(code_hash).ok()?,
+		})
+	}
+}
+
+fn encoded(value: &impl Encodable) -> Vec<u8> {
+	let mut out = Vec::new();
+	value.encode(&mut out);
+	out
+}
+
+impl<H: Hasher, D: NodeDb> MPT<H, D> {
+	/// insert_account stores the account in the trie as a secure trie keyed by the hash of the address.
+	/// An existing account at the address is overwritten.
+	pub fn insert_account(&mut self, address: Address, account: &AccountState) -> Result<(), InsertError> {
+		self.insert(H::hash(&address.to_vec()).to_vec(), account.rlp_bytes())
+	}
+
+	/// apply_updates writes account updates to the trie as a secure trie keyed by the hash of the
+	/// address & returns the new root. An update of None deletes the account.
+	pub fn apply_updates(&mut self, updates: Vec<(Address, Option<AccountState>)>) -> Result<Hash, InsertError> {
+		for (address, account) in updates {
+			match account {
+				Some(account) => self.insert_account(address, &account)?,
+				None => {
+					self.delete_prefix(&H::hash(&address.to_vec()).to_vec())?;
+				}
+			}
+		}
+		Ok(self.hash())
+	}
+}
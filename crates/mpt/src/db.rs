@@ -0,0 +1,58 @@
+use core::types::Hash;
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+};
+
+/// NodeDb stores the encodings of trie nodes by their hash.
+/// It abstracts over in-memory & on-disk storage of the trie.
+pub trait NodeDb {
+	fn get(&self, h: &Hash) -> Option<Vec<u8>>;
+	fn put(&mut self, h: Hash, v: Vec<u8>);
+
+	/// put_slice is put for a borrowed encoding. Stores that drop the nodes can skip the copy.
+	fn put_slice(&mut self, h: Hash, v: &[u8]) {
+		self.put(h, v.to_vec())
+	}
+}
+
+/// NoopDb drops every node that is put into it. It is used to hash a trie without storing its nodes.
+pub(crate) struct NoopDb;
+
+impl NodeDb for NoopDb {
+	fn get(&self, _: &Hash) -> Option<Vec<u8>> {
+		None
+	}
+
+	fn put(&mut self, _: Hash, _: Vec<u8>) {}
+
+	fn put_slice(&mut self, _: Hash, _: &[u8]) {}
+}
+
+impl NodeDb for HashMap<Hash, Vec<u8>> {
+	fn get(&self, h: &Hash) -> Option<Vec<u8>> {
+		HashMap::get(self, h).cloned()
+	}
+
+	fn put(&mut self, h: Hash, v: Vec<u8>) {
+		self.insert(h, v);
+	}
+}
+
+/// SharedDb is a node store that several tries can commit into, e.g. the account trie & its storage tries.
+/// Nodes that are shared between the tries are only stored once.
+pub type SharedDb = Arc<Mutex<HashMap<Hash, Vec<u8>>>>;
+
+impl<D: NodeDb> NodeDb for Arc<Mutex<D>> {
+	fn get(&self, h: &Hash) -> Option<Vec<u8>> {
+		self.lock().expect("Cannot use a poisoned trie db").get(h)
+	}
+
+	fn put(&mut self, h: Hash, v: Vec<u8>) {
+		self.lock().expect("Cannot use a poisoned trie db").put(h, v)
+	}
+
+	fn put_slice(&mut self, h: Hash, v: &[u8]) {
+		self.lock().expect("Cannot use a poisoned trie db").put_slice(h, v)
+	}
+}
@@ -0,0 +1,126 @@
+use crate::misc::compact_to_nibbles;
+use crate::{BranchNode, Node, TrieError, ValueNode};
+use core::types::Hash;
+
+// rlp_item splits the first RLP item off of `buf`.
+// It returns if the item is a list, the payload of the item, and the rest of the buffer.
+pub(crate) fn rlp_item(buf: &[u8]) -> Result<(bool, &[u8], &[u8]), TrieError> {
+	let mut payload = buf;
+	let header = reth_rlp::Header::decode(&mut payload).map_err(|_| TrieError::InvalidNode)?;
+	if header.payload_length > payload.len() {
+		return Err(TrieError::InvalidNode);
+	}
+	let (payload, rest) = payload.split_at(header.payload_length);
+	Ok((header.list, payload, rest))
+}
+
+// rlp_list splits the payload of an RLP list into the raw encoding of each item.
+pub(crate) fn rlp_list(mut payload: &[u8]) -> Result<Vec<&[u8]>, TrieError> {
+	let mut items = Vec::new();
+	while !payload.is_empty() {
+		let (_, _, rest) = rlp_item(payload)?;
+		items.push(&payload[..payload.len() - rest.len()]);
+		payload = rest;
+	}
+	Ok(items)
+}
+
+// decode_node decodes the RLP encoding of a node. Children that are referenced by hash
+// are left as hash nodes. Values are stored in their leaf or branch, so they are decoded right away.
+pub(crate) fn decode_node(raw: &[u8]) -> Result<Node, TrieError> {
+	let (list, payload, _) = rlp_item(raw)?;
+	if !list {
+		return Err(TrieError::InvalidNode);
+	}
+	let items = rlp_list(payload)?;
+	match items.len() {
+		2 => {
+			let (list, compact, _) = rlp_item(items[0])?;
+			if list || compact.is_empty() || compact[0] >> 4 > 3 {
+				return Err(TrieError::InvalidNode);
+			}
+			let (nibbles, extension) = compact_to_nibbles(compact);
+			let child = if extension {
+				decode_reference(items[1])?
+			} else {
+				Node::Value(decode_value(items[1])?)
+			};
+			Ok(Node::new(&nibbles, child))
+		}
+		17 => {
+			let mut branch = BranchNode::default();
+			for (child, item) in branch.children.iter_mut().zip(items.iter()) {
+				**child = decode_reference(item)?;
+			}
+			if items[16] != &[0x80][..] {
+				branch.branch_value = Some(decode_value(items[16])?);
+			}
+			Ok(branch.into())
+		}
+		_ => Err(TrieError::InvalidNode),
+	}
+}
+
+// references returns the hashes of the children that a node refers to, including those of
+// nodes that are embedded in it.
+pub(crate) fn references(raw: &[u8]) -> Result<Vec<Hash>, TrieError> {
+	let mut out = Vec::new();
+	collect_references(raw, &mut out)?;
+	Ok(out)
+}
+
+fn collect_references(raw: &[u8], out: &mut Vec<Hash>) -> Result<(), TrieError> {
+	let (list, payload, _) = rlp_item(raw)?;
+	if !list {
+		return Ok(());
+	}
+	let items = rlp_list(payload)?;
+	// Only the child of an extension & the first 16 slots of a branch are references. A path or a
+	// value can be 32 bytes long as well.
+	let slots = match items.len() {
+		2 => {
+			let (_, compact, _) = rlp_item(items[0])?;
+			if compact.first().is_none_or(|flag| flag >> 4 > 1) {
+				return Ok(());
+			}
+			&items[1..]
+		}
+		17 => &items[..16],
+		_ => return Err(TrieError::InvalidNode),
+	};
+	for item in slots {
+		match rlp_item(item)? {
+			(true, ..) => collect_references(item, out)?,
+			(false, payload, _) if payload.len() == 32 => out.push(Hash::new(payload.try_into().unwrap())),
+			_ => {}
+		}
+	}
+	Ok(())
+}
+
+// decode_reference decodes a child slot which is either empty, a hash, or an embedded node.
+// The kind is told apart by the RLP item: an empty string, a 32 byte string, or a list. Nodes are
+// only embedded if their encoding is shorter than a hash.
+fn decode_reference(raw: &[u8]) -> Result<Node, TrieError> {
+	let (list, payload, _) = rlp_item(raw)?;
+	if list {
+		if raw.len() >= 32 {
+			return Err(TrieError::InvalidNode);
+		}
+		decode_node(raw)
+	} else if payload.is_empty() {
+		Ok(Node::Empty)
+	} else if payload.len() == 32 {
+		Ok(Node::Hash(Hash::new(payload.try_into().unwrap())))
+	} else {
+		Err(TrieError::InvalidNode)
+	}
+}
+
+// decode_value decodes a value slot. Values are never stored by hash, however long they are.
+fn decode_value(raw: &[u8]) -> Result<ValueNode, TrieError> {
+	match rlp_item(raw)? {
+		(false, value, _) => Ok(ValueNode::new(value.to_vec())),
+		_ => Err(TrieError::InvalidNode),
+	}
+}
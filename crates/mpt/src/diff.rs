@@ -0,0 +1,133 @@
+use crate::misc::nibbles_to_bytes;
+use crate::{Hasher, Node, NodeDb, TrieError, MPT};
+use std::cmp::Ordering;
+
+/// DiffKind describes how a key differs between two tries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffKind {
+	/// The key is only present in the second trie.
+	Added,
+	/// The key is only present in the first trie.
+	Removed,
+	/// The key is present in both tries with different values (old, new).
+	Changed(Vec<u8>, Vec<u8>),
+}
+
+/// diff returns every key that differs between `a` and `b` in lexicographic key order.
+/// The tries are walked in lockstep while their shapes match & only the mismatched
+/// subtrees are flattened and compared leaf by leaf. Subtrees that are referenced by the
+/// same hash are skipped, as are in-memory branches with the same cached hash. This errors
+/// if a hash node that has to be walked is missing from its trie's db.
+pub fn diff<H, D: NodeDb, E: NodeDb>(a: &MPT<H, D>, b: &MPT<H, E>) -> Result<Vec<(Vec<u8>, DiffKind)>, TrieError> {
+	let mut out = Vec::new();
+	let dbs: Dbs<'_> = (&a.db, &b.db);
+	diff_nodes(&a.root, &b.root, dbs, &mut Vec::new(), &mut out, usize::MAX)?;
+	Ok(out.into_iter().map(|(path, kind)| (nibbles_to_bytes(&path), kind)).collect())
+}
+
+/// first_diverging_key returns the lexicographically first key where `a` and `b` disagree.
+/// Tries with the same cached root hash are equal without being walked. Otherwise the tries are
+/// walked as in `diff` & the walk stops at the first difference. Hashing the roots caches the
+/// hashes of the branches below them, so equal subtrees are skipped without being hashed again.
+pub fn first_diverging_key<H: Hasher, D: NodeDb, E: NodeDb>(a: &MPT<H, D>, b: &MPT<H, E>) -> Result<Option<Vec<u8>>, TrieError> {
+	if a.root_eq(b) {
+		return Ok(None);
+	}
+	let mut out = Vec::new();
+	let dbs: Dbs<'_> = (&a.db, &b.db);
+	diff_nodes(&a.root, &b.root, dbs, &mut Vec::new(), &mut out, 1)?;
+	Ok(out.first().map(|(path, _)| nibbles_to_bytes(path)))
+}
+
+type Dbs<'a> = (&'a dyn NodeDb, &'a dyn NodeDb);
+
+// diff_nodes appends the differences below `path` to `out` until it holds `limit` entries.
+fn diff_nodes(
+	a: &Node,
+	b: &Node,
+	dbs: Dbs<'_>,
+	path: &mut Vec<u8>,
+	out: &mut Vec<(Vec<u8>, DiffKind)>,
+	limit: usize,
+) -> Result<(), TrieError> {
+	if out.len() >= limit {
+		return Ok(());
+	}
+	match (a, b) {
+		(Node::Empty, Node::Empty) => {}
+		(Node::Hash(a), Node::Hash(b)) if a == b => {}
+		// Branches that were hashed since they were last modified are compared by their cached hash.
+		(Node::Hash(hash), Node::Branch(node)) | (Node::Branch(node), Node::Hash(hash)) if node.hash.get() == Some(hash) => {}
+		(Node::Branch(a), Node::Branch(b)) if a.hash.get().is_some() && a.hash.get() == b.hash.get() => {}
+		(Node::Hash(a), b) => {
+			let a = Node::resolve(*a, dbs.0)?;
+			diff_nodes(&a, b, dbs, path, out, limit)?;
+		}
+		(a, Node::Hash(b)) => {
+			let b = Node::resolve(*b, dbs.1)?;
+			diff_nodes(a, &b, dbs, path, out, limit)?;
+		}
+		(Node::Branch(a), Node::Branch(b)) => {
+			let a_value = a.branch_value.as_ref().map(|v| &v.value[..]);
+			let b_value = b.branch_value.as_ref().map(|v| &v.value[..]);
+			diff_values(path, a_value, b_value, out);
+			for (i, (a, b)) in a.children.iter().zip(b.children.iter()).enumerate() {
+				path.push(i as u8);
+				diff_nodes(a, b, dbs, path, out, limit)?;
+				path.pop();
+			}
+		}
+		(Node::Extension(a), Node::Extension(b)) if a.nibbles == b.nibbles => {
+			path.extend_from_slice(&a.nibbles);
+			diff_nodes(&a.child, &b.child, dbs, path, out, limit)?;
+			path.truncate(path.len() - a.nibbles.len());
+		}
+		(Node::Value(a), Node::Value(b)) => diff_values(path, Some(&a.value), Some(&b.value), out),
+		_ => {
+			let mut a_leaves = Vec::new();
+			let mut b_leaves = Vec::new();
+			a.leaves(path, dbs.0, &mut a_leaves)?;
+			b.leaves(path, dbs.1, &mut b_leaves)?;
+			merge_leaves(a_leaves, b_leaves, out, limit);
+		}
+	}
+	Ok(())
+}
+
+fn diff_values(path: &[u8], a: Option<&[u8]>, b: Option<&[u8]>, out: &mut Vec<(Vec<u8>, DiffKind)>) {
+	match (a, b) {
+		(None, None) => {}
+		(Some(_), None) => out.push((path.to_vec(), DiffKind::Removed)),
+		(None, Some(_)) => out.push((path.to_vec(), DiffKind::Added)),
+		(Some(a), Some(b)) => {
+			if a != b {
+				out.push((path.to_vec(), DiffKind::Changed(a.to_vec(), b.to_vec())))
+			}
+		}
+	}
+}
+
+// merge_leaves compares two lexicographically sorted leaf lists.
+fn merge_leaves(a: Vec<(Vec<u8>, Vec<u8>)>, b: Vec<(Vec<u8>, Vec<u8>)>, out: &mut Vec<(Vec<u8>, DiffKind)>, limit: usize) {
+	let mut a = a.into_iter().peekable();
+	let mut b = b.into_iter().peekable();
+	while out.len() < limit {
+		let ordering = match (a.peek(), b.peek()) {
+			(None, None) => return,
+			(Some(_), None) => Ordering::Less,
+			(None, Some(_)) => Ordering::Greater,
+			(Some((a_path, _)), Some((b_path, _))) => a_path.cmp(b_path),
+		};
+		match ordering {
+			Ordering::Less => out.push((a.next().unwrap().0, DiffKind::Removed)),
+			Ordering::Greater => out.push((b.next().unwrap().0, DiffKind::Added)),
+			Ordering::Equal => {
+				let (path, old) = a.next().unwrap();
+				let (_, new) = b.next().unwrap();
+				if old != new {
+					out.push((path, DiffKind::Changed(old, new)))
+				}
+			}
+		}
+	}
+}
@@ -1,7 +1,7 @@
 use crate::{BranchNode, ExtensionNode, ValueNode, MPT};
 use std::fmt::Debug;
 
-impl Debug for MPT {
+impl<H> Debug for MPT<H> {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		f.write_fmt(format_args!("root: {:#?}\n", &self.root))?;
 		for (k, v) in self.db.iter() {
@@ -0,0 +1,107 @@
+use crate::misc::encode_bytes;
+use crate::{AccountState, DumpError, Hasher, Keccak, EMPTY_CODE_HASH, EMPTY_TRIE_ROOT, MPT};
+use core::types::{Address, Hash};
+use reth_primitives::U256;
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+// StateDump is the output of geth's `debug_dumpBlock`.
+#[derive(Debug, Deserialize)]
+struct StateDump {
+	root: String,
+	accounts: BTreeMap<String, DumpAccount>,
+}
+
+// DumpAccount is an account in a state dump. The balance is a decimal string. Storage values are
+// the hex encoded slot contents without leading zeros, keyed by the unhashed slot.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DumpAccount {
+	balance: String,
+	nonce: u64,
+	root: Option<String>,
+	code_hash: Option<String>,
+	code: Option<String>,
+	#[serde(default)]
+	storage: BTreeMap<String, String>,
+}
+
+impl MPT {
+	/// from_state_dump builds the secure state trie from a geth `debug_dumpBlock` style JSON file
+	/// & checks that its root is the root stated in the dump. Storage tries are built from the
+	/// storage of each account. Accounts without storage use their stated storage root.
+	pub fn from_state_dump(path: impl AsRef<Path>) -> Result<Self, DumpError> {
+		let dump: StateDump = serde_json::from_slice(&std::fs::read(path)?)?;
+		let stated = parse_hash("root", &dump.root)?;
+		let mut mpt = MPT::default();
+		for (address, account) in dump.accounts {
+			mpt.insert_account(Address::new(parse_fixed("address", &address)?), &account.state()?)
+				.expect("Cannot miss a node in an in-memory trie");
+		}
+		let computed = mpt.hash();
+		if computed != stated {
+			return Err(DumpError::RootMismatch { stated, computed });
+		}
+		Ok(mpt)
+	}
+}
+
+impl DumpAccount {
+	// state converts the dumped account into the account as it is stored in the state trie.
+	fn state(&self) -> Result<AccountState, DumpError> {
+		let balance = self
+			.balance
+			.parse::<U256>()
+			.map_err(|_| DumpError::InvalidField("balance", self.balance.clone()))?;
+		let code_hash = match (&self.code, &self.code_hash) {
+			(Some(code), _) => Keccak::hash(&parse_bytes("code", code)?),
+			(None, Some(code_hash)) => parse_hash("code hash", code_hash)?,
+			(None, None) => EMPTY_CODE_HASH,
+		};
+		let stated = self.root.as_deref().map(|root| parse_hash("storage root", root)).transpose()?;
+		let storage_root = if self.storage.is_empty() {
+			stated.unwrap_or(EMPTY_TRIE_ROOT)
+		} else {
+			let computed = self.storage_root()?;
+			match stated {
+				Some(stated) if stated != computed => return Err(DumpError::RootMismatch { stated, computed }),
+				_ => computed,
+			}
+		};
+		Ok(AccountState {
+			nonce: self.nonce,
+			balance,
+			storage_root,
+			code_hash,
+		})
+	}
+
+	// storage_root builds the secure storage trie of the account. Values are stored as RLP strings.
+	fn storage_root(&self) -> Result<Hash, DumpError> {
+		let mut storage = MPT::default();
+		for (slot, value) in &self.storage {
+			let slot = parse_bytes("storage slot", slot)?;
+			let value = parse_bytes("storage value", value)?;
+			let start = value.iter().position(|b| *b != 0).unwrap_or(value.len());
+			storage.insert(Keccak::hash(&slot).to_vec(), encode_bytes(value[start..].to_vec()))
+				.expect("Cannot miss a node in an in-memory trie");
+		}
+		Ok(storage.root_hash())
+	}
+}
+
+// parse_bytes decodes hex with an optional 0x prefix as geth omits it for storage values.
+fn parse_bytes(field: &'static str, s: &str) -> Result<Vec<u8>, DumpError> {
+	hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(|_| DumpError::InvalidField(field, s.to_owned()))
+}
+
+fn parse_hash(field: &'static str, s: &str) -> Result<Hash, DumpError> {
+	Ok(Hash::new(parse_fixed(field, s)?))
+}
+
+// parse_fixed is parse_bytes for values of exactly N bytes such as addresses & hashes.
+fn parse_fixed<const N: usize>(field: &'static str, s: &str) -> Result<[u8; N], DumpError> {
+	parse_bytes(field, s)?
+		.try_into()
+		.map_err(|_| DumpError::InvalidField(field, s.to_owned()))
+}
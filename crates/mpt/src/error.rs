@@ -0,0 +1,133 @@
+use core::types::Hash;
+use std::fmt::Display;
+
+/// TrieError is returned by trie operations that need to read nodes from the db.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrieError {
+	/// A node referenced by hash is not present in the db.
+	MissingNode(Hash),
+	/// A node in the db is not a valid RLP encoded trie node.
+	InvalidNode,
+	/// The key has more nibbles than the maximum depth of the trie.
+	MaxDepthExceeded { depth: usize, max_depth: usize },
+}
+
+impl Display for TrieError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TrieError::MissingNode(hash) => f.write_fmt(format_args!("missing trie node {hash:?}")),
+			TrieError::InvalidNode => f.write_str("invalid trie node"),
+			TrieError::MaxDepthExceeded { depth, max_depth } => {
+				f.write_fmt(format_args!("key of {depth} nibbles exceeds the maximum trie depth of {max_depth}"))
+			}
+		}
+	}
+}
+
+impl std::error::Error for TrieError {}
+
+/// InsertError is returned when a key cannot be inserted into a trie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertError {
+	/// An empty key was inserted into a secure trie. It is only meaningful in a raw trie.
+	EmptyKey,
+	/// The key is longer than the maximum depth or a node on its path cannot be read from the db.
+	Trie(TrieError),
+}
+
+impl Display for InsertError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InsertError::EmptyKey => f.write_str("cannot insert an empty key into a secure trie"),
+			InsertError::Trie(err) => f.write_fmt(format_args!("cannot insert into the trie: {err}")),
+		}
+	}
+}
+
+impl std::error::Error for InsertError {}
+
+impl From<TrieError> for InsertError {
+	fn from(value: TrieError) -> Self {
+		InsertError::Trie(value)
+	}
+}
+
+/// ProofError is returned when a witness cannot answer a lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+	/// A node on the path to the key is not part of the witness.
+	MissingNode(Hash),
+	/// A node in the witness is not a valid RLP encoded trie node.
+	InvalidNode,
+	/// The key is longer than the maximum depth of the trie.
+	MaxDepthExceeded { depth: usize, max_depth: usize },
+	/// The storage trie that a proof is built from is not the storage trie of the account.
+	StorageRootMismatch { expected: Hash, got: Hash },
+}
+
+impl Display for ProofError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ProofError::MissingNode(hash) => f.write_fmt(format_args!("witness is missing trie node {hash:?}")),
+			ProofError::InvalidNode => f.write_str("invalid trie node in witness"),
+			ProofError::MaxDepthExceeded { depth, max_depth } => {
+				f.write_fmt(format_args!("key of {depth} nibbles exceeds the maximum trie depth of {max_depth}"))
+			}
+			ProofError::StorageRootMismatch { expected, got } => f.write_fmt(format_args!(
+				"storage trie root {got:?} is not the storage root {expected:?} of the account"
+			)),
+		}
+	}
+}
+
+impl std::error::Error for ProofError {}
+
+impl From<TrieError> for ProofError {
+	fn from(value: TrieError) -> Self {
+		match value {
+			TrieError::MissingNode(hash) => ProofError::MissingNode(hash),
+			TrieError::InvalidNode => ProofError::InvalidNode,
+			TrieError::MaxDepthExceeded { depth, max_depth } => ProofError::MaxDepthExceeded { depth, max_depth },
+		}
+	}
+}
+
+/// DumpError is returned when a state dump cannot be turned into a state trie.
+#[derive(Debug)]
+pub enum DumpError {
+	/// The dump file cannot be read.
+	Io(std::io::Error),
+	/// The dump is not valid JSON or is missing a field.
+	Json(serde_json::Error),
+	/// A field of the dump does not have the expected format.
+	InvalidField(&'static str, String),
+	/// The root computed from the accounts is not the root stated in the dump.
+	RootMismatch { stated: Hash, computed: Hash },
+}
+
+impl Display for DumpError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DumpError::Io(err) => f.write_fmt(format_args!("cannot read state dump: {err}")),
+			DumpError::Json(err) => f.write_fmt(format_args!("cannot parse state dump: {err}")),
+			DumpError::InvalidField(field, value) => f.write_fmt(format_args!("invalid {field} {value:?} in state dump")),
+			DumpError::RootMismatch { stated, computed } => f.write_fmt(format_args!(
+				"state dump states root {stated:?} but the accounts hash to {computed:?}"
+			)),
+		}
+	}
+}
+
+impl std::error::Error for DumpError {}
+
+impl From<std::io::Error> for DumpError {
+	fn from(value: std::io::Error) -> Self {
+		DumpError::Io(value)
+	}
+}
+
+impl From<serde_json::Error> for DumpError {
+	fn from(value: serde_json::Error) -> Self {
+		DumpError::Json(value)
+	}
+}
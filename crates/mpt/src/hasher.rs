@@ -0,0 +1,18 @@
+use core::types::{keccak, Hash};
+
+/// Hasher is the hash function used to reference trie nodes & to compute the root.
+/// It is a type parameter of the trie so environments without a native keccak (such as
+/// a fault proof VM) can plug in a precompile-backed or vendored implementation.
+pub trait Hasher {
+	fn hash(data: &[u8]) -> Hash;
+}
+
+/// Keccak is the keccak256 hasher used by Ethereum.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Keccak;
+
+impl Hasher for Keccak {
+	fn hash(data: &[u8]) -> Hash {
+		keccak(data)
+	}
+}
@@ -1,40 +1,328 @@
+use crate::db::NoopDb;
 use crate::misc::*;
-use core::types::{keccak, Hash};
-use std::{collections::HashMap, fmt::Debug};
+use core::{hash_literal, types::Hash};
+use std::{
+	cell::OnceCell,
+	collections::{HashMap, HashSet},
+	fmt::Debug,
+	marker::PhantomData,
+};
 
+pub use account::{AccountState, EMPTY_CODE_HASH};
+pub use db::{NodeDb, SharedDb};
+pub use diff::{diff, first_diverging_key, DiffKind};
+pub use error::{DumpError, InsertError, ProofError, TrieError};
+pub use hasher::{Hasher, Keccak};
+pub use misc::{compact_to_nibbles, nibbles_to_compact};
+pub use proof::{prove_account, verify_get, verify_multiproof, verify_value_changed, AccountProof, StorageProof};
+pub use walk::{WalkControl, WalkNode};
+
+mod account;
+mod db;
+mod decode;
+mod diff;
 mod display;
+mod dump;
+mod error;
+mod hasher;
 mod misc;
+mod proof;
 #[cfg(test)]
 mod test;
+mod walk;
 
-#[derive(Default)]
-pub struct MPT {
+/// EMPTY_TRIE_ROOT is the root hash of a trie with no keys when hashing with keccak.
+pub const EMPTY_TRIE_ROOT: Hash = hash_literal!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+
+/// DEFAULT_MAX_DEPTH is the default limit on the number of nibbles of the keys that a trie accepts.
+/// Secure tries only use 64 nibble keys, raw tries such as the transactions trie use short keys.
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+/// compute_root returns the root hash of a trie holding the given key/value pairs.
+/// Later pairs overwrite earlier pairs with the same key. Keys must not be longer than
+/// [DEFAULT_MAX_DEPTH] nibbles.
+pub fn compute_root(pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Hash {
+	let mut mpt = MPT::default();
+	for (k, v) in pairs {
+		mpt.insert(k, v).expect("Cannot insert a key beyond the maximum depth");
+	}
+	mpt.root_hash()
+}
+
+// reachable_from follows the references of the nodes returned by `lookup`, starting at `root`, &
+// returns the hashes of the nodes that it found.
+fn reachable_from(root: Hash, lookup: impl Fn(&Hash) -> Option<Vec<u8>>) -> HashSet<Hash> {
+	let mut reachable = HashSet::new();
+	let mut pending = vec![root];
+	while let Some(hash) = pending.pop() {
+		if reachable.contains(&hash) {
+			continue;
+		}
+		let Some(raw) = lookup(&hash) else {
+			continue;
+		};
+		pending.extend(decode::references(&raw).expect("Cannot traverse a db with invalid nodes"));
+		reachable.insert(hash);
+	}
+	reachable
+}
+
+/// MPT is a Merkle Patricia Trie. Nodes are hashed with `H`, which defaults to keccak, and
+/// are stored in `D`, which defaults to an in-memory map.
+pub struct MPT<H = Keccak, D = HashMap<Hash, Vec<u8>>> {
 	root: Node,
-	db: HashMap<Hash, Vec<u8>>,
+	db: D,
+	// root_hash caches the root hash until the trie is modified.
+	root_hash: OnceCell<Hash>,
+	// max_depth bounds the recursion of inserts, lookups & deletes of untrusted keys.
+	max_depth: usize,
+	hasher: PhantomData<H>,
+}
+
+impl Default for MPT {
+	fn default() -> Self {
+		Self::with_hasher()
+	}
 }
 
 impl MPT {
+	/// from_db creates a partial trie that only references its root by hash.
+	/// Nodes are decoded from `db` as they are needed.
+	pub fn from_db(root: Hash, db: HashMap<Hash, Vec<u8>>) -> Self {
+		Self::from_node_db(root, db)
+	}
+}
+
+impl MPT<Keccak, SharedDb> {
+	/// from_shared_db is from_db for a db that is shared with other tries.
+	pub fn from_shared_db(root: Hash, db: SharedDb) -> Self {
+		Self::from_node_db(root, db)
+	}
+}
+
+impl<H: Hasher> MPT<H> {
+	/// with_hasher creates an empty trie that hashes its nodes with `H`.
+	pub fn with_hasher() -> Self {
+		Self::from_node_db(H::hash(&[0x80]), HashMap::new())
+	}
+
+	/// from_db_with_hasher is from_db for a trie that hashes its nodes with `H`.
+	pub fn from_db_with_hasher(root: Hash, db: HashMap<Hash, Vec<u8>>) -> Self {
+		Self::from_node_db(root, db)
+	}
+
+	/// prune_db hashes the trie & then removes every db entry that is not reachable from the root.
+	/// It returns the number of removed entries.
+	pub fn prune_db(&mut self) -> usize {
+		let reachable = self.reachable();
+		let before = self.db.len();
+		self.db.retain(|hash, _| reachable.contains(hash));
+		before - self.db.len()
+	}
+}
+
+impl<H: Hasher> MPT<H, SharedDb> {
+	/// shared_db returns the db of this trie so that other tries can share it.
+	pub fn shared_db(&self) -> SharedDb {
+		self.db.clone()
+	}
+
+	/// prune_db is prune_db for a shared db. The nodes that are reachable from any of `other_roots`,
+	/// such as the roots of the storage tries that share the db, are kept as well.
+	pub fn prune_db(&mut self, other_roots: &[Hash]) -> usize {
+		let mut reachable = self.reachable();
+		for root in other_roots {
+			reachable.extend(reachable_from(*root, |hash| self.db.get(hash)));
+		}
+		let mut db = self.db.lock().expect("Cannot use a poisoned trie db");
+		let before = db.len();
+		db.retain(|hash, _| reachable.contains(hash));
+		before - db.len()
+	}
+}
+
+impl<H: Hasher, D: NodeDb> MPT<H, D> {
+	/// from_node_db creates a partial trie that only references its root by hash & decodes
+	/// its nodes from `db` as they are needed.
+	pub fn from_node_db(root: Hash, db: D) -> Self {
+		let root = if root == H::hash(&[0x80]) { Node::Empty } else { Node::Hash(root) };
+		Self {
+			root,
+			db,
+			root_hash: OnceCell::new(),
+			max_depth: DEFAULT_MAX_DEPTH,
+			hasher: PhantomData,
+		}
+	}
+
 	pub fn hash(&mut self) -> Hash {
-		keccak(self.root.rlp_bytes(&mut self.db))
+		if let Node::Hash(hash) = self.root {
+			return hash;
+		}
+		let bytes = self.root.rlp_bytes::<H>(&mut self.db);
+		let hash = H::hash(&bytes);
+		self.db.put(hash, bytes);
+		self.root_hash = OnceCell::from(hash);
+		hash
 	}
 
-	pub fn insert(&mut self, k: Vec<u8>, v: Vec<u8>) {
+	// reachable hashes the trie & returns the hashes of every db entry that is reachable from the root.
+	fn reachable(&mut self) -> HashSet<Hash> {
+		let root = self.hash();
+		reachable_from(root, |hash| self.db.get(hash))
+	}
+
+	/// reachable_hashes returns the hashes of every node that is reachable from the root, both of the
+	/// nodes in the db & of the modified nodes that `hash` would store. Unlike `hash`, the db is not
+	/// modified. Referenced nodes that are missing from the db are not included.
+	pub fn reachable_hashes(&self) -> HashSet<Hash> {
+		let mut nodes = HashMap::<Hash, Vec<u8>>::new();
+		let root = match self.root {
+			Node::Hash(hash) => hash,
+			_ => {
+				let bytes = self.root.rlp_bytes::<H>(&mut nodes);
+				let hash = H::hash(&bytes);
+				nodes.insert(hash, bytes);
+				hash
+			}
+		};
+		reachable_from(root, |hash| nodes.get(hash).cloned().or_else(|| self.db.get(hash)))
+	}
+
+	/// root_hash returns the root hash without storing the hashed nodes in the db, so the
+	/// node encodings are not copied. The hash is cached until the next modification of the trie.
+	pub fn root_hash(&self) -> Hash {
+		*self.root_hash.get_or_init(|| match self.root {
+			Node::Hash(hash) => hash,
+			_ => H::hash(&self.root.rlp_bytes::<H>(&mut NoopDb)),
+		})
+	}
+
+	/// estimate_db_size returns the number of bytes of node encodings that `hash` would store in the
+	/// db: every node with an encoding of 32 bytes or more and the root. Subtrees that are still
+	/// unresolved hash nodes are only counted by the root of the subtree.
+	pub fn estimate_db_size(&self) -> usize {
+		let mut nodes = HashMap::<Hash, Vec<u8>>::new();
+		if !matches!(self.root, Node::Hash(..)) {
+			let bytes = self.root.rlp_bytes::<H>(&mut nodes);
+			nodes.insert(H::hash(&bytes), bytes);
+		}
+		nodes.values().map(Vec::len).sum()
+	}
+
+	/// root_eq checks if both tries hold the same keys & values by comparing their root hashes.
+	pub fn root_eq<E: NodeDb>(&self, other: &MPT<H, E>) -> bool {
+		self.root_hash() == other.root_hash()
+	}
+
+	/// insert stores `v` at `k`, overwriting any existing value. Hash nodes along the path are
+	/// decoded from the db, which errors if the db does not contain them.
+	pub fn insert(&mut self, k: Vec<u8>, v: Vec<u8>) -> Result<(), InsertError> {
+		self.insert_with_mode(k, v, InsertMode::Upsert)
+	}
+
+	/// set_max_depth sets the maximum number of nibbles of a key that inserts, lookups & deletes accept.
+	/// They recurse once per node on the path of the key & a path can hold a node per nibble, so longer
+	/// keys are rejected instead of risking a stack overflow. It defaults to [DEFAULT_MAX_DEPTH].
+	pub fn set_max_depth(&mut self, max_depth: usize) {
+		self.max_depth = max_depth;
+	}
+
+	/// insert_secure stores `v` at the hash of `k` as in a secure trie. Unlike in a raw trie, where an
+	/// empty key stores the value at the root, an empty key is rejected.
+	pub fn insert_secure(&mut self, k: &[u8], v: Vec<u8>) -> Result<(), InsertError> {
+		if k.is_empty() {
+			return Err(InsertError::EmptyKey);
+		}
+		self.insert(H::hash(k).to_vec(), v)
+	}
+
+	// check_depth errors if the key of `nibbles` is longer than the maximum depth of the trie.
+	fn check_depth(&self, nibbles: &[u8]) -> Result<(), TrieError> {
+		if nibbles.len() > self.max_depth {
+			return Err(TrieError::MaxDepthExceeded {
+				depth: nibbles.len(),
+				max_depth: self.max_depth,
+			});
+		}
+		Ok(())
+	}
+
+	/// insert_with_mode stores `v` at `k`. With InsertMode::Insert an existing value is left untouched.
+	/// The path is resolved from the db before the trie is modified, so on error the trie is unchanged.
+	pub fn insert_with_mode(&mut self, k: Vec<u8>, v: Vec<u8>, mode: InsertMode) -> Result<(), InsertError> {
 		let k = bytes_to_nibbles(&k);
+		self.check_depth(&k)?;
+		self.root.resolve_path(&k, &self.db)?;
 		let root = std::mem::take(&mut self.root);
-		self.root = root.insert(&k, v);
+		self.root = root.insert(NibblePath::new(&k), v, mode);
+		self.root_hash.take();
+		Ok(())
+	}
+
+	/// delete_prefix removes every key that starts with `prefix` and returns how many were removed.
+	/// Hash nodes in the removed subtrees are decoded from the db to count them, as are the nodes
+	/// that the trie collapses into. The nodes are resolved before the trie is modified, so on error
+	/// the trie is unchanged.
+	pub fn delete_prefix(&mut self, prefix: &[u8]) -> Result<usize, TrieError> {
+		let prefix = bytes_to_nibbles(prefix);
+		self.check_depth(&prefix)?;
+		let removed = self.root.resolve_prefix(&prefix, &self.db)?;
+		if removed > 0 {
+			let root = std::mem::take(&mut self.root);
+			self.root = root.delete_prefix(&prefix);
+			self.root_hash.take();
+		}
+		Ok(removed)
 	}
 
-	pub fn get(&self, k: Vec<u8>) -> Option<&[u8]> {
-		self.root.get(&bytes_to_nibbles(&k))
+	/// get returns the value stored at `k`. Hash nodes along the path are decoded from the db,
+	/// which errors if the db does not contain them.
+	pub fn get(&self, k: Vec<u8>) -> Result<Option<Vec<u8>>, TrieError> {
+		let k = bytes_to_nibbles(&k);
+		self.check_depth(&k)?;
+		self.root.get(&k, &self.db)
+	}
+
+	/// get_ref borrows the value stored at `k` without copying it. Hash nodes along the path are
+	/// decoded from the db into the trie so that the value can be borrowed from it.
+	pub fn get_ref(&mut self, k: &[u8]) -> Result<Option<&[u8]>, TrieError> {
+		let k = bytes_to_nibbles(k);
+		self.check_depth(&k)?;
+		self.root.resolve_path(&k, &self.db)?;
+		match self.root.lookup(&k) {
+			Lookup::Found(value) => Ok(Some(value)),
+			Lookup::Missing => Ok(None),
+			Lookup::Unresolved(hash, _) => Err(TrieError::MissingNode(hash)),
+		}
 	}
 }
 
+/// InsertMode selects what happens when a key that is being inserted already has a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertMode {
+	/// Keep the existing value.
+	Insert,
+	/// Overwrite the existing value.
+	Upsert,
+}
+
 #[derive(Debug)]
 enum Node {
 	Empty,
 	Branch(BranchNode),
 	Extension(ExtensionNode),
 	Value(ValueNode),
+	// Hash is a reference to a node that has not been decoded from the db yet.
+	Hash(Hash),
+}
+
+// Lookup is the result of walking the in-memory part of the trie.
+enum Lookup<'a, 'b> {
+	Found(&'a [u8]),
+	Missing,
+	// Unresolved contains the hash node that was hit & the remaining nibbles of the key.
+	Unresolved(Hash, &'b [u8]),
 }
 
 impl Node {
@@ -50,36 +338,191 @@ impl Node {
 		Node::Value(ValueNode::new(value))
 	}
 
-	fn insert(self, nibbles: &[u8], value: Vec<u8>) -> Self {
+	fn insert(self, path: NibblePath, value: Vec<u8>, mode: InsertMode) -> Self {
 		match self {
-			Node::Empty => Node::new(nibbles, Node::new_value(value)),
-			Node::Branch(node) => node.insert(nibbles, value),
-			Node::Extension(node) => node.insert(nibbles, value),
+			Node::Empty => Node::new(path.as_slice(), Node::new_value(value)),
+			Node::Branch(node) => node.insert(path, value, mode),
+			Node::Extension(node) => node.insert(path, value, mode),
 			Node::Value(node) => {
-				if nibbles.is_empty() {
-					Node::new_value(value)
+				if !path.is_empty() {
+					BranchNode::new_with_value(node).insert(path, value, mode)
+				} else if mode == InsertMode::Insert {
+					Node::Value(node)
+				} else {
+					let mut node = node;
+					node.set_value(value);
+					Node::Value(node)
+				}
+			}
+			Node::Hash(..) => unreachable!("Should have resolved the path before inserting"),
+		}
+	}
+
+	// extend places `child` under the given nibbles while keeping the trie canonical:
+	// empty nodes are dropped & nested extension nodes are merged.
+	fn extend(mut nibbles: Vec<u8>, child: Node) -> Self {
+		match child {
+			Node::Empty => Node::Empty,
+			Node::Extension(child) => {
+				nibbles.extend_from_slice(&child.nibbles);
+				Node::new(&nibbles, *child.child)
+			}
+			child => Node::new(&nibbles, child),
+		}
+	}
+
+	// delete_prefix removes the keys under `nibbles`. The path must have been resolved with resolve_prefix.
+	fn delete_prefix(self, nibbles: &[u8]) -> Self {
+		if nibbles.is_empty() {
+			return Node::Empty;
+		}
+		match self {
+			Node::Branch(node) => node.delete_prefix(nibbles),
+			Node::Extension(node) => node.delete_prefix(nibbles),
+			node => node,
+		}
+	}
+
+	// resolve_prefix decodes the hash nodes that delete_prefix walks through in place & returns the
+	// number of values under `nibbles`. Besides the path, the last other entry of a branch is resolved
+	// as the branch collapses into it if the prefix empties its child.
+	fn resolve_prefix(&mut self, nibbles: &[u8], db: &dyn NodeDb) -> Result<usize, TrieError> {
+		if nibbles.is_empty() {
+			return self.count_values(db);
+		}
+		while let Node::Hash(hash) = *self {
+			*self = Node::resolve(hash, db)?;
+		}
+		match self {
+			Node::Branch(node) => {
+				let i = nibbles[0] as usize;
+				let removed = node.children[i].resolve_prefix(&nibbles[1..], db)?;
+				let others: Vec<usize> = (0..16).filter(|&j| j != i && !matches!(*node.children[j], Node::Empty)).collect();
+				if removed > 0 && node.branch_value.is_none() && others.len() == 1 {
+					while let Node::Hash(hash) = *node.children[others[0]] {
+						*node.children[others[0]] = Node::resolve(hash, db)?;
+					}
+				}
+				Ok(removed)
+			}
+			Node::Extension(node) => {
+				let (_, new_nibbles, old_nibbles) = match_paths(nibbles, &node.nibbles);
+				if new_nibbles.is_empty() {
+					// The prefix ends inside of this extension so everything under it matches.
+					node.child.count_values(db)
+				} else if old_nibbles.is_empty() {
+					node.child.resolve_prefix(new_nibbles, db)
 				} else {
-					BranchNode::new_with_value(node).insert(nibbles, value)
+					Ok(0)
 				}
 			}
+			_ => Ok(0),
 		}
 	}
 
-	fn get(&self, nibbles: &[u8]) -> Option<&[u8]> {
+	// count_values returns the number of values stored under this node.
+	fn count_values(&self, db: &dyn NodeDb) -> Result<usize, TrieError> {
 		match self {
-			Node::Empty => None,
-			Node::Branch(node) => node.get(nibbles),
-			Node::Extension(node) => node.get(nibbles),
-			Node::Value(node) => node.get(nibbles),
+			Node::Empty => Ok(0),
+			Node::Branch(node) => {
+				let mut count = node.branch_value.is_some() as usize;
+				for child in node.children.iter() {
+					count += child.count_values(db)?;
+				}
+				Ok(count)
+			}
+			Node::Extension(node) => node.child.count_values(db),
+			Node::Value(..) => Ok(1),
+			Node::Hash(hash) => Node::resolve(*hash, db)?.count_values(db),
+		}
+	}
+
+	// resolve decodes the node behind a hash from the db.
+	fn resolve(hash: Hash, db: &dyn NodeDb) -> Result<Node, TrieError> {
+		let bytes = db.get(&hash).ok_or(TrieError::MissingNode(hash))?;
+		decode::decode_node(&bytes)
+	}
+
+	// resolve_path decodes the hash nodes on the path of `nibbles` in place, so that the nodes that
+	// an insert of the key walks through or modifies are in memory. Nodes off the path stay hash nodes.
+	fn resolve_path(&mut self, nibbles: &[u8], db: &dyn NodeDb) -> Result<(), TrieError> {
+		while let Node::Hash(hash) = *self {
+			*self = Node::resolve(hash, db)?;
+		}
+		match self {
+			Node::Branch(node) => match nibbles.split_first() {
+				Some((i, rest)) => node.children[*i as usize].resolve_path(rest, db),
+				None => Ok(()),
+			},
+			Node::Extension(node) => match nibbles.strip_prefix(&node.nibbles[..]) {
+				Some(rest) => node.child.resolve_path(rest, db),
+				None => Ok(()),
+			},
+			_ => Ok(()),
+		}
+	}
+
+	fn get(&self, nibbles: &[u8], db: &dyn NodeDb) -> Result<Option<Vec<u8>>, TrieError> {
+		match self.lookup(nibbles) {
+			Lookup::Found(value) => Ok(Some(value.to_vec())),
+			Lookup::Missing => Ok(None),
+			Lookup::Unresolved(hash, nibbles) => Node::resolve(hash, db)?.get(nibbles, db),
+		}
+	}
+
+	fn lookup<'a, 'b>(&'a self, nibbles: &'b [u8]) -> Lookup<'a, 'b> {
+		match self {
+			Node::Empty => Lookup::Missing,
+			Node::Branch(node) => node.lookup(nibbles),
+			Node::Extension(node) => node.lookup(nibbles),
+			Node::Value(node) => node.lookup(nibbles),
+			Node::Hash(hash) => Lookup::Unresolved(*hash, nibbles),
+		}
+	}
+
+	// leaves appends every (nibble path, value) pair under this node to `out` in lexicographic order.
+	// Hash nodes are decoded from the db, which errors if they are missing.
+	fn leaves(&self, path: &mut Vec<u8>, db: &dyn NodeDb, out: &mut Vec<(Vec<u8>, Vec<u8>)>) -> Result<(), TrieError> {
+		match self {
+			Node::Empty => {}
+			Node::Branch(node) => {
+				if let Some(value) = &node.branch_value {
+					out.push((path.clone(), value.value.clone()));
+				}
+				for (i, child) in node.children.iter().enumerate() {
+					path.push(i as u8);
+					child.leaves(path, db, out)?;
+					path.pop();
+				}
+			}
+			Node::Extension(node) => {
+				path.extend_from_slice(&node.nibbles);
+				node.child.leaves(path, db, out)?;
+				path.truncate(path.len() - node.nibbles.len());
+			}
+			Node::Value(node) => out.push((path.clone(), node.value.clone())),
+			Node::Hash(hash) => Node::resolve(*hash, db)?.leaves(path, db, out)?,
 		}
+		Ok(())
 	}
 
-	fn rlp_bytes(&mut self, db: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
+	fn rlp_bytes<H: Hasher>(&self, db: &mut dyn NodeDb) -> Vec<u8> {
 		match self {
 			Node::Empty => vec![0x80],
-			Node::Branch(node) => node.rlp_bytes(db),
-			Node::Extension(node) => node.rlp_bytes(db),
+			Node::Branch(node) => node.rlp_bytes::<H>(db),
+			Node::Extension(node) => node.rlp_bytes::<H>(db),
 			Node::Value(node) => node.rlp_bytes(db),
+			Node::Hash(hash) => db.get(hash).expect("Cannot encode a hash node that is not in the db"),
+		}
+	}
+
+	// reference returns how this node is referred to from its parent: either embedded or by hash.
+	fn reference<H: Hasher>(&self, db: &mut dyn NodeDb) -> RLPEncodeableWrapper {
+		match self {
+			Node::Hash(hash) => RLPEncodeableWrapper::Bytes(hash.to_vec()),
+			Node::Branch(node) => node.reference::<H>(db),
+			Node::Value(node) => node.reference::<H>(db),
+			_ => mpt_hash::<H>(&self.rlp_bytes::<H>(db), db),
 		}
 	}
 }
@@ -94,26 +537,65 @@ impl Default for Node {
 struct BranchNode {
 	children: [Box<Node>; 16],
 	branch_value: Option<ValueNode>,
+	// hash caches the hash of the encoding if it is long enough to be referenced by hash. It is
+	// cleared when the branch or any node below it is modified.
+	hash: OnceCell<Hash>,
 }
 
 impl BranchNode {
 	// inserts adds a key/value to a branch node as either a sub-node or as a value.
-	// It returns none if there is an error.
-	fn insert(mut self, nibbles: &[u8], value: Vec<u8>) -> Node {
-		if nibbles.is_empty() {
-			self.branch_value = Some(ValueNode::new(value));
-		} else {
-			let i = nibbles[0] as usize;
-			*self.children[i] = std::mem::take(&mut self.children[i]).insert(&nibbles[1..], value);
+	// An existing branch value is only replaced in upsert mode.
+	fn insert(mut self, path: NibblePath, value: Vec<u8>, mode: InsertMode) -> Node {
+		self.hash.take();
+		match path.first() {
+			None => match &mut self.branch_value {
+				Some(node) if mode == InsertMode::Upsert => node.set_value(value),
+				Some(_) => {}
+				None => self.branch_value = Some(ValueNode::new(value)),
+			},
+			Some(i) => {
+				let i = i as usize;
+				*self.children[i] = std::mem::take(&mut self.children[i]).insert(path.advance(1), value, mode);
+			}
 		};
 		self.into()
 	}
 
-	fn get(&self, nibbles: &[u8]) -> Option<&[u8]> {
+	fn delete_prefix(mut self, nibbles: &[u8]) -> Node {
+		self.hash.take();
+		let i = nibbles[0] as usize;
+		*self.children[i] = std::mem::take(&mut *self.children[i]).delete_prefix(&nibbles[1..]);
+		self.collapse()
+	}
+
+	// collapse turns a branch node with less than two entries into the equivalent smaller node.
+	// The remaining child must be resolved so that its path can be merged into the new node.
+	fn collapse(mut self) -> Node {
+		let children: Vec<usize> = (0..16).filter(|&i| !matches!(*self.children[i], Node::Empty)).collect();
+		let only_child = match children[..] {
+			[] => None,
+			[i] => Some(i),
+			_ => return self.into(),
+		};
+		match (only_child, self.branch_value.take()) {
+			(None, None) => Node::Empty,
+			(None, Some(value)) => Node::Value(value),
+			(Some(i), None) => Node::extend(vec![i as u8], std::mem::take(&mut *self.children[i])),
+			(Some(_), Some(value)) => {
+				self.branch_value = Some(value);
+				self.into()
+			}
+		}
+	}
+
+	fn lookup<'a, 'b>(&'a self, nibbles: &'b [u8]) -> Lookup<'a, 'b> {
 		if nibbles.is_empty() {
-			self.branch_value.as_ref().map(|v| &v.value[..])
+			match &self.branch_value {
+				Some(v) => Lookup::Found(&v.value),
+				None => Lookup::Missing,
+			}
 		} else {
-			self.children[nibbles[0] as usize].get(&nibbles[1..])
+			self.children[nibbles[0] as usize].lookup(&nibbles[1..])
 		}
 	}
 
@@ -132,19 +614,31 @@ impl BranchNode {
 		}
 	}
 
-	fn rlp_bytes(&mut self, db: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
+	fn rlp_bytes<H: Hasher>(&self, db: &mut dyn NodeDb) -> Vec<u8> {
 		let mut list: Vec<RLPEncodeableWrapper> = Vec::new();
 		let mut bytes = Vec::new();
-		for child in self.children.iter_mut() {
-			list.push(mpt_hash(&child.rlp_bytes(db), db));
+		for child in self.children.iter() {
+			list.push(child.reference::<H>(db));
 		}
 		match &self.branch_value {
-			Some(value) => list.push(mpt_hash(&value.rlp_bytes(db), db)),
+			Some(value) => list.push(value.inline()),
 			None => list.push(RLPEncodeableWrapper::EmptyString),
 		}
 		reth_rlp::encode_list(&list, &mut bytes);
 		bytes
 	}
+
+	// reference is mpt_hash for the branch node that only hashes the encoding the first time, so
+	// that the hashes of unmodified subtrees can be compared without hashing them again.
+	fn reference<H: Hasher>(&self, db: &mut dyn NodeDb) -> RLPEncodeableWrapper {
+		let bytes = self.rlp_bytes::<H>(db);
+		if bytes.len() < 32 {
+			return RLPEncodeableWrapper::Raw(bytes);
+		}
+		let hash = *self.hash.get_or_init(|| H::hash(&bytes));
+		db.put_slice(hash, &bytes);
+		RLPEncodeableWrapper::Bytes(hash.to_vec())
+	}
 }
 
 impl From<BranchNode> for Node {
@@ -156,62 +650,94 @@ impl From<BranchNode> for Node {
 struct ExtensionNode {
 	nibbles: Vec<u8>,
 	child: Box<Node>,
+	// compact caches the compact encoding of the nibbles. Extension nodes are rebuilt on every
+	// insert so this never needs to be invalidated.
+	compact: OnceCell<Vec<u8>>,
 }
 
 impl ExtensionNode {
 	fn new_node(nibbles: Vec<u8>, child: Box<Node>) -> Node {
-		Node::Extension(Self { nibbles, child })
+		Node::Extension(Self {
+			nibbles,
+			child,
+			compact: OnceCell::new(),
+		})
 	}
 
-	fn compact(&self) -> Vec<u8> {
-		let extension = match *self.child {
-			Node::Empty => panic!("Cannot point to an empty node in an extension"),
-			Node::Extension(..) => panic!("Cannot point to an extension node in an extension node"),
-			Node::Value(..) => false,
-			Node::Branch(..) => true,
-		};
-		nibbles_to_compact(&self.nibbles, extension)
+	fn compact(&self) -> &[u8] {
+		self.compact.get_or_init(|| {
+			let extension = match *self.child {
+				Node::Empty => panic!("Cannot point to an empty node in an extension"),
+				Node::Extension(..) => panic!("Cannot point to an extension node in an extension node"),
+				Node::Value(..) => false,
+				// Values are always decoded eagerly so a hash node under an extension is a branch.
+				Node::Branch(..) | Node::Hash(..) => true,
+			};
+			nibbles_to_compact(&self.nibbles, extension)
+		})
 	}
 
-	fn insert(self, nibbles: &[u8], value: Vec<u8>) -> Node {
-		let (common, new_nibbles, old_nibbles) = match_paths(nibbles, &self.nibbles);
-		if new_nibbles.is_empty() && old_nibbles.is_empty() {
-			return ExtensionNode::new_node(common, Box::new(self.child.insert(&[], value)));
+	fn insert(mut self, path: NibblePath, value: Vec<u8>, mode: InsertMode) -> Node {
+		let common = path.common_prefix_len(&self.nibbles);
+		let path = path.advance(common);
+		if path.is_empty() && common == self.nibbles.len() {
+			return ExtensionNode::new_node(self.nibbles, Box::new(self.child.insert(path, value, mode)));
 		}
 		// Inserting here will alwasy create branch node.
 		// Turn the existing node into that branch node then insert the new value.
-		let branch_node = if old_nibbles.is_empty() {
+		let branch_node = if common == self.nibbles.len() {
 			match *self.child {
 				Node::Empty => panic!("Cannot point to an empty node in an extension"),
 				Node::Extension(..) => panic!("Cannot point to an extension node in an extension node"),
 				Node::Value(child) => BranchNode::new_with_value(child),
 				Node::Branch(child) => child,
+				Node::Hash(..) => unreachable!("Should have resolved the path before inserting"),
 			}
 		} else {
-			let child = Box::new(Node::new(&old_nibbles[1..], *(self.child)));
-			BranchNode::new_with_node(old_nibbles[0], child)
+			let child = Box::new(Node::new(&self.nibbles[common + 1..], *(self.child)));
+			BranchNode::new_with_node(self.nibbles[common], child)
 		}
-		.insert(new_nibbles, value);
-		// Create an extension node based on the common part if needed.
-		if common.is_empty() {
+		.insert(path, value, mode);
+		// Create an extension node based on the common part if needed. It reuses the nibbles of this node.
+		if common == 0 {
 			branch_node
 		} else {
-			ExtensionNode::new_node(common, Box::new(branch_node))
+			self.nibbles.truncate(common);
+			ExtensionNode::new_node(self.nibbles, Box::new(branch_node))
+		}
+	}
+
+	fn delete_prefix(self, nibbles: &[u8]) -> Node {
+		let (_, new_nibbles, old_nibbles) = match_paths(nibbles, &self.nibbles);
+		if new_nibbles.is_empty() {
+			// The prefix ends inside of this extension so everything under it matches.
+			return Node::Empty;
+		}
+		if !old_nibbles.is_empty() {
+			return Node::Extension(self);
 		}
+		let new_nibbles = new_nibbles.to_vec();
+		let child = self.child.delete_prefix(&new_nibbles);
+		Node::extend(self.nibbles, child)
 	}
 
-	fn get(&self, nibbles: &[u8]) -> Option<&[u8]> {
+	fn lookup<'a, 'b>(&'a self, nibbles: &'b [u8]) -> Lookup<'a, 'b> {
 		let (_, new_nibbles, old_nibbles) = match_paths(nibbles, &self.nibbles);
 		if old_nibbles.is_empty() {
-			self.child.get(new_nibbles)
+			self.child.lookup(new_nibbles)
 		} else {
-			None
+			Lookup::Missing
 		}
 	}
 
-	fn rlp_bytes(&mut self, db: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
+	fn rlp_bytes<H: Hasher>(&self, db: &mut dyn NodeDb) -> Vec<u8> {
 		let mut bytes = Vec::new();
-		let list = vec![RLPEncodeableWrapper::Bytes(self.compact()), mpt_hash(&self.child.rlp_bytes(db), db)];
+		// The value of a leaf is stored in the leaf itself, however long it is.
+		let child = match &*self.child {
+			Node::Value(value) => value.inline(),
+			child => child.reference::<H>(db),
+		};
+		let list = vec![RLPEncodeableWrapper::Bytes(self.compact().to_vec()), child];
 		reth_rlp::encode_list(&list, &mut bytes);
 		bytes
 	}
@@ -219,23 +745,54 @@ impl ExtensionNode {
 
 struct ValueNode {
 	value: Vec<u8>,
+	// hash caches the hash of the encoding if it is long enough to be referenced by hash.
+	hash: OnceCell<Hash>,
 }
 
 impl ValueNode {
 	fn new(value: Vec<u8>) -> Self {
-		Self { value }
+		Self {
+			value,
+			hash: OnceCell::new(),
+		}
 	}
-	fn get(&self, _nibbles: &[u8]) -> Option<&[u8]> {
+
+	// set_value overwrites the value & clears the cached hash of the old value.
+	fn set_value(&mut self, value: Vec<u8>) {
+		self.value = value;
+		self.hash = OnceCell::new();
+	}
+
+	// reference is mpt_hash for the leaf of the value node that only hashes the encoding the first time.
+	// The encoding is still stored in the db every time as the db may have changed.
+	fn reference<H: Hasher>(&self, db: &mut dyn NodeDb) -> RLPEncodeableWrapper {
+		let bytes = self.rlp_bytes(db);
+		if bytes.len() < 32 {
+			return RLPEncodeableWrapper::Raw(bytes);
+		}
+		let hash = *self.hash.get_or_init(|| H::hash(&bytes));
+		db.put_slice(hash, &bytes);
+		RLPEncodeableWrapper::Bytes(hash.to_vec())
+	}
+	fn lookup<'a, 'b>(&'a self, _nibbles: &'b [u8]) -> Lookup<'a, 'b> {
 		if _nibbles.is_empty() {
-			Some(&self.value)
+			Lookup::Found(&self.value)
 		} else {
-			None
+			Lookup::Missing
 		}
 		// // TODO: Intentional bug to see if fuzzing will catch it.
 		// // It did not b/c I did not fuzz by querying with known missing keys.
 		// Some(&self.value)
 	}
-	fn rlp_bytes(&self, _: &mut HashMap<Hash, Vec<u8>>) -> Vec<u8> {
-		encode_bytes(self.value.clone())
+	// inline returns the value as it is stored in the value slot of a branch or a leaf.
+	fn inline(&self) -> RLPEncodeableWrapper {
+		RLPEncodeableWrapper::Bytes(self.value.clone())
+	}
+
+	// rlp_bytes returns the encoding of the value node as a leaf with an empty path.
+	fn rlp_bytes(&self, _: &mut dyn NodeDb) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		reth_rlp::encode_list(&[RLPEncodeableWrapper::Bytes(vec![0x20]), self.inline()], &mut bytes);
+		bytes
 	}
 }
@@ -1,7 +1,8 @@
-use core::types::{keccak, Hash};
+use crate::{Hasher, NodeDb};
+use core::types::Hash;
 use reth_primitives::Bytes;
 use reth_rlp::Encodable;
-use std::{collections::HashMap, fmt::Debug, iter::zip};
+use std::{fmt::Debug, iter::zip};
 
 #[derive(Debug)]
 pub enum RLPEncodeableWrapper {
@@ -29,12 +30,12 @@ pub fn encode_bytes(x: Vec<u8>) -> Vec<u8> {
 }
 
 // mpt_hash implements H(x) as used in the MPT.
-pub fn mpt_hash(x: &[u8], db: &mut HashMap<Hash, Vec<u8>>) -> RLPEncodeableWrapper {
+pub fn mpt_hash<H: Hasher>(x: &[u8], db: &mut dyn NodeDb) -> RLPEncodeableWrapper {
 	if x.len() < 32 {
 		RLPEncodeableWrapper::Raw(x.to_vec())
 	} else {
-		let h = keccak(x);
-		db.insert(h, x.to_vec());
+		let h = H::hash(x);
+		db.put_slice(h, x);
 		RLPEncodeableWrapper::Bytes(h.to_vec())
 	}
 }
@@ -54,6 +55,46 @@ pub fn match_paths<'a, 'b>(key: &'a [u8], path: &'b [u8]) -> (Vec<u8>, &'a [u8],
 	(common, &key[i..], &path[i..])
 }
 
+// NibblePath is a view of the nibbles of a key that are left to be inserted. Advancing it moves an
+// offset into the key so the insert recursion does not allocate the remaining nibbles at each level.
+#[derive(Debug, Clone, Copy)]
+pub struct NibblePath<'a> {
+	nibbles: &'a [u8],
+	offset: usize,
+}
+
+impl<'a> NibblePath<'a> {
+	pub fn new(nibbles: &'a [u8]) -> Self {
+		Self { nibbles, offset: 0 }
+	}
+
+	pub fn as_slice(&self) -> &'a [u8] {
+		&self.nibbles[self.offset..]
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.offset == self.nibbles.len()
+	}
+
+	pub fn first(&self) -> Option<u8> {
+		self.as_slice().first().copied()
+	}
+
+	// advance skips the next `n` nibbles. It panics if fewer than `n` nibbles are left.
+	pub fn advance(self, n: usize) -> Self {
+		assert!(self.offset + n <= self.nibbles.len(), "Cannot advance past the end of the key");
+		Self {
+			nibbles: self.nibbles,
+			offset: self.offset + n,
+		}
+	}
+
+	// common_prefix_len returns the number of leading nibbles that the path shares with `other`.
+	pub fn common_prefix_len(&self, other: &[u8]) -> usize {
+		zip(self.as_slice(), other).take_while(|(a, b)| a == b).count()
+	}
+}
+
 // bytes_to_nibbles splits a list of bytes into a list of nibbles
 pub fn bytes_to_nibbles(key: &[u8]) -> Vec<u8> {
 	let mut out = Vec::new();
@@ -64,6 +105,12 @@ pub fn bytes_to_nibbles(key: &[u8]) -> Vec<u8> {
 	out
 }
 
+// nibbles_to_bytes folds a list of nibbles back into bytes. It is the inverse of bytes_to_nibbles
+// and expects an even number of nibbles.
+pub fn nibbles_to_bytes(nibbles: &[u8]) -> Vec<u8> {
+	nibbles.chunks_exact(2).map(|a| a[0] << 4 | a[1]).collect()
+}
+
 // nibbles_to_compact turns a list of nibbles into Ethereum's compact encoding scheme.
 // It prefixes the parity of the nibbles length & if it's an extension into the first nibble
 // and then folds the nibbles into bytes.
@@ -90,7 +137,6 @@ pub fn nibbles_to_compact(nibbles: &[u8], extension: bool) -> Vec<u8> {
 
 // compact_to_nibbles decodes Ethereum's compact encoding into the original nibbles
 // array and also returns if the path was an extension or not.
-#[allow(dead_code)]
 pub fn compact_to_nibbles(compact: &[u8]) -> (Vec<u8>, bool) {
 	let (extension, even) = match compact[0] >> 4 {
 		0 => (true, true),
@@ -0,0 +1,142 @@
+use crate::misc::bytes_to_nibbles;
+use crate::{AccountState, Hasher, Node, NodeDb, ProofError, TrieError, EMPTY_TRIE_ROOT, MPT};
+use core::types::{Address, Hash};
+use std::{cell::RefCell, collections::HashMap};
+
+/// StorageProof is the proof of a storage slot as in an `eth_getProof` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageProof {
+	pub key: Hash,
+	/// The RLP encoded value of the slot or None if the slot is empty
+	pub value: Option<Vec<u8>>,
+	pub proof: Vec<Vec<u8>>,
+}
+
+/// AccountProof is the proof of an account & of some of its storage slots as in an `eth_getProof` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountProof {
+	pub address: Address,
+	/// The account or None if it does not exist
+	pub account: Option<AccountState>,
+	pub account_proof: Vec<Vec<u8>>,
+	pub storage_proofs: Vec<StorageProof>,
+}
+
+impl<H: Hasher, D: NodeDb> MPT<H, D> {
+	/// prove hashes the trie & returns the nodes that a lookup of `k` reads, starting at the root.
+	/// Its result answers the lookup with [verify_get]. The proof of any key of an empty trie is empty.
+	pub fn prove(&mut self, k: &[u8]) -> Result<Vec<Vec<u8>>, TrieError> {
+		let k = bytes_to_nibbles(k);
+		self.check_depth(&k)?;
+		let root = self.hash();
+		// The stored encoding of the empty root is not a node that a lookup can read.
+		if matches!(self.root, Node::Empty) {
+			return Ok(Vec::new());
+		}
+		let db = ReadRecorder {
+			db: &self.db,
+			reads: RefCell::default(),
+		};
+		Node::resolve(root, &db)?.get(&k, &db)?;
+		Ok(db.reads.into_inner())
+	}
+}
+
+/// prove_account proves an account of the secure state trie & the storage slots `keys` of the secure
+/// storage trie of the account. The storage trie must hash to the storage root of the account or to
+/// the empty root if the account does not exist.
+pub fn prove_account<H: Hasher, D: NodeDb, E: NodeDb>(
+	state: &mut MPT<H, D>,
+	storage: &mut MPT<H, E>,
+	address: Address,
+	keys: &[Hash],
+) -> Result<AccountProof, ProofError> {
+	let account_key = H::hash(&address.to_vec()).to_vec();
+	let account = state
+		.get(account_key.clone())?
+		.map(|raw| AccountState::decode(&raw).ok_or(ProofError::InvalidNode))
+		.transpose()?;
+	let expected = account.map_or(EMPTY_TRIE_ROOT, |a| a.storage_root);
+	let storage_root = storage.hash();
+	if storage_root != expected {
+		return Err(ProofError::StorageRootMismatch {
+			expected,
+			got: storage_root,
+		});
+	}
+	let storage_proofs = keys
+		.iter()
+		.map(|key| -> Result<StorageProof, ProofError> {
+			let slot = H::hash(&key.to_vec()).to_vec();
+			Ok(StorageProof {
+				key: *key,
+				value: storage.get(slot.clone())?,
+				proof: storage.prove(&slot)?,
+			})
+		})
+		.collect::<Result<_, _>>()?;
+	Ok(AccountProof {
+		address,
+		account,
+		account_proof: state.prove(&account_key)?,
+		storage_proofs,
+	})
+}
+
+// ReadRecorder is a read only view of a db that records the nodes that are read from it.
+struct ReadRecorder<'a> {
+	db: &'a dyn NodeDb,
+	reads: RefCell<Vec<Vec<u8>>>,
+}
+
+impl NodeDb for ReadRecorder<'_> {
+	fn get(&self, h: &Hash) -> Option<Vec<u8>> {
+		let node = self.db.get(h)?;
+		self.reads.borrow_mut().push(node.clone());
+		Some(node)
+	}
+
+	fn put(&mut self, _: Hash, _: Vec<u8>) {
+		unreachable!("Cannot write to a read only db")
+	}
+}
+
+/// verify_get answers a lookup of `key` in the trie with root `root` from a witness of trie nodes.
+/// Only the nodes on the path to the key are decoded, so the witness does not need to contain the
+/// rest of the trie. It errors if the witness misses a node that the lookup requires. Nodes are hashed
+/// with `H`, which must be the hasher of the trie.
+pub fn verify_get<H: Hasher>(root: Hash, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, ProofError> {
+	let db: HashMap<Hash, Vec<u8>> = proof.iter().map(|node| (H::hash(node), node.clone())).collect();
+	Ok(MPT::<H>::from_db_with_hasher(root, db).get(key.to_vec())?)
+}
+
+/// verify_multiproof answers the lookups of several keys against the same root. Each lookup is
+/// verified against the nodes of its own witness only, as with [verify_get], but a node shared by
+/// several witnesses, like the root, is only hashed once.
+pub fn verify_multiproof<H: Hasher>(root: Hash, items: &[(Vec<u8>, Vec<Vec<u8>>)]) -> Result<Vec<Option<Vec<u8>>>, ProofError> {
+	let mut hashes: HashMap<&[u8], Hash> = HashMap::new();
+	items.iter()
+		.map(|(key, proof)| {
+			let db: HashMap<Hash, Vec<u8>> = proof
+				.iter()
+				.map(|node| (*hashes.entry(node.as_slice()).or_insert_with(|| H::hash(node)), node.clone()))
+				.collect();
+			Ok(MPT::<H>::from_db_with_hasher(root, db).get(key.clone())?)
+		})
+		.collect()
+}
+
+/// verify_value_changed checks if the value of `key` differs between the tries with roots `old_root` &
+/// `new_root` given a witness for the key against each root. Both lookups are verified before the values
+/// are compared, so a witness that misses a node is an error rather than a change.
+pub fn verify_value_changed<H: Hasher>(
+	key: &[u8],
+	old_root: Hash,
+	old_proof: &[Vec<u8>],
+	new_root: Hash,
+	new_proof: &[Vec<u8>],
+) -> Result<bool, ProofError> {
+	let old = verify_get::<H>(old_root, key, old_proof)?;
+	let new = verify_get::<H>(new_root, key, new_proof)?;
+	Ok(old != new)
+}
@@ -1,5 +1,5 @@
 use crate::*;
-use core::hash_literal;
+use core::{address_literal, hash_literal, types::Address};
 
 struct NibblesCompactTestCase {
 	nibbles: Vec<u8>,
@@ -71,6 +71,23 @@ fn test_nibbles_compact_conversions() {
 	}
 }
 
+#[test]
+fn test_compact_round_trip_matrix() {
+	for len in 0..=9 {
+		for extension in [true, false] {
+			// Cover every nibble value, including leading zero & 0xf nibbles.
+			for offset in 0..16 {
+				let nibbles: Vec<u8> = (0..len).map(|i| (i * 7 + offset) % 16).collect();
+				let compact = nibbles_to_compact(&nibbles, extension);
+				assert_eq!(compact.len(), nibbles.len() / 2 + 1);
+				assert_eq!((compact[0] >> 4) & 1, len % 2, "parity flag for {nibbles:?}");
+				assert_eq!(compact[0] >> 5 == 0, extension, "extension flag for {nibbles:?}");
+				assert_eq!(compact_to_nibbles(&compact), (nibbles, extension));
+			}
+		}
+	}
+}
+
 #[test]
 fn test_empty_root_hash() {
 	let mut mpt = MPT::default();
@@ -84,39 +101,39 @@ fn test_mpt_get() {
 	let mut mpt = MPT::default();
 	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
 	for (k, v) in inputs.iter() {
-		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
 	}
 	for (k, v) in inputs {
-		assert_eq!(mpt.get(k.into()), Some(v.as_bytes()));
+		assert_eq!(mpt.get(k.into()), Ok(Some(v.into())));
 	}
-	assert_eq!(mpt.get("".into()), None);
-	assert_eq!(mpt.get("dogf".into()), None);
-	assert_eq!(mpt.get("hors".into()), None);
-	// assert_eq!(mpt.get("horses".into()), None); // TODO: Find this bug with fuzzing.
+	assert_eq!(mpt.get("".into()), Ok(None));
+	assert_eq!(mpt.get("dogf".into()), Ok(None));
+	assert_eq!(mpt.get("hors".into()), Ok(None));
+	// assert_eq!(mpt.get("horses".into()), Ok(None)); // TODO: Find this bug with fuzzing.
 }
 
 #[test]
 // Found via fuzzing
 fn test_mpt_empty_overwrite() {
 	let mut mpt = MPT::default();
-	mpt.insert(vec![], vec![]);
-	mpt.insert(vec![2], vec![0]);
-	mpt.insert(vec![], vec![]);
+	mpt.insert(vec![], vec![]).unwrap();
+	mpt.insert(vec![2], vec![0]).unwrap();
+	mpt.insert(vec![], vec![]).unwrap();
 
-	assert_eq!(mpt.get(vec![]), Some(vec![].as_slice()));
-	assert_eq!(mpt.get(vec![2]), Some(vec![0].as_slice()));
+	assert_eq!(mpt.get(vec![]), Ok(Some(vec![])));
+	assert_eq!(mpt.get(vec![2]), Ok(Some(vec![0])));
 }
 
 #[test]
 // Found via fuzzing
 fn test_mpt_overwrite_value_of_extension_node() {
 	let mut mpt = MPT::default();
-	mpt.insert(vec![0], vec![]);
-	mpt.insert(vec![0], vec![0]);
-	mpt.insert(vec![], vec![]);
+	mpt.insert(vec![0], vec![]).unwrap();
+	mpt.insert(vec![0], vec![0]).unwrap();
+	mpt.insert(vec![], vec![]).unwrap();
 
-	assert_eq!(mpt.get(vec![]), Some(vec![].as_slice()));
-	assert_eq!(mpt.get(vec![0]), Some(vec![0].as_slice()));
+	assert_eq!(mpt.get(vec![]), Ok(Some(vec![])));
+	assert_eq!(mpt.get(vec![0]), Ok(Some(vec![0])));
 }
 
 #[test]
@@ -131,10 +148,10 @@ fn test_mpt_overwrite() {
 		("horse", "mare"),
 	];
 	for (k, v) in inputs.iter() {
-		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
 	}
-	assert_eq!(mpt.get("doge".into()), Some("moon".as_bytes()));
-	assert_eq!(mpt.get("horse".into()), Some("mare".as_bytes()));
+	assert_eq!(mpt.get("doge".into()), Ok(Some("moon".into())));
+	assert_eq!(mpt.get("horse".into()), Ok(Some("mare".into())));
 }
 
 #[test]
@@ -142,12 +159,12 @@ fn test_mpt_prefix_get() {
 	let mut mpt = MPT::default();
 	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
 	for (k, v) in inputs.iter() {
-		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
 	}
-	assert_eq!(mpt.get("d".into()), None);
-	assert_eq!(mpt.get("dodo".into()), None);
-	assert_eq!(mpt.get("doges".into()), None);
-	assert_eq!(mpt.get("horses".into()), None);
+	assert_eq!(mpt.get("d".into()), Ok(None));
+	assert_eq!(mpt.get("dodo".into()), Ok(None));
+	assert_eq!(mpt.get("doges".into()), Ok(None));
+	assert_eq!(mpt.get("horses".into()), Ok(None));
 }
 
 // Test MPT from https://ethereum.org/en/developers/docs/data-structures-and-encoding/patricia-merkle-trie/
@@ -189,23 +206,1158 @@ fn test_mpt_prefix_get() {
 fn test_mpt_hash() {
 	let mut mpt = MPT::default();
 
-	mpt.insert("do".into(), "verb".into());
+	mpt.insert("do".into(), "verb".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("014f07ed95e2e028804d915e0dbd4ed451e394e1acfd29e463c11a060b2ddef7");
 	assert_eq!(expected_hash, hash);
 
-	mpt.insert("dog".into(), "puppy".into());
+	mpt.insert("dog".into(), "puppy".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("779db3986dd4f38416bfde49750ef7b13c6ecb3e2221620bcad9267e94604d36");
 	assert_eq!(expected_hash, hash);
 
-	mpt.insert("doge".into(), "coin".into());
+	mpt.insert("doge".into(), "coin".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("ef7b2fe20f5d2c30c46ad4d83c39811bcbf1721aef2e805c0e107947320888b6");
 	assert_eq!(expected_hash, hash);
 
-	mpt.insert("horse".into(), "stallion".into());
+	mpt.insert("horse".into(), "stallion".into()).unwrap();
 	let hash = mpt.hash();
 	let expected_hash: Hash = hash_literal!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84");
 	assert_eq!(expected_hash, hash);
 }
+
+#[test]
+fn test_mpt_hash_leaf_values() {
+	// Values are stored in their leaf or branch however long they are & a leaf with an empty path
+	// under a branch is still encoded as a leaf. The roots are those of the standard Ethereum trie.
+	let mut mpt = MPT::default();
+	mpt.insert("a".into(), vec![b'x'; 40]).unwrap();
+	mpt.insert("b".into(), "short".into()).unwrap();
+	assert_eq!(
+		mpt.hash(),
+		hash_literal!("5700cbf0e0fce130c6359cd2ee9a291aad481aa1966da2c1d08ec4f7639f0bd1")
+	);
+
+	mpt.insert("bc".into(), vec![b'y'; 32]).unwrap();
+	let root = mpt.hash();
+	assert_eq!(
+		root,
+		hash_literal!("e8d6d1787c913b4e10415a217defcd56c3513c9ae60b18b27b38b4a024fc9d08")
+	);
+
+	let partial = MPT::from_db(root, mpt.db);
+	assert_eq!(partial.get("a".into()), Ok(Some(vec![b'x'; 40])));
+	assert_eq!(partial.get("b".into()), Ok(Some("short".into())));
+	assert_eq!(partial.get("bc".into()), Ok(Some(vec![b'y'; 32])));
+}
+
+#[test]
+fn test_mpt_diff() {
+	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	let mut a = MPT::default();
+	let mut b = MPT::default();
+	for (k, v) in inputs.iter() {
+		a.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+		b.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	assert_eq!(diff(&a, &b), Ok(Vec::new()));
+
+	b.insert("doge".into(), "moon".into()).unwrap();
+	b.insert("horses".into(), "herd".into()).unwrap();
+	let expected: Vec<(Vec<u8>, DiffKind)> = vec![
+		("doge".into(), DiffKind::Changed("coin".into(), "moon".into())),
+		("horses".into(), DiffKind::Added),
+	];
+	assert_eq!(diff(&a, &b), Ok(expected));
+
+	let expected: Vec<(Vec<u8>, DiffKind)> = vec![
+		("doge".into(), DiffKind::Changed("moon".into(), "coin".into())),
+		("horses".into(), DiffKind::Removed),
+	];
+	assert_eq!(diff(&b, &a), Ok(expected));
+}
+
+#[test]
+fn test_first_diverging_key() {
+	let inputs = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	let build = || {
+		let mut mpt = MPT::default();
+		for (k, v) in inputs.iter() {
+			mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+		}
+		mpt
+	};
+	let a = build();
+	let mut b = build();
+	assert_eq!(first_diverging_key(&a, &b), Ok(None));
+
+	b.insert("horse".into(), "pony".into()).unwrap();
+	b.insert("doge".into(), "moon".into()).unwrap();
+	assert_eq!(first_diverging_key(&a, &b), Ok(Some("doge".into())));
+
+	// Tries that are only referenced by hash are resolved from their dbs.
+	let mut c = build();
+	c.insert("horse".into(), "pony".into()).unwrap();
+	let c = MPT::from_db(c.hash(), c.db);
+	let mut a = build();
+	let a = MPT::from_db(a.hash(), a.db);
+	assert_eq!(first_diverging_key(&a, &c), Ok(Some("horse".into())));
+	assert_eq!(first_diverging_key(&c, &c), Ok(None));
+}
+
+#[test]
+fn test_diff_skips_cached_subtrees() {
+	// The root branch holds the branch of "dog" & "dug" and the embedded leaf of "h".
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let build = || {
+		let mut mpt = MPT::default();
+		for k in ["dog", "dug"] {
+			mpt.insert(k.into(), long(k)).unwrap();
+		}
+		mpt.insert("h".into(), "x".into()).unwrap();
+		mpt
+	};
+	let mut changed = build();
+	changed.insert("h".into(), "y".into()).unwrap();
+	let root = changed.hash();
+	// The witness of "h" does not contain the branch of "dog" & "dug".
+	let witness: HashMap<Hash, Vec<u8>> = changed.prove(b"h").unwrap().into_iter().map(|n| (Keccak::hash(&n), n)).collect();
+	let b = MPT::from_db(root, witness);
+	let expected = vec![(b"h".to_vec(), DiffKind::Changed("x".into(), "y".into()))];
+
+	// Without a cached hash the branch has to be resolved from the witness.
+	let mut a = build();
+	assert!(matches!(diff(&a, &b), Err(TrieError::MissingNode(_))));
+	a.hash();
+	assert_eq!(diff(&a, &b), Ok(expected));
+	let expected = vec![(b"h".to_vec(), DiffKind::Changed("y".into(), "x".into()))];
+	assert_eq!(diff(&b, &a), Ok(expected));
+
+	// Comparing the roots caches the hashes of the branches below them.
+	let a = build();
+	assert_eq!(first_diverging_key(&a, &b), Ok(Some("h".into())));
+	assert_eq!(first_diverging_key(&b, &a), Ok(Some("h".into())));
+}
+
+#[test]
+fn test_mpt_from_db() {
+	let mut mpt = MPT::default();
+	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	let root = mpt.hash();
+
+	let partial = MPT::from_db(root, mpt.db.clone());
+	assert!(matches!(partial.root, Node::Hash(..)));
+	for (k, v) in inputs {
+		assert_eq!(partial.get(k.into()), Ok(Some(v.into())));
+	}
+	assert_eq!(partial.get("dogf".into()), Ok(None));
+
+	let missing = MPT::from_db(root, HashMap::new());
+	assert_eq!(missing.get("do".into()), Err(TrieError::MissingNode(root)));
+	assert_eq!(MPT::from_db(EMPTY_TRIE_ROOT, HashMap::new()).get("do".into()), Ok(None));
+}
+
+#[test]
+fn test_mpt_insert_into_db_trie() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let mut mpt = MPT::default();
+	for k in ["do", "dog", "doge", "horse", "hound"] {
+		mpt.insert(k.into(), long(k)).unwrap();
+	}
+	let root = mpt.hash();
+
+	// The hash nodes on the paths of the keys are resolved from the db, the others stay unresolved.
+	let mut partial = MPT::from_db(root, mpt.db.clone());
+	partial.insert("dogs".into(), long("dogs")).unwrap();
+	partial.insert("do".into(), long("verb")).unwrap();
+	partial.insert("horsefly".into(), long("horsefly")).unwrap();
+	for k in ["do", "dog", "dogs", "doge", "horse", "horsefly", "hound"] {
+		let v = if k == "do" { long("verb") } else { long(k) };
+		mpt.insert(k.into(), v).unwrap();
+	}
+	assert_eq!(partial.hash(), mpt.hash());
+	assert_eq!(partial.get("hound".into()), Ok(Some(long("hound"))));
+
+	// A missing node is reported & leaves the trie untouched.
+	let mut missing = MPT::from_db(root, HashMap::new());
+	assert_eq!(
+		missing.insert("do".into(), long("verb")),
+		Err(InsertError::Trie(TrieError::MissingNode(root)))
+	);
+	assert_eq!(missing.hash(), root);
+}
+
+#[test]
+fn test_mpt_get_ref() {
+	let mut mpt = MPT::default();
+	let inputs = vec![("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	for (k, v) in inputs.iter() {
+		mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+	}
+	for (k, v) in inputs.iter() {
+		assert_eq!(mpt.get_ref(k.as_bytes()), Ok(Some(v.as_bytes())));
+	}
+	assert_eq!(mpt.get_ref(b"dogf"), Ok(None));
+
+	// Both calls must point at the bytes stored in the trie rather than a copy.
+	let a = mpt.get_ref(b"horse").unwrap().unwrap().as_ptr();
+	let b = mpt.get_ref(b"horse").unwrap().unwrap().as_ptr();
+	assert!(std::ptr::eq(a, b));
+
+	// Values behind hash nodes are resolved from the db.
+	let root = mpt.hash();
+	let mut partial = MPT::from_db(root, mpt.db.clone());
+	for (k, v) in inputs {
+		assert_eq!(partial.get_ref(k.as_bytes()), Ok(Some(v.as_bytes())));
+	}
+	assert_eq!(partial.get_ref(b"dogf"), Ok(None));
+	let mut missing = MPT::from_db(root, HashMap::new());
+	assert_eq!(missing.get_ref(b"do"), Err(TrieError::MissingNode(root)));
+}
+
+#[test]
+fn test_extension_compact_cache() {
+	let mut mpt = MPT::default();
+	mpt.insert("do".into(), "verb".into()).unwrap();
+	mpt.insert("dog".into(), "puppy".into()).unwrap();
+	let node = match &mpt.root {
+		Node::Extension(node) => node,
+		_ => panic!("expected the root to be an extension node"),
+	};
+	assert!(node.compact.get().is_none());
+	let expected = nibbles_to_compact(&node.nibbles, true);
+	assert_eq!(node.compact(), expected);
+	assert_eq!(node.compact.get(), Some(&expected));
+	// Hashing & formatting reuse the cached encoding.
+	assert!(!format!("{:?}", mpt).is_empty());
+	mpt.hash();
+	let node = match &mpt.root {
+		Node::Extension(node) => node,
+		_ => panic!("expected the root to be an extension node"),
+	};
+	assert_eq!(node.compact(), expected);
+}
+
+#[test]
+fn test_mpt_delete_prefix() {
+	let mut mpt = MPT::default();
+	for (k, v) in [
+		("do", "verb"),
+		("dog", "puppy"),
+		("doge", "coin"),
+		("horse", "stallion"),
+		("hound", "dog"),
+	] {
+		mpt.insert(k.into(), v.into()).unwrap();
+	}
+	assert_eq!(mpt.delete_prefix(b"cat"), Ok(0));
+	assert_eq!(mpt.delete_prefix(b"dog"), Ok(2));
+	assert_eq!(mpt.get("do".into()), Ok(Some("verb".into())));
+	assert_eq!(mpt.get("dog".into()), Ok(None));
+	assert_eq!(mpt.get("doge".into()), Ok(None));
+	assert_eq!(mpt.get("horse".into()), Ok(Some("stallion".into())));
+
+	// The remaining trie must be identical to one which never held the removed keys.
+	let mut expected = MPT::default();
+	for (k, v) in [("do", "verb"), ("horse", "stallion"), ("hound", "dog")] {
+		expected.insert(k.into(), v.into()).unwrap();
+	}
+	assert_eq!(mpt.hash(), expected.hash());
+
+	assert_eq!(mpt.delete_prefix(b""), Ok(3));
+	assert_eq!(mpt.hash(), EMPTY_TRIE_ROOT);
+}
+
+#[test]
+fn test_mpt_delete_prefix_from_db() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let trie = |keys: &[&str]| {
+		let mut mpt = MPT::default();
+		for k in keys {
+			mpt.insert(k.as_bytes().to_vec(), long(k)).unwrap();
+		}
+		let root = mpt.hash();
+		(root, mpt.db)
+	};
+	// The branch collapses into the remaining hash node, a leaf in the first case & an extension in the
+	// second case. Its path is merged into the new node.
+	for (keys, remaining) in [
+		(&["dog", "horse"][..], &["horse"][..]),
+		(&["dog", "horse", "hound"][..], &["horse", "hound"][..]),
+	] {
+		let (root, db) = trie(keys);
+		let mut partial = MPT::from_db(root, db);
+		assert_eq!(partial.delete_prefix(b"dog"), Ok(1));
+		assert_eq!(partial.hash(), trie(remaining).0);
+		assert_eq!(partial.get("horse".into()), Ok(Some(long("horse"))));
+	}
+
+	// The root is an extension to the branch that holds the nodes of "dog" & "horse". Both are needed
+	// to delete "dog" & the trie is left unchanged if either is missing.
+	let (root, db) = trie(&["dog", "horse"]);
+	let branch = decode::references(&db[&root]).unwrap()[0];
+	for missing in decode::references(&db[&branch]).unwrap() {
+		let mut partial_db = db.clone();
+		partial_db.remove(&missing);
+		let mut partial = MPT::from_db(root, partial_db);
+		assert_eq!(partial.delete_prefix(b"dog"), Err(TrieError::MissingNode(missing)));
+		assert_eq!(partial.hash(), root);
+	}
+}
+
+thread_local! {
+	static HASHER_CALLS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+// CountingHasher is keccak that counts how often it is called.
+struct CountingHasher;
+
+impl Hasher for CountingHasher {
+	fn hash(data: &[u8]) -> Hash {
+		HASHER_CALLS.with(|c| c.set(c.get() + 1));
+		Keccak::hash(data)
+	}
+}
+
+#[test]
+fn test_mpt_custom_hasher() {
+	let mut mpt = MPT::<CountingHasher>::with_hasher();
+	let mut expected = MPT::default();
+	for (k, v) in [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")] {
+		mpt.insert(k.into(), v.into()).unwrap();
+		expected.insert(k.into(), v.into()).unwrap();
+	}
+	assert_eq!(HASHER_CALLS.with(|c| c.get()), 0);
+	assert_eq!(mpt.hash(), expected.hash());
+	assert!(HASHER_CALLS.with(|c| c.get()) > 0);
+}
+
+#[test]
+fn test_branch_insert_modes() {
+	let branch = || BranchNode::new_with_value(ValueNode::new("old".into()));
+	let value = |node: Node| match node {
+		Node::Branch(node) => node.branch_value.unwrap().value,
+		_ => panic!("expected a branch node"),
+	};
+	assert_eq!(
+		value(branch().insert(NibblePath::new(&[]), "new".into(), InsertMode::Insert)),
+		b"old"
+	);
+	assert_eq!(
+		value(branch().insert(NibblePath::new(&[]), "new".into(), InsertMode::Upsert)),
+		b"new"
+	);
+
+	let mut mpt = MPT::default();
+	mpt.insert("do".into(), "verb".into()).unwrap();
+	mpt.insert("dog".into(), "puppy".into()).unwrap();
+	mpt.insert_with_mode("do".into(), "noun".into(), InsertMode::Insert).unwrap();
+	assert_eq!(mpt.get("do".into()), Ok(Some("verb".into())));
+	mpt.insert_with_mode("do".into(), "noun".into(), InsertMode::Upsert).unwrap();
+	assert_eq!(mpt.get("do".into()), Ok(Some("noun".into())));
+}
+
+#[test]
+fn test_mpt_root_eq() {
+	let build = |keys: &[(&str, &str)]| {
+		let mut mpt = MPT::default();
+		for (k, v) in keys {
+			mpt.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec()).unwrap();
+		}
+		mpt
+	};
+	let keys = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")];
+	let mut a = build(&keys);
+	let b = build(&keys);
+	assert!(a.root_eq(&b));
+	assert_eq!(a.root_hash(), a.hash());
+
+	let c = build(&keys[..3]);
+	assert!(!a.root_eq(&c));
+	a.delete_prefix(b"horse").unwrap();
+	assert!(a.root_eq(&c));
+	a.insert("horse".into(), "mare".into()).unwrap();
+	assert!(!a.root_eq(&b));
+}
+
+#[test]
+fn test_compute_root() {
+	let pairs: Vec<(Vec<u8>, Vec<u8>)> = [("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")]
+		.iter()
+		.map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+		.collect();
+	let mut mpt = MPT::default();
+	for (k, v) in pairs.clone() {
+		mpt.insert(k, v).unwrap();
+	}
+	assert_eq!(compute_root(pairs), mpt.hash());
+	assert_eq!(compute_root(Vec::new()), EMPTY_TRIE_ROOT);
+}
+
+#[test]
+fn test_reachable_hashes() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let mut mpt = MPT::default();
+	for k in ["do", "dog", "doge", "horse", "hound"] {
+		mpt.insert(k.into(), long(k)).unwrap();
+	}
+	// The modified nodes are reachable before they are committed to the db.
+	let uncommitted = mpt.reachable_hashes();
+	assert!(mpt.db.is_empty());
+	mpt.hash();
+	let reachable = mpt.reachable_hashes();
+	assert_eq!(reachable, uncommitted);
+	assert_eq!(reachable, mpt.db.keys().copied().collect());
+
+	// A partial trie finds the same nodes through the db.
+	let partial = MPT::from_db(mpt.hash(), mpt.db.clone());
+	assert_eq!(partial.reachable_hashes(), reachable);
+	assert_eq!(MPT::default().reachable_hashes(), HashSet::from([EMPTY_TRIE_ROOT]));
+}
+
+#[test]
+fn test_mpt_prune_db() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let mut mpt = MPT::default();
+	for k in ["do", "dog", "doge", "horse", "hound"] {
+		mpt.insert(k.into(), long(k)).unwrap();
+	}
+	mpt.hash();
+	assert_eq!(mpt.prune_db(), 0);
+	mpt.delete_prefix(b"dog").unwrap();
+	mpt.hash();
+
+	let mut expected = MPT::default();
+	for k in ["do", "horse", "hound"] {
+		expected.insert(k.into(), long(k)).unwrap();
+	}
+	expected.hash();
+	let before = mpt.db.len();
+	assert!(before > expected.db.len());
+	assert_eq!(mpt.prune_db(), before - expected.db.len());
+	let mut keys: Vec<_> = mpt.db.keys().collect();
+	let mut expected_keys: Vec<_> = expected.db.keys().collect();
+	keys.sort_by_key(|h| h.to_vec());
+	expected_keys.sort_by_key(|h| h.to_vec());
+	assert_eq!(keys, expected_keys);
+}
+
+#[test]
+fn test_mpt_shared_db() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let mut account = MPT::from_shared_db(EMPTY_TRIE_ROOT, SharedDb::default());
+	let mut storage = MPT::from_shared_db(EMPTY_TRIE_ROOT, account.shared_db());
+	for k in ["do", "dog", "doge", "horse"] {
+		account.insert(k.into(), long(k)).unwrap();
+		storage.insert(k.into(), long(k)).unwrap();
+	}
+	storage.insert("hound".into(), long("hound")).unwrap();
+	let account_root = account.hash();
+	let len = account.db.lock().unwrap().len();
+	let storage_root = storage.hash();
+
+	// Only the nodes that differ between the tries are added by the second commit.
+	let mut alone = MPT::default();
+	for k in ["do", "dog", "doge", "horse", "hound"] {
+		alone.insert(k.into(), long(k)).unwrap();
+	}
+	alone.hash();
+	assert!(account.db.lock().unwrap().len() < len + alone.db.len());
+
+	let db = account.shared_db();
+	assert_eq!(
+		MPT::from_shared_db(account_root, db.clone()).get("dog".into()),
+		Ok(Some(long("dog")))
+	);
+	assert_eq!(MPT::from_shared_db(storage_root, db).get("hound".into()), Ok(Some(long("hound"))));
+}
+
+#[test]
+fn test_mpt_shared_db_prune() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let mut account = MPT::from_shared_db(EMPTY_TRIE_ROOT, SharedDb::default());
+	let mut storage = MPT::from_shared_db(EMPTY_TRIE_ROOT, account.shared_db());
+	for k in ["do", "dog", "doge"] {
+		account.insert(k.into(), long(k)).unwrap();
+		storage.insert(k.into(), long(&k.to_uppercase())).unwrap();
+	}
+	account.hash();
+	let storage_root = storage.hash();
+	// The second commit leaves the nodes of the first account root stale.
+	account.insert("horse".into(), long("horse")).unwrap();
+	let account_root = account.hash();
+
+	assert!(account.prune_db(&[storage_root]) > 0);
+	let db = account.shared_db();
+	assert_eq!(
+		MPT::from_shared_db(storage_root, db.clone()).get("dog".into()),
+		Ok(Some(long("DOG")))
+	);
+	assert_eq!(account.prune_db(&[storage_root]), 0);
+
+	// Without the storage root, the nodes of the storage trie are removed.
+	assert!(account.prune_db(&[]) > 0);
+	assert!(MPT::from_shared_db(storage_root, db.clone()).get("dog".into()).is_err());
+	assert_eq!(MPT::from_shared_db(account_root, db).get("horse".into()), Ok(Some(long("horse"))));
+}
+
+// CountingDb is an in-memory db that counts the calls that go through the NodeDb trait.
+#[derive(Default)]
+struct CountingDb {
+	nodes: HashMap<Hash, Vec<u8>>,
+	gets: std::cell::Cell<usize>,
+	puts: usize,
+}
+
+impl NodeDb for CountingDb {
+	fn get(&self, h: &Hash) -> Option<Vec<u8>> {
+		self.gets.set(self.gets.get() + 1);
+		self.nodes.get(h).cloned()
+	}
+
+	fn put(&mut self, h: Hash, v: Vec<u8>) {
+		self.puts += 1;
+		self.nodes.insert(h, v);
+	}
+}
+
+#[test]
+fn test_mpt_custom_node_db() {
+	let long = |s: &str| s.repeat(20).into_bytes();
+	let mut mpt = MPT::<Keccak, CountingDb>::from_node_db(EMPTY_TRIE_ROOT, CountingDb::default());
+	for k in ["do", "dog", "doge", "horse"] {
+		mpt.insert(k.into(), long(k)).unwrap();
+	}
+	let root = mpt.hash();
+	assert!(mpt.db.puts > 1);
+	assert_eq!(mpt.db.puts, mpt.db.nodes.len());
+	assert_eq!(mpt.db.gets.get(), 0);
+
+	let partial = MPT::<Keccak, CountingDb>::from_node_db(root, mpt.db);
+	assert_eq!(partial.get("dog".into()), Ok(Some(long("dog"))));
+	assert!(partial.db.gets.get() > 0);
+}
+
+#[test]
+fn test_decode_branch_node() {
+	let hash = Hash::new([0x11; 32]);
+	let mut raw = vec![0xa0];
+	raw.extend_from_slice(&hash.to_vec());
+	// An embedded leaf with an empty path & the value "hi".
+	raw.extend_from_slice(&[0xc4, 0x20, 0x82, b'h', b'i']);
+	raw.extend_from_slice(&[0x80; 14]);
+	raw.extend_from_slice(&[0x83, b'v', b'a', b'l']);
+	let mut node = vec![0xf8, raw.len() as u8];
+	node.extend_from_slice(&raw);
+
+	let Node::Branch(branch) = decode::decode_node(&node).unwrap() else {
+		panic!("expected a branch node");
+	};
+	assert!(matches!(*branch.children[0], Node::Hash(h) if h == hash));
+	assert!(matches!(&*branch.children[1], Node::Value(v) if v.value == b"hi"));
+	assert!(branch.children[2..].iter().all(|c| matches!(**c, Node::Empty)));
+	assert_eq!(branch.branch_value.map(|v| v.value), Some(b"val".to_vec()));
+
+	// An embedded node that is as long as a hash must have been stored by hash.
+	let mut raw = vec![0xe0, 0x20, 0x9e];
+	raw.extend_from_slice(&[0x42; 30]);
+	raw.extend_from_slice(&[0x80; 16]);
+	let mut node = vec![0xc0 + raw.len() as u8];
+	node.extend_from_slice(&raw);
+	assert!(matches!(decode::decode_node(&node), Err(TrieError::InvalidNode)));
+}
+
+fn witness_trie() -> (Hash, Vec<Vec<u8>>) {
+	let mut mpt = MPT::default();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i], vec![i; 32]).unwrap();
+	}
+	let root = mpt.hash();
+	(root, mpt.db.values().cloned().collect())
+}
+
+#[test]
+fn test_verify_get() {
+	let (root, proof) = witness_trie();
+	assert_eq!(verify_get::<Keccak>(root, &[0x12], &proof), Ok(Some(vec![0x12; 32])));
+	assert_eq!(verify_get::<Keccak>(root, &[0x12, 0x34], &proof), Ok(None));
+	assert_eq!(verify_get::<Keccak>(EMPTY_TRIE_ROOT, &[0x12], &[]), Ok(None));
+}
+
+#[test]
+fn test_verify_get_missing_node() {
+	let (root, proof) = witness_trie();
+	let mut missing = 0;
+	for i in 0..proof.len() {
+		let mut partial = proof.clone();
+		let removed = Keccak::hash(&partial.remove(i));
+		match verify_get::<Keccak>(root, &[0x12], &partial) {
+			// Nodes that are not on the path to the key are not needed.
+			Ok(value) => assert_eq!(value, Some(vec![0x12; 32])),
+			Err(err) => {
+				assert_eq!(err, ProofError::MissingNode(removed));
+				missing += 1;
+			}
+		}
+	}
+	// The root, the intermediate branch, the leaf & its value are all required.
+	assert!(missing >= 3, "only {missing} nodes were required");
+}
+
+// ReversedKeccak is a hasher other than keccak whose hashes are the reversed keccak hashes.
+struct ReversedKeccak;
+
+impl Hasher for ReversedKeccak {
+	fn hash(data: &[u8]) -> Hash {
+		let mut hash = Keccak::hash(data).to_vec();
+		hash.reverse();
+		Hash::try_from(hash.as_slice()).unwrap()
+	}
+}
+
+#[test]
+fn test_verify_with_hasher() {
+	let mut mpt = MPT::<ReversedKeccak>::with_hasher();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i], vec![i; 32]).unwrap();
+	}
+	let root = mpt.hash();
+	let proof = mpt.prove(&[0x12]).unwrap();
+	assert_eq!(verify_get::<ReversedKeccak>(root, &[0x12], &proof), Ok(Some(vec![0x12; 32])));
+	assert_eq!(
+		verify_multiproof::<ReversedKeccak>(root, &[(vec![0x12], proof.clone())]),
+		Ok(vec![Some(vec![0x12; 32])])
+	);
+	// The keccak hashes of the witness do not match the references of the trie.
+	assert!(verify_get::<Keccak>(root, &[0x12], &proof).is_err());
+}
+
+// RecordingDb records the hashes of the nodes that are read from it.
+struct RecordingDb {
+	inner: HashMap<Hash, Vec<u8>>,
+	reads: std::cell::RefCell<Vec<Hash>>,
+}
+
+impl NodeDb for RecordingDb {
+	fn get(&self, h: &Hash) -> Option<Vec<u8>> {
+		self.reads.borrow_mut().push(*h);
+		self.inner.get(h).cloned()
+	}
+
+	fn put(&mut self, h: Hash, v: Vec<u8>) {
+		self.inner.insert(h, v);
+	}
+}
+
+#[test]
+fn test_verify_multiproof() {
+	let (root, witness) = witness_trie();
+	let db: HashMap<Hash, Vec<u8>> = witness.iter().map(|node| (Keccak::hash(node), node.clone())).collect();
+	// The proof of a key holds exactly the nodes that a lookup of it reads.
+	let proof = |key: &[u8]| {
+		let mpt = MPT::<Keccak, _>::from_node_db(
+			root,
+			RecordingDb {
+				inner: db.clone(),
+				reads: Default::default(),
+			},
+		);
+		mpt.get(key.to_vec()).unwrap();
+		let reads = mpt.db.reads.take();
+		reads.iter().map(|h| db[h].clone()).collect::<Vec<_>>()
+	};
+	let keys = [vec![0x12], vec![0x13], vec![0xf0], vec![0x12, 0x34]];
+	let items: Vec<(Vec<u8>, Vec<Vec<u8>>)> = keys.iter().map(|k| (k.clone(), proof(k))).collect();
+	// All proofs share the root node.
+	assert!(items.iter().all(|(_, p)| p.contains(&db[&root])));
+
+	assert_eq!(
+		verify_multiproof::<Keccak>(root, &items),
+		Ok(vec![Some(vec![0x12; 32]), Some(vec![0x13; 32]), Some(vec![0xf0; 32]), None])
+	);
+	for (key, proof) in items.iter() {
+		assert_eq!(
+			verify_get::<Keccak>(root, key, proof),
+			verify_multiproof::<Keccak>(root, &[(key.clone(), proof.clone())]).map(|v| v[0].clone())
+		);
+	}
+	assert_eq!(
+		verify_multiproof::<Keccak>(root, &[(vec![0x12], Vec::new())]),
+		Err(ProofError::MissingNode(root))
+	);
+	assert_eq!(verify_multiproof::<Keccak>(root, &[]), Ok(Vec::new()));
+
+	// A witness cannot rely on the nodes of the other witnesses.
+	let (_, shared) = &items[0];
+	let (key, proof) = &items[1];
+	let missing = proof.iter().find(|node| shared.contains(*node) && **node != db[&root]).unwrap();
+	let partial: Vec<Vec<u8>> = proof.iter().filter(|node| *node != missing).cloned().collect();
+	assert_eq!(
+		verify_multiproof::<Keccak>(root, &[items[0].clone(), (key.clone(), partial)]),
+		Err(ProofError::MissingNode(Keccak::hash(missing)))
+	);
+}
+
+#[test]
+fn test_verify_value_changed() {
+	let (old_root, old_proof) = witness_trie();
+	let mut mpt = MPT::default();
+	for i in 0..=255u8 {
+		mpt.insert(vec![i], vec![i; 32]).unwrap();
+	}
+	mpt.insert(vec![0x12], vec![0xff; 32]).unwrap();
+	let new_root = mpt.hash();
+	let new_proof: Vec<Vec<u8>> = mpt.db.values().cloned().collect();
+
+	assert_eq!(
+		verify_value_changed::<Keccak>(&[0x12], old_root, &old_proof, new_root, &new_proof),
+		Ok(true)
+	);
+	assert_eq!(
+		verify_value_changed::<Keccak>(&[0x13], old_root, &old_proof, new_root, &new_proof),
+		Ok(false)
+	);
+	assert_eq!(
+		verify_value_changed::<Keccak>(&[0x12], old_root, &old_proof, old_root, &old_proof),
+		Ok(false)
+	);
+	assert_eq!(
+		verify_value_changed::<Keccak>(&[0x12], old_root, &old_proof, new_root, &[]),
+		Err(ProofError::MissingNode(new_root))
+	);
+}
+
+#[test]
+fn test_prove_account() {
+	let address = address_literal!("4444444444444444444444444444444444444444");
+	let mut storage = MPT::default();
+	let slots: Vec<Hash> = (1..=40u8).map(|i| Hash::new([i; 32])).collect();
+	for (i, slot) in slots.iter().enumerate() {
+		storage.insert_secure(&slot.to_vec(), encode_bytes(vec![i as u8 + 1; 4])).unwrap();
+	}
+	let account = AccountState {
+		nonce: 7,
+		storage_root: storage.hash(),
+		..Default::default()
+	};
+	let mut state = MPT::default();
+	for i in 0..40u8 {
+		state.insert_account(Address::new([i; 20]), &AccountState::default()).unwrap();
+	}
+	state.insert_account(address, &account).unwrap();
+	assert_eq!(AccountState::decode(&account.rlp_bytes()), Some(account));
+
+	let missing = Hash::new([0xff; 32]);
+	let proof = prove_account(&mut state, &mut storage, address, &[slots[3], missing]).unwrap();
+	assert_eq!(proof.account, Some(account));
+	let account_key = Keccak::hash(&address.to_vec()).to_vec();
+	assert_eq!(
+		verify_get::<Keccak>(state.hash(), &account_key, &proof.account_proof),
+		Ok(Some(account.rlp_bytes()))
+	);
+	assert_eq!(proof.storage_proofs.len(), 2);
+	for storage_proof in &proof.storage_proofs {
+		let slot = Keccak::hash(&storage_proof.key.to_vec()).to_vec();
+		assert_eq!(
+			verify_get::<Keccak>(account.storage_root, &slot, &storage_proof.proof),
+			Ok(storage_proof.value.clone())
+		);
+	}
+	assert_eq!(proof.storage_proofs[0].value, Some(encode_bytes(vec![4; 4])));
+	assert_eq!(proof.storage_proofs[1].value, None);
+
+	// A storage trie that does not belong to the account is rejected.
+	let other = Address::new([1; 20]);
+	assert_eq!(
+		prove_account(&mut state, &mut storage, other, &[]),
+		Err(ProofError::StorageRootMismatch {
+			expected: EMPTY_TRIE_ROOT,
+			got: account.storage_root
+		})
+	);
+}
+
+#[test]
+fn test_prove_account_without_storage() {
+	let address = address_literal!("4444444444444444444444444444444444444444");
+	let mut state = MPT::default();
+	state.insert_account(address, &AccountState::default()).unwrap();
+	let mut storage = MPT::default();
+	let keys = [Hash::new([1; 32]), Hash::new([2; 32])];
+
+	let proof = prove_account(&mut state, &mut storage, address, &keys).unwrap();
+	assert_eq!(proof.account, Some(AccountState::default()));
+	assert_eq!(proof.storage_proofs.len(), 2);
+	for (storage_proof, key) in proof.storage_proofs.iter().zip(keys) {
+		assert_eq!(storage_proof.key, key);
+		assert_eq!(storage_proof.value, None);
+		assert!(storage_proof.proof.is_empty());
+		let slot = Keccak::hash(&key.to_vec()).to_vec();
+		assert_eq!(verify_get::<Keccak>(EMPTY_TRIE_ROOT, &slot, &storage_proof.proof), Ok(None));
+	}
+}
+
+#[test]
+fn test_apply_updates() {
+	let a = address_literal!("1111111111111111111111111111111111111111");
+	let b = address_literal!("2222222222222222222222222222222222222222");
+	let funded = AccountState {
+		nonce: 1,
+		balance: reth_primitives::U256::from(1_000_000_000_000_000_000u64),
+		..Default::default()
+	};
+	let funded_rlp = hex::decode(concat!(
+		"f84c01880de0b6b3a7640000",
+		"a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+		"a0c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+	))
+	.unwrap();
+	assert_eq!(funded.rlp_bytes(), funded_rlp);
+	let empty_rlp = [&hex::decode("f8448080").unwrap()[..], &funded_rlp[12..]].concat();
+	assert_eq!(AccountState::default().rlp_bytes(), empty_rlp);
+
+	// The roots are those of the standard secure state trie with these accounts.
+	let mut mpt = MPT::default();
+	let root = mpt
+		.apply_updates(vec![(a, Some(funded)), (b, Some(AccountState::default()))])
+		.unwrap();
+	assert_eq!(
+		root,
+		hash_literal!("3e8f1452320c00e5e3f815b8961a510c04c166928164a2e54e506c0f16312f58")
+	);
+
+	let root = mpt.apply_updates(vec![(b, None)]).unwrap();
+	assert_eq!(
+		root,
+		hash_literal!("b3e58fdbabd36dfe1519fdc910e208791c4d3fd0153ee4b650be5112ddcf41c6")
+	);
+	assert_eq!(mpt.apply_updates(vec![(a, None)]), Ok(EMPTY_TRIE_ROOT));
+}
+
+#[test]
+fn test_apply_updates_from_db() {
+	let a = address_literal!("1111111111111111111111111111111111111111");
+	let b = address_literal!("2222222222222222222222222222222222222222");
+	let c = address_literal!("3333333333333333333333333333333333333333");
+	let funded = AccountState {
+		nonce: 1,
+		balance: reth_primitives::U256::from(1_000_000_000_000_000_000u64),
+		..Default::default()
+	};
+	let empty = AccountState::default();
+	let mut mpt = MPT::default();
+	let root = mpt
+		.apply_updates(vec![(a, Some(funded)), (b, Some(empty)), (c, Some(empty))])
+		.unwrap();
+	assert_eq!(
+		root,
+		hash_literal!("d301d9b361d51e4b7e24038b477db3846428e5ac0b36559bf7d6372592811fe7")
+	);
+
+	// The partial trie only holds its root by hash. The updates decode the nodes they modify & the
+	// deletes resolve the leaf that the root collapses into.
+	let mut partial = MPT::from_db(root, mpt.db.clone());
+	let root = partial.apply_updates(vec![(a, Some(empty)), (b, None), (c, Some(funded))]).unwrap();
+	assert_eq!(
+		root,
+		hash_literal!("0136c493c39506e47d63a9b04dc974b399a5ce7cf51f6540f7cb70bd08f5b19a")
+	);
+	let root = partial.apply_updates(vec![(c, None)]).unwrap();
+	assert_eq!(
+		root,
+		hash_literal!("03c3200d0b3e2a02d26b6c5032ee5477b05a5ef6bdf6130d6c1a0e694679fbec")
+	);
+
+	// A db without the nodes of the trie errors instead of panicking.
+	let mut missing = MPT::from_db(root, HashMap::new());
+	assert_eq!(
+		missing.apply_updates(vec![(b, Some(funded))]),
+		Err(InsertError::Trie(TrieError::MissingNode(root)))
+	);
+	assert_eq!(missing.hash(), root);
+}
+
+#[test]
+fn test_insert_account() {
+	let address = address_literal!("3333333333333333333333333333333333333333");
+	// Both integers need their leading zero bytes trimmed & 0x80 is no longer a single byte encoding.
+	let account = AccountState {
+		nonce: 0x0100,
+		balance: reth_primitives::U256::from(0x80),
+		..Default::default()
+	};
+	let reference = hex::decode(concat!(
+		"f8478201008180",
+		"a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+		"a0c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+	))
+	.unwrap();
+	assert_eq!(account.rlp_bytes(), reference);
+
+	let key = Keccak::hash(&address.to_vec()).to_vec();
+	let mut mpt = MPT::default();
+	mpt.insert_account(address, &account).unwrap();
+	assert_eq!(mpt.get(key.clone()), Ok(Some(reference)));
+	assert_eq!(
+		mpt.hash(),
+		hash_literal!("d4ad586e95db567c70b21e691e85e76f189f855ac176ccf85e7e8ecaf5cfa067")
+	);
+
+	mpt.insert_account(address, &AccountState::default()).unwrap();
+	assert_eq!(mpt.get(key), Ok(Some(AccountState::default().rlp_bytes())));
+}
+
+#[test]
+fn test_mpt_walk() {
+	let mut mpt = MPT::default();
+	let keys: [&[u8]; 4] = [b"do", b"dog", b"doge", b"horse"];
+	for key in keys {
+		mpt.insert(key.to_vec(), key.to_vec()).unwrap();
+	}
+	let mpt = MPT::from_db(mpt.hash(), mpt.db);
+
+	let mut values = Vec::new();
+	mpt.walk(&mut |path, node| {
+		match node {
+			WalkNode::Value(value) => values.push((path.to_vec(), value.to_vec())),
+			WalkNode::Branch { value: Some(value) } => values.push((path.to_vec(), value.to_vec())),
+			_ => {}
+		}
+		WalkControl::Continue
+	})
+	.unwrap();
+	let expected: Vec<(Vec<u8>, Vec<u8>)> = keys.iter().map(|k| (bytes_to_nibbles(k), k.to_vec())).collect();
+	assert_eq!(values, expected);
+
+	// Skipping the subtree under "d" only leaves "horse".
+	let mut seen = Vec::new();
+	mpt.walk(&mut |path, node| {
+		if matches!(node, WalkNode::Value(..)) {
+			seen.push(path.to_vec());
+		}
+		if path == [6, 4] {
+			WalkControl::SkipSubtree
+		} else {
+			WalkControl::Continue
+		}
+	})
+	.unwrap();
+	assert_eq!(seen, vec![bytes_to_nibbles(b"horse")]);
+
+	let mut visited = 0;
+	mpt.walk(&mut |_, node| {
+		visited += 1;
+		if matches!(node, WalkNode::Value(..) | WalkNode::Branch { value: Some(..) }) {
+			WalkControl::Stop
+		} else {
+			WalkControl::Continue
+		}
+	})
+	.unwrap();
+	// The root extension, the branch under it & the extension to the "do" branch come first.
+	assert_eq!(visited, 4);
+
+	// A partial trie ends the walk at the first missing node.
+	let root = mpt.root_hash();
+	let partial = MPT::from_db(root, HashMap::new());
+	assert_eq!(partial.walk(&mut |_, _| WalkControl::Continue), Err(TrieError::MissingNode(root)));
+}
+
+#[test]
+fn test_mpt_estimate_db_size() {
+	let mut mpt = MPT::default();
+	assert_eq!(mpt.estimate_db_size(), 1);
+	for i in 0..100u8 {
+		mpt.insert(vec![i, i], vec![i; (i % 40) as usize + 1]).unwrap();
+	}
+	let estimate = mpt.estimate_db_size();
+	mpt.hash();
+	assert_eq!(estimate, mpt.db.values().map(Vec::len).sum::<usize>());
+}
+
+#[test]
+fn test_mpt_root_hash_skips_db() {
+	let mut mpt = MPT::default();
+	for i in 0..100u8 {
+		mpt.insert(vec![i, i], vec![i; (i % 40) as usize + 1]).unwrap();
+	}
+	let root = mpt.root_hash();
+	assert!(mpt.db.is_empty());
+	assert_eq!(root, mpt.hash());
+	assert!(!mpt.db.is_empty());
+}
+
+#[test]
+fn test_value_node_hash_cache() {
+	let mut db = HashMap::<Hash, Vec<u8>>::new();
+	let mut node = ValueNode::new(vec![1; 40]);
+	assert!(node.hash.get().is_none());
+	node.reference::<Keccak>(&mut db);
+	let hash = *node.hash.get().unwrap();
+	assert_eq!(hash, Keccak::hash(&node.rlp_bytes(&mut db)));
+	assert!(db.contains_key(&hash));
+
+	node.set_value(vec![2; 40]);
+	assert!(node.hash.get().is_none());
+	node.reference::<Keccak>(&mut db);
+	assert_ne!(*node.hash.get().unwrap(), hash);
+
+	// Short values are embedded in their parent & are not hashed.
+	let short = ValueNode::new(vec![3; 4]);
+	short.reference::<Keccak>(&mut db);
+	assert!(short.hash.get().is_none());
+
+	// Overwriting a value through an upsert clears the cache as well.
+	let cached = Node::new_value(vec![1; 40]);
+	cached.reference::<Keccak>(&mut db);
+	match cached.insert(NibblePath::new(&[]), vec![2; 40], InsertMode::Upsert) {
+		Node::Value(node) => {
+			assert_eq!(node.value, vec![2; 40]);
+			assert!(node.hash.get().is_none());
+		}
+		_ => panic!("expected a value node"),
+	}
+}
+
+#[test]
+fn test_nibble_path() {
+	let nibbles = [1, 2, 3, 4];
+	let path = NibblePath::new(&nibbles);
+	assert_eq!(path.first(), Some(1));
+	assert_eq!(path.common_prefix_len(&[1, 2, 5]), 2);
+	assert_eq!(path.common_prefix_len(&[1, 2, 3, 4, 5]), 4);
+	let path = path.advance(3);
+	assert_eq!(path.as_slice(), [4]);
+	assert_eq!(path.common_prefix_len(&[5]), 0);
+	assert!(path.advance(1).is_empty());
+	assert_eq!(path.advance(1).first(), None);
+}
+
+#[test]
+fn test_insert_order_with_deep_extensions() {
+	// The keys share long prefixes & split extension nodes at every position.
+	let keys: Vec<Vec<u8>> = (0..64u8).map(|i| [vec![0xab; 20], vec![i & 0xf0, i & 0x0f, i]].concat()).collect();
+	let build = |keys: &[Vec<u8>]| {
+		let mut mpt = MPT::default();
+		for key in keys {
+			mpt.insert(key.clone(), key[20..].to_vec()).unwrap();
+		}
+		mpt
+	};
+	let mut forward = build(&keys);
+	let reversed: Vec<Vec<u8>> = keys.iter().rev().cloned().collect();
+	let interleaved: Vec<Vec<u8>> = keys.iter().step_by(2).chain(keys.iter().skip(1).step_by(2)).cloned().collect();
+	assert_eq!(forward.hash(), build(&reversed).hash());
+	assert_eq!(forward.hash(), build(&interleaved).hash());
+	// The roots are those of the standard Ethereum trie with the same keys.
+	assert_eq!(
+		forward.hash(),
+		hash_literal!("9f11cff66ebf5fec1435f72b50f54644d852469e088f825cbdd140570cfa3b56")
+	);
+	assert_eq!(
+		build(&keys[..32]).hash(),
+		hash_literal!("41d0eb6dbefe62c15ba4f833ffc801e878ea82c072e25ad8586e9dc3ec90f9cb")
+	);
+	for key in keys.iter() {
+		assert_eq!(forward.get(key.clone()), Ok(Some(key[20..].to_vec())));
+	}
+	assert_eq!(forward.get(vec![0xab; 20]), Ok(None));
+}
+
+#[test]
+fn test_empty_key_insert() {
+	// A raw trie stores the value of the empty key at the root.
+	let mut raw = MPT::default();
+	raw.insert(Vec::new(), b"root".to_vec()).unwrap();
+	assert!(matches!(raw.root, Node::Value(..)));
+	raw.insert(b"do".to_vec(), b"verb".to_vec()).unwrap();
+	assert_eq!(raw.get(Vec::new()), Ok(Some(b"root".to_vec())));
+	assert_eq!(raw.get(b"do".to_vec()), Ok(Some(b"verb".to_vec())));
+
+	let mut secure = MPT::default();
+	assert_eq!(secure.insert_secure(&[], b"root".to_vec()), Err(InsertError::EmptyKey));
+	assert_eq!(secure.hash(), EMPTY_TRIE_ROOT);
+	secure.insert_secure(b"do", b"verb".to_vec()).unwrap();
+	assert_eq!(secure.get(Keccak::hash(b"do").to_vec()), Ok(Some(b"verb".to_vec())));
+}
+
+#[test]
+fn test_from_state_dump() {
+	// The dump is the one that geth's TestDump in core/state checks, including the root.
+	let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/state_dump.json");
+	let mut mpt = MPT::from_state_dump(path).unwrap();
+	assert_eq!(
+		mpt.hash(),
+		hash_literal!("71edff0130dd2385947095001c73d9e28d862fc286fca2b922ca6f6f3cddfdd2")
+	);
+
+	let contract = address_literal!("0000000000000000000000000000000000000102");
+	let account = AccountState {
+		code_hash: hash_literal!("87874902497a5bb968da31a2998d8f22e949d1ef6214bcdedd8bae24cca4b9e3"),
+		..Default::default()
+	};
+	assert_eq!(
+		mpt.get(Keccak::hash(&contract.to_vec()).to_vec()).unwrap(),
+		Some(account.rlp_bytes())
+	);
+
+	let tampered = std::fs::read_to_string(path)
+		.unwrap()
+		.replace("\"balance\": \"22\"", "\"balance\": \"23\"");
+	let tampered_path = std::env::temp_dir().join(format!("mpt-tampered-state-dump-{}.json", std::process::id()));
+	std::fs::write(&tampered_path, tampered).unwrap();
+	let result = MPT::from_state_dump(&tampered_path);
+	std::fs::remove_file(&tampered_path).unwrap();
+	assert!(matches!(result, Err(DumpError::RootMismatch { .. })));
+}
+
+#[test]
+fn test_max_depth() {
+	// Keys that only differ in their last nibble collapse into one extension above a branch. A path can
+	// hold a node per nibble of its key though, so the keys are bounded by their length.
+	let mut mpt = MPT::default();
+	let long = vec![0x11; 100_000];
+	let mut other = long.clone();
+	other[long.len() - 1] = 0x12;
+	let exceeded = TrieError::MaxDepthExceeded {
+		depth: 200_000,
+		max_depth: DEFAULT_MAX_DEPTH,
+	};
+	assert_eq!(mpt.insert(other, b"value".to_vec()), Err(InsertError::Trie(exceeded.clone())));
+	assert_eq!(
+		mpt.insert_with_mode(long.clone(), b"value".to_vec(), InsertMode::Insert),
+		Err(InsertError::Trie(exceeded.clone()))
+	);
+	assert_eq!(mpt.get(long.clone()), Err(exceeded.clone()));
+	assert_eq!(mpt.get_ref(&long), Err(exceeded.clone()));
+	assert_eq!(mpt.delete_prefix(&long), Err(exceeded.clone()));
+	assert_eq!(mpt.prove(&long), Err(exceeded));
+	assert_eq!(mpt.hash(), EMPTY_TRIE_ROOT);
+
+	mpt.set_max_depth(8);
+	mpt.insert(vec![1, 2, 3, 4], b"value".to_vec()).unwrap();
+	assert!(matches!(
+		mpt.insert(vec![1, 2, 3, 4, 5], b"value".to_vec()),
+		Err(InsertError::Trie(TrieError::MaxDepthExceeded { .. }))
+	));
+	assert_eq!(mpt.get(vec![1, 2, 3, 4]), Ok(Some(b"value".to_vec())));
+
+	// Secure keys & account updates go through the same check.
+	mpt.set_max_depth(63);
+	assert!(matches!(
+		mpt.insert_secure(b"do", b"verb".to_vec()),
+		Err(InsertError::Trie(TrieError::MaxDepthExceeded { depth: 64, .. }))
+	));
+	let update = (
+		address_literal!("1111111111111111111111111111111111111111"),
+		Some(AccountState::default()),
+	);
+	assert!(matches!(
+		mpt.apply_updates(vec![update]),
+		Err(InsertError::Trie(TrieError::MaxDepthExceeded { depth: 64, .. }))
+	));
+}
@@ -0,0 +1,79 @@
+use crate::{Node, NodeDb, TrieError, MPT};
+
+/// WalkControl tells [MPT::walk] how to continue after visiting a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+	/// Visit the children of the node.
+	Continue,
+	/// Do not visit the children of the node, but continue with its siblings.
+	SkipSubtree,
+	/// End the walk.
+	Stop,
+}
+
+/// WalkNode is the view of a trie node that is passed to the visitor of [MPT::walk].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkNode<'a> {
+	/// A branch node & the value stored at its path, if any.
+	Branch { value: Option<&'a [u8]> },
+	/// An extension node & the nibbles that its child is placed under.
+	Extension { nibbles: &'a [u8] },
+	/// A value stored at the path.
+	Value(&'a [u8]),
+}
+
+impl<H, D: NodeDb> MPT<H, D> {
+	/// walk visits the nodes of the trie depth first in lexicographic order. The visitor receives the
+	/// nibble path of each node & controls if the walk descends into it. Hash nodes are decoded
+	/// from the db, a node that is missing from it ends the walk with an error.
+	pub fn walk(&self, visitor: &mut dyn FnMut(&[u8], &WalkNode) -> WalkControl) -> Result<(), TrieError> {
+		walk_node(&self.root, &mut Vec::new(), &self.db, visitor)?;
+		Ok(())
+	}
+}
+
+// walk_node visits `node` & its children. It returns false once the visitor stopped the walk.
+fn walk_node(
+	node: &Node,
+	path: &mut Vec<u8>,
+	db: &dyn NodeDb,
+	visitor: &mut dyn FnMut(&[u8], &WalkNode) -> WalkControl,
+) -> Result<bool, TrieError> {
+	let view = match node {
+		Node::Empty => return Ok(true),
+		Node::Branch(node) => WalkNode::Branch {
+			value: node.branch_value.as_ref().map(|v| &v.value[..]),
+		},
+		Node::Extension(node) => WalkNode::Extension { nibbles: &node.nibbles },
+		Node::Value(node) => WalkNode::Value(&node.value),
+		Node::Hash(hash) => {
+			let node = Node::resolve(*hash, db)?;
+			return walk_node(&node, path, db, visitor);
+		}
+	};
+	match visitor(path, &view) {
+		WalkControl::Stop => return Ok(false),
+		WalkControl::SkipSubtree => return Ok(true),
+		WalkControl::Continue => {}
+	}
+	match node {
+		Node::Branch(node) => {
+			for (i, child) in node.children.iter().enumerate() {
+				path.push(i as u8);
+				let more = walk_node(child, path, db, visitor);
+				path.pop();
+				if !more? {
+					return Ok(false);
+				}
+			}
+			Ok(true)
+		}
+		Node::Extension(node) => {
+			path.extend_from_slice(&node.nibbles);
+			let more = walk_node(&node.child, path, db, visitor);
+			path.truncate(path.len() - node.nibbles.len());
+			more
+		}
+		_ => Ok(true),
+	}
+}
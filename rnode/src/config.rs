@@ -0,0 +1,161 @@
+use client::Provider;
+use core::chain_config::RollupConfig;
+use core::types::Hash;
+use eyre::{eyre, Result};
+use std::path::PathBuf;
+
+/// The number of blocks that an L1 block must be behind the head before it is derived from.
+pub const DEFAULT_CONFIRMATION_DEPTH: u64 = 4;
+
+/// The file that the derivation snapshot is flushed to on shutdown.
+pub const DEFAULT_SNAPSHOT_PATH: &str = "derivation_snapshot.json";
+
+/// StartL1 is the L1 block where derivation begins, given by hash or by number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartL1 {
+	Hash(Hash),
+	Number(u64),
+}
+
+impl std::str::FromStr for StartL1 {
+	type Err = eyre::Report;
+
+	/// Parses a 0x prefixed 32 byte hex string as a block hash and anything else as a block number.
+	fn from_str(s: &str) -> Result<Self> {
+		match s.strip_prefix("0x") {
+			Some(hex) if hex.len() == 64 => {
+				let bytes = ethers_core::utils::hex::decode(hex)?;
+				Ok(StartL1::Hash(Hash::new(bytes.try_into().expect("Should have decoded 32 bytes"))))
+			}
+			_ => Ok(StartL1::Number(s.parse().map_err(|_| eyre!("invalid start L1 block: {s}"))?)),
+		}
+	}
+}
+
+/// Config is the configuration of the node.
+#[derive(Debug, Clone)]
+pub struct Config {
+	/// The L1 RPC url
+	pub rpc: String,
+	/// The rollup being derived
+	pub rollup: RollupConfig,
+	/// The L1 block where derivation begins. Defaults to the L1 genesis of the rollup.
+	pub start_l1: Option<StartL1>,
+	/// The number of blocks behind the L1 head that derivation stays to avoid reorged blocks
+	pub confirmation_depth: u64,
+	/// The file that the derivation snapshot is flushed to on shutdown
+	pub snapshot_path: PathBuf,
+	/// The address that the debug server listens on. It is not started without an address.
+	#[cfg(feature = "debug-server")]
+	pub debug_addr: Option<String>,
+}
+
+impl Config {
+	/// Constructs the config from the `RPC` and optional `START_L1`, `CONFIRMATION_DEPTH`, `SNAPSHOT_PATH`
+	/// & `DEBUG_ADDR` environment variables
+	pub fn from_env(rollup: RollupConfig) -> Result<Self> {
+		let rpc = std::env::var("RPC")?;
+		let start_l1 = std::env::var("START_L1").ok().map(|s| s.parse()).transpose()?;
+		let confirmation_depth = match std::env::var("CONFIRMATION_DEPTH") {
+			Ok(depth) => depth.parse().map_err(|_| eyre!("invalid confirmation depth: {depth}"))?,
+			Err(_) => DEFAULT_CONFIRMATION_DEPTH,
+		};
+		let snapshot_path = std::env::var("SNAPSHOT_PATH")
+			.unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.to_string())
+			.into();
+		Ok(Config {
+			rpc,
+			rollup,
+			start_l1,
+			confirmation_depth,
+			snapshot_path,
+			#[cfg(feature = "debug-server")]
+			debug_addr: std::env::var("DEBUG_ADDR").ok(),
+		})
+	}
+
+	/// start_l1_number resolves the number of the L1 block where derivation begins.
+	/// A block hash is looked up with the provider.
+	pub fn start_l1_number(&self, provider: &mut impl Provider) -> Result<u64> {
+		match self.start_l1 {
+			Some(StartL1::Hash(hash)) => Ok(provider.get_header(hash)?.number),
+			Some(StartL1::Number(number)) => Ok(number),
+			None => Ok(self.rollup.l1_genesis.number),
+		}
+	}
+
+	/// safe_l1_number returns the number of the latest L1 block that is at least `confirmation_depth`
+	/// blocks behind the L1 head. It is `None` while the chain is shorter than the depth.
+	pub fn safe_l1_number(&self, head: u64) -> Option<u64> {
+		head.checked_sub(self.confirmation_depth)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use client::prelude::*;
+	use core::chain_config::GOERLI_CONFIG;
+	use ethers_core::types::{Block, Bloom, Transaction, H256, H64, U64};
+	use ethers_providers::Provider as RPCProvider;
+	use mpt::EMPTY_TRIE_ROOT;
+
+	fn config(start_l1: Option<StartL1>) -> Config {
+		Config {
+			rpc: String::new(),
+			rollup: GOERLI_CONFIG,
+			start_l1,
+			confirmation_depth: DEFAULT_CONFIRMATION_DEPTH,
+			snapshot_path: DEFAULT_SNAPSHOT_PATH.into(),
+			#[cfg(feature = "debug-server")]
+			debug_addr: None,
+		}
+	}
+
+	#[test]
+	fn test_parse_start_l1() {
+		assert_eq!("8300532".parse::<StartL1>().unwrap(), StartL1::Number(8300532));
+		let hash = format!("0x{}", "ab".repeat(32));
+		assert_eq!(hash.parse::<StartL1>().unwrap(), StartL1::Hash(Hash::new([0xab; 32])));
+		assert!("0xabcd".parse::<StartL1>().is_err());
+		assert!(format!("0x{}", "zz".repeat(32)).parse::<StartL1>().is_err());
+	}
+
+	#[test]
+	fn test_safe_l1_number() {
+		let mut config = config(None);
+		config.confirmation_depth = 10;
+		assert_eq!(config.safe_l1_number(8300600), Some(8300590));
+		assert_eq!(config.safe_l1_number(10), Some(0));
+		assert_eq!(config.safe_l1_number(9), None);
+		config.confirmation_depth = 0;
+		assert_eq!(config.safe_l1_number(8300600), Some(8300600));
+	}
+
+	#[test]
+	fn test_start_l1_number() {
+		let (provider, mock) = RPCProvider::mocked();
+		let mut client = Client::with_provider(provider).unwrap();
+
+		assert_eq!(config(None).start_l1_number(&mut client).unwrap(), GOERLI_CONFIG.l1_genesis.number);
+		assert_eq!(
+			config(Some(StartL1::Number(8300600))).start_l1_number(&mut client).unwrap(),
+			8300600
+		);
+
+		let block: Block<Transaction> = Block {
+			number: Some(U64::from(8300532)),
+			author: Some(Default::default()),
+			logs_bloom: Some(Bloom::default()),
+			mix_hash: Some(H256::zero()),
+			nonce: Some(H64::zero()),
+			transactions_root: EMPTY_TRIE_ROOT.into(),
+			receipts_root: EMPTY_TRIE_ROOT.into(),
+			..Default::default()
+		};
+		mock.push::<Block<Transaction>, _>(block).unwrap();
+		let hash = Hash::new([0xab; 32]);
+		assert_eq!(config(Some(StartL1::Hash(hash))).start_l1_number(&mut client).unwrap(), 8300532);
+		mock.assert_request("eth_getBlockByHash", (H256::from(hash), true)).unwrap();
+	}
+}
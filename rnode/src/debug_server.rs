@@ -0,0 +1,99 @@
+use derivation::derivation::{Derivation, DerivationSnapshot};
+use eyre::{eyre, Result};
+use std::{
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+};
+use tiny_http::{Header, Method, Response, Server};
+use tracing::info;
+
+/// DebugState holds the last published snapshot of the pipeline that the debug server responds with.
+#[derive(Debug, Clone, Default)]
+pub struct DebugState(Arc<Mutex<Option<DerivationSnapshot>>>);
+
+impl DebugState {
+	/// publish replaces the served snapshot with the current state of the pipeline.
+	pub fn publish(&self, derivation: &Derivation) {
+		*self.0.lock().expect("Cannot publish to a poisoned debug state") = Some(derivation.snapshot());
+	}
+
+	// to_json encodes the last published snapshot. It is None until the first L1 block was derived.
+	fn to_json(&self) -> Option<String> {
+		let snapshot = self.0.lock().expect("Cannot read a poisoned debug state");
+		snapshot.as_ref()
+			.map(|s| serde_json::to_string(s).expect("Should have serialized the snapshot"))
+	}
+}
+
+/// start serves the debug state on `addr` if it is set.
+pub fn start(addr: Option<&str>) -> Result<Option<DebugState>> {
+	let Some(addr) = addr else {
+		return Ok(None);
+	};
+	let state = DebugState::default();
+	let addr = serve(addr, state.clone())?;
+	info!("serving the derivation state on http://{addr}/state");
+	Ok(Some(state))
+}
+
+/// serve answers `GET /state` with the last published snapshot as JSON from a background thread
+/// & returns the address that the server listens on.
+pub fn serve(addr: &str, state: DebugState) -> Result<SocketAddr> {
+	let server = Server::http(addr).map_err(|e| eyre!("cannot start the debug server on {addr}: {e}"))?;
+	let addr = server
+		.server_addr()
+		.to_ip()
+		.ok_or_else(|| eyre!("debug server is not listening on an ip address"))?;
+	std::thread::spawn(move || {
+		for request in server.incoming_requests() {
+			let response = match (request.method(), request.url(), state.to_json()) {
+				(Method::Get, "/state", Some(json)) => Response::from_string(json).with_header(json_header()),
+				(Method::Get, "/state", None) => Response::from_string("no state yet").with_status_code(503),
+				_ => Response::from_string("not found").with_status_code(404),
+			};
+			// The client may have gone away, which does not concern the node.
+			let _ = request.respond(response);
+		}
+	});
+	Ok(addr)
+}
+
+fn json_header() -> Header {
+	Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("Should be a valid header")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use core::chain_config::GOERLI_CONFIG;
+	use std::io::{Read, Write};
+	use std::net::TcpStream;
+
+	// get sends a GET request & returns the status line & the body of the response.
+	fn get(addr: SocketAddr, path: &str) -> (String, String) {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).unwrap();
+		let (head, body) = response.split_once("\r\n\r\n").unwrap();
+		(head.lines().next().unwrap().to_string(), body.to_string())
+	}
+
+	#[test]
+	fn test_state_endpoint() {
+		let state = DebugState::default();
+		let addr = serve("127.0.0.1:0", state.clone()).unwrap();
+		assert!(get(addr, "/state").0.contains("503"));
+
+		state.publish(&Derivation::new(GOERLI_CONFIG));
+		let (status, body) = get(addr, "/state");
+		assert!(status.contains("200"));
+		let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+		assert_eq!(json["l2_safe_head"], GOERLI_CONFIG.l2_genesis.number);
+		assert_eq!(json["l2_safe_head_time"], GOERLI_CONFIG.l2_genesis_time);
+		assert_eq!(json["channel_bank"]["channel_timeout"], GOERLI_CONFIG.channel_timeout);
+		assert_eq!(json["batch_queue"]["batches"], 0);
+
+		assert!(get(addr, "/other").0.contains("404"));
+	}
+}
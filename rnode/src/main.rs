@@ -1,18 +1,52 @@
 use dotenv::dotenv;
 use eyre::Result;
+use std::sync::{
+	atomic::{AtomicBool, Ordering},
+	Arc,
+};
 
 use client::prelude::*;
 use derivation::derivation::Derivation;
 
+mod config;
+#[cfg(feature = "debug-server")]
+mod debug_server;
+
+use config::Config;
+
 fn main() -> Result<()> {
 	// Load environment variables from local ".env" file
 	dotenv().ok();
+	// Log the events of the node & of the derivation pipeline to stdout
+	tracing_subscriber::fmt::init();
+
+	let config = Config::from_env(core::chain_config::GOERLI_CONFIG)?;
+	let mut provider = Client::new(&config.rpc)?;
+
+	// Walk forward from the starting block up to the L1 block that is confirmed deep enough
+	let start = config.start_l1_number(&mut provider)?;
+	let head = provider.health_check()?;
+	let Some(safe) = config.safe_l1_number(head) else {
+		eyre::bail!("L1 head {head} is less than {} blocks deep", config.confirmation_depth);
+	};
+
+	// On a shutdown signal the in-flight L1 block is finished & the state is flushed before exiting
+	let shutdown = Arc::new(AtomicBool::new(false));
+	let handler = shutdown.clone();
+	ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst))?;
 
-	let provider = std::env::var("RPC")?;
-	let mut provider = Client::new(&provider)?;
+	// The debug server responds with the state that is published after every L1 block
+	#[cfg(feature = "debug-server")]
+	let debug_state = debug_server::start(config.debug_addr.as_deref())?;
 
-	let mut derivation = Derivation::new(core::chain_config::GOERLI_CONFIG);
-	derivation.run(8300532, 8300533, &mut provider);
+	let mut derivation = Derivation::new(config.rollup);
+	derivation.run_with_observer(start, safe + 1, &mut provider, &shutdown, |_derivation| {
+		#[cfg(feature = "debug-server")]
+		if let Some(state) = &debug_state {
+			state.publish(_derivation);
+		}
+	});
+	derivation.flush(&config.snapshot_path)?;
 
 	Ok(())
 }
@@ -1,5 +1,6 @@
 use std::str::FromStr;
 
+use core::types::{ethers_h256, reth_h256};
 use ethers_core::abi::AbiDecode;
 use ethers_core::types::H256;
 use rs_node::client::*;
@@ -10,7 +11,7 @@ pub fn test_get_header() {
 	let mut client = Client::new(rpc_url).unwrap();
 	let hash = H256::decode_hex("0xee9dd94ebc06b50d5d5c0f72299a3cc56737e459ce41ddb44f0411870f86b1a3").unwrap();
 	let header = client.get_header(hash).unwrap();
-	let expected_hash = reth_primitives::H256::from(hash.as_fixed_bytes());
+	let expected_hash = reth_h256(hash);
 	assert_eq!(header.hash_slow(), expected_hash);
 }
 
@@ -27,7 +28,7 @@ pub fn test_get_transactions_by_root() {
 	// Now let's load the header, then fetch the transactions by the transactions root hash
 	let hash = H256::decode_hex("0xee9dd94ebc06b50d5d5c0f72299a3cc56737e459ce41ddb44f0411870f86b1a3").unwrap();
 	let header = client.get_header(hash).unwrap();
-	let tx_root_hash = ethers_core::types::H256::from(header.transactions_root.as_fixed_bytes());
+	let tx_root_hash = ethers_h256(header.transactions_root);
 	let transactions = client.get_transactions_by_root(tx_root_hash).unwrap();
 	assert_eq!(transactions.len(), 8);
 }
@@ -42,10 +43,11 @@ pub fn test_get_receipts_by_root() {
 	let missing_receipts = client.get_receipts_by_root(expected_receipts_root_hash);
 	assert!(missing_receipts.is_err());
 
-	// Now let's load the header, then fetch the receipts by the receipts root hash
+	// Now let's load the header & its receipts, then fetch the receipts by the receipts root hash
 	let hash = H256::decode_hex("0xee9dd94ebc06b50d5d5c0f72299a3cc56737e459ce41ddb44f0411870f86b1a3").unwrap();
 	let header = client.get_header(hash).unwrap();
-	let receipt_root_hash = ethers_core::types::H256::from(header.receipts_root.as_fixed_bytes());
+	client.get_receipts(&header).unwrap();
+	let receipt_root_hash = ethers_h256(header.receipts_root);
 	assert_eq!(receipt_root_hash, expected_receipts_root_hash);
 	let receipts = client.get_receipts_by_root(receipt_root_hash).unwrap();
 	assert_eq!(receipts.len(), 8);